@@ -0,0 +1,112 @@
+#!/bin/bash
+#      ^^^^ - NOT /bin/sh, as this code uses arrays
+#
+# Starts a ring of real node processes on ephemeral localhost ports, stores and finds keys
+# through the client subcommand, lets one node leave and checks that its keys survive.
+# Exits with 0 if all checks passed.
+#
+# How to run this script:
+#
+# bash ring_test.sh [NUMBER_OF_NODES] [NUMBER_OF_KEYS]
+# (e.g.) bash ring_test.sh 4 20
+
+PIDS=()
+ADDRS=()
+
+NUMBER_OF_NODES=${1:-4}
+NUMBER_OF_KEYS=${2:-20}
+TEST_IP=127.0.0.1
+PROJECT_BUILD="./target/debug/hll-rust"
+# Seconds to wait for a node to listen and for the ring to agree on the owners of the keys
+TIMEOUT=60
+LOG_DIR=$(mktemp -d)
+
+cleanup() {
+  for pid in "${PIDS[@]}"; do
+    kill -0 "$pid" 2> /dev/null && kill "$pid" # kill process only if it's still running
+  done
+}
+
+trap cleanup EXIT SIGTERM
+
+fail() {
+  echo "FAILED: $1 (node logs in $LOG_DIR)"
+  exit 1
+}
+
+client() {
+  ${PROJECT_BUILD} client "$@" 2> /dev/null
+}
+
+# Starts a node listening on an ephemeral port and waits until it logged the port
+start_node() {
+  local log="$LOG_DIR/node$1.log"
+  ${PROJECT_BUILD} ${TEST_IP} 0 $2 --headless > "$log" 2>&1 & PIDS+=( "$!" )
+  for _ in $(seq 1 $((TIMEOUT * 10))); do
+    local port=$(sed -n 's/.*Listening on ephemeral port \([0-9]*\).*/\1/p' "$log" | head -n 1)
+    if [[ -n "$port" ]]; then
+      ADDRS+=( "${TEST_IP}:${port}" )
+      return
+    fi
+    sleep .1
+  done
+  fail "node $1 did not start listening"
+}
+
+# Waits until every node in ADDRS names the same owner for every key, i.e. the ring converged
+wait_for_convergence() {
+  for _ in $(seq 1 $TIMEOUT); do
+    local converged=1
+    for key in $(seq 1 $NUMBER_OF_KEYS); do
+      local owners=$(for addr in "${ADDRS[@]}"; do client "$addr" owner "key$key"; done | sort -u)
+      if [[ $(echo "$owners" | wc -l) -ne 1 || "$owners" != *"owned by"* ]]; then
+        converged=0
+        break
+      fi
+    done
+    if ((converged)); then
+      return
+    fi
+    sleep 1
+  done
+  fail "the ring did not agree on the owners of the keys within $TIMEOUT seconds"
+}
+
+# Finds every key through every node in ADDRS
+check_keys() {
+  for addr in "${ADDRS[@]}"; do
+    for key in $(seq 1 $NUMBER_OF_KEYS); do
+      local value=$(client "$addr" get "key$key")
+      [[ "$value" == "value$key" ]] || fail "$1: key$key found through $addr is '$value' instead of 'value$key'"
+    done
+  done
+}
+
+cargo build || exit 1
+
+echo "Starting a chord ring of $NUMBER_OF_NODES nodes..."
+start_node 0
+for i in $(seq 1 $((NUMBER_OF_NODES - 1))); do
+  start_node $i "${ADDRS[0]}"
+done
+wait_for_convergence
+echo "Join: passed"
+
+for key in $(seq 1 $NUMBER_OF_KEYS); do
+  client "${ADDRS[$((key % NUMBER_OF_NODES))]}" store "key$key" "value$key" > /dev/null || fail "storing key$key"
+done
+echo "Store: passed"
+
+check_keys "Find"
+echo "Find: passed"
+
+# SIGINT lets the node hand its keys off and leave the ring
+LEAVING=$((NUMBER_OF_NODES - 1))
+kill -INT "${PIDS[$LEAVING]}"
+wait "${PIDS[$LEAVING]}" || fail "the leaving node exited with an error"
+unset "ADDRS[$LEAVING]"
+wait_for_convergence
+check_keys "Leave"
+echo "Graceful leave: passed"
+
+echo "All checks passed"
@@ -1,22 +1,35 @@
-use std::io::stdin;
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, stdin, BufReader, BufWriter};
 use std::net::SocketAddr;
+use std::path::Path;
 use std::{error::Error};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::process;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time;
+use std::time::{self, Instant};
 
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
+use futures::sync::oneshot;
 use num::bigint::{BigInt, Sign, ToBigInt};
+use num::Integer;
 use signal_hook::{iterator::Signals, SIGINT};
 
 use super::chord;
+use super::config::{ChordConfig, SuccessorSelection};
+#[cfg(feature = "discovery")]
+use super::discovery;
 use super::fingertable::*;
 use super::input::*;
+use super::logging;
 use super::network;
+use super::network::Network;
 use super::node::*;
 use super::print;
 use super::protocols::*;
@@ -30,111 +43,357 @@ pub const SUCCESSORLIST_SIZE: usize = CHORD_CIRCLE_BITS;
 /// At most a number of `2^m` nodes are allowed in the Chord Circle (Bit Shift left)
 pub const CHORD_RING_SIZE: usize = 1 << CHORD_CIRCLE_BITS;
 
+/// First stabilize interval, adapted between `stabilize_min_interval` and `stabilize_max_interval` afterwards
 pub const NODE_STABILIZE_INTERVAL: time::Duration = time::Duration::from_millis(2000);
 
 pub const NODE_FIX_FINGERS_INTERVAL: time::Duration = time::Duration::from_millis(500);
 
 pub const NODE_CHECK_PREDECESSOR_INTERVAL: time::Duration = time::Duration::from_millis(1000);
+/// While the predecessor fails to answer, the check interval doubles up to this many times
+pub const PREDECESSOR_CHECK_MAX_BACKOFF: u32 = 3;
 
 pub const NODE_INIT_SLEEP_INTERVAL: time::Duration = time::Duration::from_millis(2000);
 
 pub const NODE_PRINT_INTERVAL: time::Duration = time::Duration::from_millis(2000);
 
+/// Time the watchdog waits before restarting a background thread that panicked
+pub const NODE_RESTART_INTERVAL: time::Duration = time::Duration::from_millis(1000);
+
+/// Time between two probes for other rings
+pub const NODE_PROBE_RINGS_INTERVAL: time::Duration = time::Duration::from_millis(30000);
+
+pub const NODE_CHECK_LOAD_INTERVAL: time::Duration = time::Duration::from_millis(10000);
+
+/// A node owning more than this factor times the fair share of keys is imbalanced
+pub const LOAD_IMBALANCE_FACTOR: f64 = 2.0;
+
+/// Imbalance is not reported for nodes owning fewer keys
+pub const LOAD_IMBALANCE_MIN_KEYS: usize = 10;
+
+/// Most keys a node with `MigrationStrategy::Lazy` hands over per `check_load` cycle
+pub const LAZY_MIGRATION_BATCH_SIZE: usize = 64;
+
+/// Most node deaths a node gossips to its predecessor with each stabilize response
+pub const DEAD_GOSSIP_SIZE: usize = 8;
+
+/// Time a node I found dead stays in my gossip
+pub const DEAD_GOSSIP_TTL: time::Duration = time::Duration::from_millis(10000);
+
+/// Time until a lookup answered with `Response::LookupBusy` is sent again
+pub const LOOKUP_BUSY_RETRY_INTERVAL: time::Duration = time::Duration::from_millis(200);
+
+/// Most nodes a crawl of the ring visits, see `crawl_ring`
+pub const CRAWL_MAX_NODES: usize = 4096;
+
+/// Overall time a lookup may take across all of its hops
+pub const LOOKUP_TIMEOUT: time::Duration = time::Duration::from_millis(10000);
+
 pub const PING_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(5000);
 
+/// Entries dated further into the future than this are logged as written by a skewed clock
+pub const MAX_CLOCK_SKEW_MILLIS: u64 = 60 * 1000;
+
 pub const LISTENING_ADDRESS: &str = "0.0.0.0";
 
-pub fn join(id: BigInt, sender: OtherNode, join_ip: SocketAddr) {
-    info!("Trying to join...");
-    let req = Request::FindSuccessor { id };
-    network::send_request(sender, join_ip, req);
+/// Log target of stabilization, predecessor checks and joins (filterable in config/log4rs.yaml)
+pub const LOG_STABILIZE: &str = "chord::stabilize";
+/// Log target of finger table maintenance
+pub const LOG_FIX_FINGERS: &str = "chord::fix_fingers";
+/// Log target of sending, receiving and pinging
+pub const LOG_NETWORK: &str = "chord::network";
+/// Log target of storing, finding and moving keys
+pub const LOG_DHT: &str = "chord::dht";
+
+/// Incomplete chunked uploads are rejected after this time
+pub const DHT_CHUNK_UPLOAD_TIMEOUT: time::Duration = time::Duration::from_millis(30000);
+
+pub fn join(network: &Network, id: BigInt, sender: OtherNode, join_ip: SocketAddr) {
+    info!(target: LOG_STABILIZE, "Trying to join...");
+    let req = Request::FindSuccessor { id, lookup: LookupContext::new() };
+    network.send_join_request(sender, join_ip, req);
 }
 
-pub fn stabilize(arc: Arc<Mutex<Node>>) {
-    info!("Starting stabilisation...");
+/// Pings the successor found while joining, see `Node::take_join_candidate`, without holding
+/// the node lock and joins it if it answers. Returns whether there was one.
+pub fn confirm_join_candidate(arc: &Arc<Mutex<Node>>) -> bool {
+    let mut node = arc.lock().unwrap();
+    let candidate = node.take_join_candidate();
+    let me = node.to_other_node();
+    let join_pings = node.get_config().join_pings;
+    let network = node.get_network().clone();
+    drop(node);
+    match candidate {
+        Some(candidate) => {
+            let alive = (0..join_pings).all(|_| network.check_alive(*candidate.get_ip_addr(), me.clone()));
+            arc.lock().unwrap().confirm_join_candidate(candidate, alive);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Waits up to `timeout` for the join to complete, confirming the successors found meanwhile
+fn wait_for_join(arc: &Arc<Mutex<Node>>, trigger: &StabilizeTrigger, timeout: time::Duration) {
+    let deadline = Instant::now() + timeout;
     loop {
-        debug!("Stabilize.............");
-        // make a copy of node and instantly drop it
-        let node = arc.lock().unwrap();
-        let node_clone = node.clone();
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        trigger.wait(deadline - now);
+        confirm_join_candidate(arc);
+        if arc.lock().unwrap().is_joined() {
+            return;
+        }
+    }
+}
+
+pub fn stabilize(arc: Arc<Mutex<Node>>, entry_node_addrs: Vec<SocketAddr>) {
+    info!(target: LOG_STABILIZE, "Starting stabilisation...");
+    let mut round: usize = 0;
+    let mut interval = chord::NODE_STABILIZE_INTERVAL;
+    // Successor and predecessor ids seen by the last cycle
+    let mut last_neighbours: Option<(Vec<BigInt>, Option<BigInt>)> = None;
+    let trigger = arc.lock().unwrap().get_stabilize_trigger().clone();
+    let shutdown = arc.lock().unwrap().get_shutdown_signal().clone();
+    loop {
+        debug!(target: LOG_STABILIZE, "Stabilize.............");
+        // copy only what is needed and instantly drop the lock
+        let mut node = arc.lock().unwrap();
+        node.expire_outstanding_requests();
+        let paused = node.is_maintenance_paused();
+        let joined = node.is_joined();
+        let me = node.to_other_node();
+        let successor_list = node.get_successor_list().clone();
+        let config = node.get_config().clone();
+        let network = node.get_network().clone();
+        let neighbours = (successor_list.iter().map(|succ| succ.get_id().clone()).collect(),
+                          node.get_predecessor().clone().map(|pre| pre.get_id().clone()));
         drop(node);
+        let changed = last_neighbours.as_ref() != Some(&neighbours);
+        last_neighbours = Some(neighbours);
 
-        if node_clone.is_joined() {
+        if paused {
+            debug!(target: LOG_STABILIZE, "Maintenance paused, skipping");
+        } else if joined {
             let mut ring_is_alive = false;
-            for succ in node_clone.get_successor_list().clone() {
-                if network::check_alive(*succ.get_ip_addr(), node_clone.to_other_node().clone()) {
-                    let req = Request::GetPredecessor;
-                    network::send_request(node_clone.to_other_node(), *succ.get_ip_addr(), req);
-                    // after async operation check_alive() lock again.
-                    arc.lock().unwrap().update_successor_and_successor_list(succ);
+            for (i, succ) in successor_list.iter().cloned().enumerate() {
+                if let Some(rtt) = network.ping_rtt(*succ.get_ip_addr(), me.clone()) {
+                    // The first live successor stays my successor, the strategy only decides who is asked
+                    let target = match config.successor_selection {
+                        SuccessorSelection::FirstLive => succ.clone(),
+                        SuccessorSelection::RoundRobin => select_round_robin(&network, &me, &successor_list[i..], round),
+                    };
+                    // after async operation ping_rtt() lock again.
+                    // The live successor has to be set before GetPredecessor is sent, otherwise
+                    // the response handler could still compare with and notify a dead successor.
+                    let mut node = arc.lock().unwrap();
+                    node.record_rtt(succ.get_id().clone(), rtt);
+                    if succ == me {
+                        node.become_single_node_ring(&successor_list[..i]);
+                    }
+                    node.update_successor_and_successor_list(succ);
+                    drop(node);
+                    // An observer must not notify, so it always uses the two message exchange.
+                    // Older peers do not know the Stabilize request.
+                    let req = if config.batch_stabilize && !config.observer &&
+                        negotiate_version(target.get_protocol_version()) >= STABILIZE_MIN_VERSION {
+                        Request::Stabilize { from: me.clone() }
+                    } else {
+                        Request::GetPredecessor
+                    };
+                    network.send_request(me.clone(), *target.get_ip_addr(), req);
 
                     ring_is_alive = true;
                     break;
                 } else {
-                    debug!("Node is dead: {:?}", succ);
+                    debug!(target: LOG_STABILIZE, "Node is dead: {:?}", succ);
+                    arc.lock().unwrap().record_dead(succ.get_id().clone());
                 }
             }
             if !ring_is_alive {
-                error!("No functional successor found in successor list. RING IS DEAD. Initializing shutdown...");
-                process::exit(1);
+                error!(target: LOG_STABILIZE, "No functional successor found in successor list. RING IS DEAD. Trying to rejoin...");
+                // Former successors may become reachable again after a network blip
+                let mut bootstrap: Vec<SocketAddr> = successor_list.iter()
+                    .filter(|succ| **succ != me)
+                    .map(|succ| *succ.get_ip_addr())
+                    .collect();
+                bootstrap.extend(entry_node_addrs.iter().cloned());
+                arc.lock().unwrap().rejoin(&bootstrap);
             }
+            round = round.wrapping_add(1);
         } else {
-            info!("Not joined jet going to sleep again")
+            info!(target: LOG_STABILIZE, "Not joined jet going to sleep again");
+            // A successor found by the last join requests is pinged before asking again
+            if !confirm_join_candidate(&arc) {
+                arc.lock().unwrap().send_join_requests();
+            }
+        }
+        if !paused {
+            interval = adapt_stabilize_interval(interval, changed || !joined, &config);
+            debug!(target: LOG_STABILIZE, "Next stabilize in {:?}", interval);
         }
         //this is super important, because otherwise the lock would persist endlessly due to the loop
-        //node_clone.send_message_to_socket(node_clone.successor.ip_addr, req);
-        thread::sleep(chord::NODE_STABILIZE_INTERVAL);
+        if trigger.wait(interval) {
+            debug!(target: LOG_STABILIZE, "Stabilize triggered before the interval was over");
+        }
+        // The shutdown fires the trigger as well
+        if shutdown.is_requested() {
+            break;
+        }
+    }
+}
+
+/// Falls back to the minimum interval after successors or predecessor changed, and doubles
+/// the interval up to the maximum while the ring is quiet
+fn adapt_stabilize_interval(interval: time::Duration, changed: bool, config: &ChordConfig) -> time::Duration {
+    let max_interval = cmp::max(config.stabilize_min_interval, config.stabilize_max_interval);
+    if changed {
+        config.stabilize_min_interval
+    } else {
+        cmp::max(config.stabilize_min_interval, cmp::min(interval * 2, max_interval))
     }
 }
 
+/// Picks the `round`-th live node of `live_successors`, whose first entry is known to be alive
+fn select_round_robin(network: &Network, me: &OtherNode, live_successors: &[OtherNode], round: usize) -> OtherNode {
+    let start = round % live_successors.len();
+    for offset in 0..live_successors.len() {
+        let index = (start + offset) % live_successors.len();
+        let candidate = &live_successors[index];
+        if index == 0 || network.check_alive(*candidate.get_ip_addr(), me.clone()) {
+            return candidate.clone();
+        }
+    }
+    live_successors[0].clone()
+}
+
 pub fn fix_fingers(arc: Arc<Mutex<Node>>) {
-    debug!("Starting fix_fingers...");
+    debug!(target: LOG_FIX_FINGERS, "Starting fix_fingers...");
+    let shutdown = arc.lock().unwrap().get_shutdown_signal().clone();
     let mut next = 1;
     loop {
-        // make a copy of node and instantly drop it
+        // copy only what is needed and instantly drop the lock
         let node = arc.lock().unwrap();
-        let node_clone = node.clone();
+        let paused = node.is_maintenance_paused();
+        let joined = node.is_joined();
+        let me = node.to_other_node();
+        let successor = node.get_successor();
+        let network = node.get_network().clone();
         drop(node);
 
-        if node_clone.is_joined() {
-            let finger_id = get_finger_id(node_clone.get_id(), next);
+        if paused {
+            debug!(target: LOG_FIX_FINGERS, "Maintenance paused, skipping");
+        } else if joined {
+            // Index `next` holds finger `next + 1`
+            let finger_id = finger_start(me.get_id(), next + 1);
 
             let req = Request::FindSuccessorFinger { index: next, finger_id };
-            network::send_request(node_clone.to_other_node(), *node_clone.get_successor().get_ip_addr(), req);
+            network.send_request(me, *successor.get_ip_addr(), req);
 
             next = if next < chord::FINGERTABLE_SIZE - 1 {
                 next + 1
             } else {
                 1
             };
-        } else { info!("Not joined yet going to sleep again") }
+        } else { info!(target: LOG_FIX_FINGERS, "Not joined yet going to sleep again") }
 
-        thread::sleep(chord::NODE_FIX_FINGERS_INTERVAL);
+        if shutdown.wait(chord::NODE_FIX_FINGERS_INTERVAL) {
+            break;
+        }
     }
 }
 
 pub fn check_predecessor(arc: Arc<Mutex<Node>>) {
-    debug!("Starting check_predecessor...");
+    debug!(target: LOG_STABILIZE, "Starting check_predecessor...");
+    let shutdown = arc.lock().unwrap().get_shutdown_signal().clone();
+    // Consecutive failed checks of the predecessor with the given id
+    let mut failures: u32 = 0;
+    let mut failing_id: Option<BigInt> = None;
     loop {
-        // make a copy of node and instantly drop it
+        // copy only what is needed and instantly drop the lock
         let node = arc.lock().unwrap();
-        let node_clone = node.clone();
+        let paused = node.is_maintenance_paused();
+        let joined = node.is_joined();
+        let me = node.to_other_node();
+        let predecessor = node.get_predecessor().clone();
+        let threshold = node.get_config().predecessor_failure_threshold.max(1);
+        let network = node.get_network().clone();
         drop(node);
 
-        if node_clone.is_joined() {
-            if let Some(predecessor) = node_clone.get_predecessor().clone() {
-                if !network::check_alive(*predecessor.get_ip_addr(), node_clone.to_other_node().clone()) {
-                    debug!("Predecessor Node #{} is dead", predecessor.get_id());
+        if paused {
+            debug!(target: LOG_STABILIZE, "Maintenance paused, skipping");
+        } else if joined {
+            if let Some(predecessor) = predecessor {
+                if failing_id.as_ref() != Some(predecessor.get_id()) {
+                    failures = 0;
+                }
+                let alive = network.check_alive(*predecessor.get_ip_addr(), me);
+                // after async operation check_alive() lock again.
+                if arc.lock().unwrap().resolve_predecessor_claim(&predecessor, alive) {
+                    failures = 0;
+                } else if !alive {
+                    failures += 1;
+                    failing_id = Some(predecessor.get_id().clone());
+                    if failures as usize >= threshold {
+                        debug!(target: LOG_STABILIZE, "Predecessor Node #{} is dead", chord::short_id(predecessor.get_id()));
+                        failures = 0;
 
-                    // after async operation check_alive() lock again.
-                    arc.lock().unwrap().set_predecessor(None);
+                        let mut node = arc.lock().unwrap();
+                        node.set_predecessor(None);
+                        node.record_dead(predecessor.get_id().clone());
+                    } else {
+                        debug!(target: LOG_STABILIZE, "Predecessor Node #{} did not answer ({}/{}), checking again later",
+                               chord::short_id(predecessor.get_id()), failures, threshold);
+                    }
                 } else {
-                    debug!("Predecessor Node #{} is alive", predecessor.get_id());
+                    failures = 0;
+                    debug!(target: LOG_STABILIZE, "Predecessor Node #{} is alive", chord::short_id(predecessor.get_id()));
                 }
             }
-        } else { info!("Not joined yet going to sleep again") }
+        } else { info!(target: LOG_STABILIZE, "Not joined yet going to sleep again") }
 
-        thread::sleep(chord::NODE_CHECK_PREDECESSOR_INTERVAL);
+        if shutdown.wait(chord::NODE_CHECK_PREDECESSOR_INTERVAL * 2u32.pow(failures.min(PREDECESSOR_CHECK_MAX_BACKOFF))) {
+            break;
+        }
+    }
+}
+
+/// Compares the own number of keys with the successors' and reports a `LoadImbalance`
+pub fn check_load(arc: Arc<Mutex<Node>>) {
+    debug!(target: LOG_DHT, "Starting check_load...");
+    let shutdown = arc.lock().unwrap().get_shutdown_signal().clone();
+    loop {
+        let mut node = arc.lock().unwrap();
+        if !node.is_maintenance_paused() && node.is_joined() {
+            node.check_load();
+            node.request_keys_owned();
+            node.expire_tombstones();
+            node.expire_chunked_uploads();
+            node.migrate_misplaced_keys();
+        }
+        drop(node);
+
+        if shutdown.wait(chord::NODE_CHECK_LOAD_INTERVAL) {
+            break;
+        }
+    }
+}
+
+/// Probes the configured nodes for rings split off from mine, see `Node::probe_rings`
+pub fn probe_rings(arc: Arc<Mutex<Node>>) {
+    debug!(target: LOG_STABILIZE, "Starting probe_rings...");
+    let shutdown = arc.lock().unwrap().get_shutdown_signal().clone();
+    loop {
+        let node = arc.lock().unwrap();
+        if !node.is_maintenance_paused() && node.is_joined() {
+            node.probe_rings();
+        }
+        drop(node);
+
+        if shutdown.wait(chord::NODE_PROBE_RINGS_INTERVAL) {
+            break;
+        }
     }
 }
 
@@ -144,15 +403,18 @@ pub fn print_and_interact(arc: Arc<Mutex<Node>>)  -> Result<(), Box<Error>> {
 
     let node = arc.lock().unwrap();
     let other_node = node.to_other_node().clone();
+    let config = node.get_config().clone();
+    let shutdown = node.get_shutdown_signal().clone();
     drop(node);
 
-    let _handle = thread::Builder::new().name("Interaction".to_string()).spawn(move || {
+    let arc_clone = arc.clone();
+    let _handle = logging::spawn("Interaction", move || {
         loop {
             let buffer = &mut String::new();
             stdin().read_line(buffer).unwrap();
             if let "m" = buffer.trim_right() {
                 i_clone.store(true, Ordering::SeqCst);
-                perform_user_interaction(other_node.clone()).expect("perform_user_interaction failed");
+                perform_user_interaction(arc_clone.clone(), other_node.clone(), &config).expect("perform_user_interaction failed");
                 i_clone.store(false, Ordering::SeqCst);
             };
         }
@@ -160,29 +422,32 @@ pub fn print_and_interact(arc: Arc<Mutex<Node>>)  -> Result<(), Box<Error>> {
 
     loop {
         let node = arc.lock().unwrap();
-        let node_clone = node.clone();
+        let state = if node.is_joined() && !interaction_in_progress.load(Ordering::SeqCst) {
+            Some(print::format_current_node_state(&node))
+        } else {
+            None
+        };
         drop(node);
-        if node_clone.is_joined() && !interaction_in_progress.load(Ordering::SeqCst) {
-            print::print_current_node_state(&node_clone)
+        if let Some(state) = state {
+            print::print_node_state(&state)
+        }
+        if shutdown.wait(chord::NODE_PRINT_INTERVAL) {
+            break;
         }
-        thread::sleep(chord::NODE_PRINT_INTERVAL);
     }
+    // The interaction thread stays blocked on stdin, it ends with the process
+    Ok(())
 }
 
-pub fn listen_for_kill_signal(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+/// Requests the shutdown of the node on SIGINT, see `spawn_node`
+pub fn listen_for_kill_signal(shutdown: ShutdownSignal) -> Result<(), Box<Error>> {
     let signals = Signals::new(&[SIGINT])?;
-    let _handle = thread::Builder::new().name("Interaction".to_string()).spawn(move || {
+    let _handle = logging::spawn("Interaction", move || {
         for sig in signals.forever() {
             if sig == SIGINT {
-                let node = arc.lock().unwrap();
-                let node_clone = node.clone();
-                drop(node);
-
-                let handle_opt = node_clone.clone().graceful_shutdown();
-                if let Some(handle) = handle_opt {
-                    handle.join().expect("handle_graceful_shutdown failed");
-                }
-                process::exit(0);
+                info!("Got SIGINT, shutting down...");
+                shutdown.request();
+                break;
             }
         }
     }).unwrap();
@@ -190,22 +455,80 @@ pub fn listen_for_kill_signal(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
     Ok(())
 }
 
-pub fn create_node_id(ip_addr: SocketAddr) -> BigInt {
-    let hash = create_hash(&ip_addr.to_string());
+/// The routing state written by `save_routing_state`, `None` if there is none or it is unreadable
+fn load_routing_state(path: &Path) -> Option<RoutingState> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!(target: LOG_STABILIZE, "Unable to open the routing state {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match serde_json::from_reader(BufReader::new(file)) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!(target: LOG_STABILIZE, "Ignoring the invalid routing state {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn save_routing_state(arc: &Arc<Mutex<Node>>, path: &Path) {
+    let state = arc.lock().unwrap().export_routing_state();
+    let result = File::create(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::to_writer(BufWriter::new(file), &state).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => info!(target: LOG_STABILIZE, "Saved the routing state to {}", path.display()),
+        Err(e) => warn!(target: LOG_STABILIZE, "Unable to save the routing state to {}: {}", path.display(), e),
+    }
+}
+
+/// Hands the stored keys off to a successor and lets the others know that the node
+/// is leaving. The listener still answers while the keys are sent.
+fn leave_ring(arc: &Arc<Mutex<Node>>) {
+    let mut node = arc.lock().unwrap();
+    node.set_leaving();
+    let handoff = node.prepare_handoff();
+    drop(node);
+
+    let handle_opt = handoff.and_then(Handoff::send);
+    if let Some(handle) = handle_opt {
+        handle.join().expect("handle_graceful_shutdown failed");
+    }
+}
+
+/// Ring id of a node, the hash of its identity string, see `NodeIdentity`
+pub fn create_node_id(identity: &str) -> BigInt {
+    let hash = create_hash(identity);
     let byte_vec = hash.as_bytes().to_vec();
     let id = BigInt::from_bytes_be(Sign::Plus, &byte_vec);
     x_modulo_ring_size(id)
 }
 
-pub fn create_id(string: &str) -> BigInt {
-    let hash = create_hash(string);
+/// Key ids live in a space of `2^key_space_bits` ids, which may be larger than the ring.
+/// Two keys only share a storage slot if their key ids collide in this larger space.
+pub fn create_key_id(key: &str, key_space_bits: usize) -> BigInt {
+    let hash = create_hash(key);
     let byte_vec = hash.as_bytes().to_vec();
     let id = BigInt::from_bytes_be(Sign::Plus, &byte_vec);
-    x_modulo_ring_size(id)
+    id % (BigInt::from(1) << key_space_bits)
+}
+
+/// Folds a key id onto the ring: `key_id mod 2^m`. As `2^m` divides `2^key_space_bits`,
+/// a key is placed on the same node regardless of the key space it was hashed into.
+pub fn key_placement_id(key_id: &BigInt) -> BigInt {
+    x_modulo_ring_size(key_id.clone())
+}
+
+/// Fixed-width hex form of an id for logs and tables, messages keep using the full id
+pub fn short_id(id: &BigInt) -> String {
+    format!("{:0width$x}", id, width = CHORD_CIRCLE_BITS / 4)
 }
 
 /**
- * Test if id ∈ (first, second)
+ * Test if id ∈ [first, second)
  */
 pub fn is_in_interval(first: &BigInt, second: &BigInt, id: &BigInt) -> bool {
     if first == second {
@@ -215,18 +538,111 @@ pub fn is_in_interval(first: &BigInt, second: &BigInt, id: &BigInt) -> bool {
     }
 }
 
+/// Distance from `b` clockwise to `a`, in [0, ring size)
 pub fn chord_abs(a: &BigInt, b: &BigInt) -> BigInt {
-    if b > a {
-        chord::CHORD_RING_SIZE - b + a
-    } else {
-        a - b
-    }
+    x_modulo_ring_size(a - b)
 }
 
+/// Test if key_id ∈ (pre_id, self_id], i.e. a key whose id equals a node id belongs to that node
+/// and not to its predecessor, as in the chord paper. Storing, finding and the successor lookup
+/// all use this convention, otherwise a key stored exactly on a node id could not be found.
+/// `pre_id == self_id` is the whole ring.
 pub fn is_my_key(self_id: &BigInt, pre_id: &BigInt, key_id: &BigInt) -> bool {
     self_id == key_id || (key_id != pre_id && is_in_interval(pre_id, self_id, key_id))
 }
 
+/// Splits the ring interval [start, end) into at most two intervals that do not wrap around.
+/// `start == end` is the whole ring.
+pub fn ring_segments(start: &BigInt, end: &BigInt) -> Vec<(BigInt, BigInt)> {
+    if start < end {
+        vec![(start.clone(), end.clone())]
+    } else {
+        let ring_end = chord::CHORD_RING_SIZE.to_bigint().unwrap();
+        vec![(start.clone(), ring_end), (BigInt::from(0), end.clone())]
+            .into_iter()
+            .filter(|(segment_start, segment_end)| segment_start < segment_end)
+            .collect()
+    }
+}
+
+/// The ring segments of the ids from `from_id` exclusive to `to_id` inclusive, those a node
+/// with id `to_id` and predecessor `from_id` owns
+pub fn owned_segments(from_id: &BigInt, to_id: &BigInt) -> Vec<(BigInt, BigInt)> {
+    ring_segments(&x_modulo_ring_size(from_id + 1), &x_modulo_ring_size(to_id + 1))
+}
+
+/// The parts of the segments `a` not covered by any of the segments `b`
+pub fn subtract_segments(a: &[(BigInt, BigInt)], b: &[(BigInt, BigInt)]) -> Vec<(BigInt, BigInt)> {
+    let mut rest = a.to_vec();
+    for (b_start, b_end) in b {
+        rest = rest.into_iter().flat_map(|(start, end)| {
+            let mut parts = Vec::new();
+            if &start < b_start {
+                parts.push((start.clone(), cmp::min(end.clone(), b_start.clone())));
+            }
+            if &end > b_end {
+                parts.push((cmp::max(start, b_end.clone()), end));
+            }
+            parts
+        }).collect();
+    }
+    rest
+}
+
+/// The parts of the segments `a` covered by one of the segments `b`
+pub fn intersect_segments(a: &[(BigInt, BigInt)], b: &[(BigInt, BigInt)]) -> Vec<(BigInt, BigInt)> {
+    subtract_segments(a, &subtract_segments(a, b))
+}
+
+/// Visits every node of the ring once, starting at `start` and asking up to `parallelism` nodes
+/// at the same time. `visit` asks one node and returns its answer and the nodes after it, i.e.
+/// its successor list. A node whose visit fails, e.g. after the network timeout, is returned with
+/// the error, and the crawl goes on with the successors the other nodes named. The crawl stops
+/// after `CRAWL_MAX_NODES` visits, e.g. if nodes churn faster than they are visited.
+pub fn crawl_ring<T, F>(start: OtherNode, parallelism: usize, visit: F) -> Vec<(OtherNode, Result<T, String>)>
+    where T: Send + 'static, F: Fn(&OtherNode) -> Result<(T, Vec<OtherNode>), String> + Clone + Send + 'static {
+    let mut visits = Vec::new();
+    let mut seen: HashSet<BigInt> = HashSet::new();
+    seen.insert(start.get_id().clone());
+    let mut pending = VecDeque::new();
+    pending.push_back(start);
+    while !pending.is_empty() && visits.len() < CRAWL_MAX_NODES {
+        let batch_size = cmp::min(cmp::min(cmp::max(parallelism, 1), pending.len()), CRAWL_MAX_NODES - visits.len());
+        let batch: Vec<_> = pending.drain(..batch_size)
+            .map(|node| {
+                let visit = visit.clone();
+                let visited = node.clone();
+                (node, logging::spawn("Crawl", move || visit(&visited)))
+            })
+            .collect();
+        for (node, handle) in batch {
+            let result = match handle {
+                Ok(handle) => handle.join().unwrap_or_else(|_| Err("the visit panicked".to_string())),
+                Err(e) => Err(e.to_string()),
+            };
+            match result {
+                Ok((answer, next_nodes)) => {
+                    for next in next_nodes {
+                        if seen.insert(next.get_id().clone()) {
+                            pending.push_back(next);
+                        }
+                    }
+                    visits.push((node, Ok(answer)));
+                }
+                Err(e) => visits.push((node, Err(e))),
+            }
+        }
+    }
+    visits
+}
+
+/// Hex SHA-1 of `value`, see `storage::content_key`
+pub fn create_content_hash(value: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(value);
+    hasher.result_str()
+}
+
 fn create_hash(string: &str) -> String {
     // create a Sha1 object
     let mut hasher = Sha1::new();
@@ -238,85 +654,677 @@ fn create_hash(string: &str) -> String {
     hasher.result_str()
 }
 
-fn x_modulo_ring_size(x: BigInt) -> BigInt {
-    let one: &BigInt = &1.to_bigint().unwrap();
+/// `x` moved into [0, ring size), also if it is negative, e.g. the difference of two ids
+pub fn x_modulo_ring_size(x: BigInt) -> BigInt {
     let chord_ring_size: &BigInt = &chord::CHORD_RING_SIZE.to_bigint().unwrap();
-    BigInt::modpow(&x, one, chord_ring_size)
+    x.mod_floor(chord_ring_size)
+}
+
+/// Reasons for a node to stop, returned by the thread of `spawn_node`
+#[derive(Debug)]
+pub enum ChordError {
+    /// No join request was answered within `ChordConfig::join_timeout`
+    JoinTimeout(time::Duration),
+}
+
+impl fmt::Display for ChordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChordError::JoinTimeout(timeout) => write!(f, "Unable to join the chord ring within {:?}", timeout),
+        }
+    }
+}
+
+impl Error for ChordError {}
+
+/// Runs `task` on a named thread and restarts it whenever it panics.
+/// If the panic left the node poisoned a restart would fail right away, so the node exits instead.
+fn spawn_supervised<F>(name: &str, arc: Arc<Mutex<Node>>, task: F) -> JoinHandle<()>
+    where F: Fn(Arc<Mutex<Node>>) + Send + 'static {
+    let thread_name = name.to_string();
+    logging::spawn(name, move || {
+        loop {
+            let arc_clone = arc.clone();
+            match panic::catch_unwind(AssertUnwindSafe(|| task(arc_clone))) {
+                Ok(()) => break,
+                Err(_) if arc.is_poisoned() => {
+                    error!("{} thread panicked while holding the node, exiting", thread_name);
+                    process::exit(1);
+                }
+                Err(_) => {
+                    error!("{} thread panicked, restarting it", thread_name);
+                    thread::sleep(chord::NODE_RESTART_INTERVAL);
+                }
+            }
+        }
+    }).unwrap()
+}
+
+/// Asks the local network for ring members if `--discover` is given
+#[cfg(feature = "discovery")]
+fn discover_entry_nodes(config: &ChordConfig) -> Vec<SocketAddr> {
+    if !config.discover {
+        return Vec::new();
+    }
+    match discovery::discover(discovery::DISCOVERY_TIMEOUT) {
+        Ok(entry_node_addrs) => {
+            info!("Discovered {} node(s) in the local network: {:?}", entry_node_addrs.len(), entry_node_addrs);
+            entry_node_addrs
+        }
+        Err(e) => {
+            warn!("Discovery failed, creating a new chord ring: {}", e);
+            Vec::new()
+        }
+    }
 }
 
-pub fn spawn_node(node_ip_addr: SocketAddr, port: i32, entry_node_addr: Option<SocketAddr>) -> JoinHandle<()> {
+#[cfg(not(feature = "discovery"))]
+fn discover_entry_nodes(_config: &ChordConfig) -> Vec<SocketAddr> {
+    Vec::new()
+}
+
+/// Lets nodes started later find this one
+#[cfg(feature = "discovery")]
+fn advertise(node_ip_addr: SocketAddr) {
+    if let Err(e) = discovery::advertise(node_ip_addr) {
+        warn!("Unable to advertise the node in the local network: {}", e);
+    }
+}
+
+#[cfg(not(feature = "discovery"))]
+fn advertise(_node_ip_addr: SocketAddr) {}
+
+/// Starts a node on its own thread. The node runs until SIGINT or a `Kill` message requests
+/// its shutdown, then leaves the ring, stops its threads and lets the listener finish the
+/// connections in progress. The thread returns an error if the node could not join the ring in time.
+pub fn spawn_node(node_ip_addr: SocketAddr, port: i32, entry_node_addr: Option<SocketAddr>, config: ChordConfig) -> JoinHandle<Result<(), ChordError>> {
     if entry_node_addr.is_some() {
         info!("Spawn node and join.");
     } else {
         info!("Spawn master node.");
     }
-    let builder = thread::Builder::new().name("Node".to_string());
-    builder
-        .spawn(move || {
-            let node = if entry_node_addr.is_some() {
-                Node::new(node_ip_addr)
-            } else {
-                Node::new_first(node_ip_addr)
-            };
-            // let mut node = node::Node::new(node_ip_addr.clone());
-            let id = node.get_id().clone();
-            let id_clone = id.clone();
-
-            let other_node = node.to_other_node();
-
-            let arc = Arc::new(Mutex::new(node));
-
-            let arc_clone1 = arc.clone();
-            let handle1 = thread::Builder::new().name("Listen".to_string())
-                .spawn(move || {
-                    network::start_listening_on_socket(arc_clone1, port, id_clone).expect("network_util::start_listening_on_socket failed");
-                }).unwrap();
-
-            let arc_join = arc.clone();
-            if let Some(entry_node_addr) = entry_node_addr {
-                thread::sleep(chord::NODE_INIT_SLEEP_INTERVAL);
-                let mut node_join = arc_join.lock().unwrap();
-                while !node_join.is_joined() {
-                    drop(node_join);
-                    chord::join(id.clone(), other_node.clone(), entry_node_addr);
-                    thread::sleep(chord::NODE_INIT_SLEEP_INTERVAL);
-                    node_join = arc_join.lock().unwrap();
-                }
+    logging::spawn("Node", move || {
+        let listeners = network::bind_listeners(port, &config.listen_addresses).expect("network::bind_listeners failed");
+        let bound_port = listeners[0].local_addr().expect("listener.local_addr failed").port();
+        if port == 0 {
+            info!("Listening on ephemeral port {}", bound_port);
+        }
+        let node_ip_addr = SocketAddr::new(node_ip_addr.ip(), bound_port);
+        let entry_node_addrs = match entry_node_addr {
+            Some(entry_node_addr) => vec![entry_node_addr],
+            None => discover_entry_nodes(&config),
+        };
+        let discover = config.discover;
+        let join_timeout = config.join_timeout;
+        let headless = config.headless;
+        let mut node = if !entry_node_addrs.is_empty() {
+            Node::new(node_ip_addr, config)
+        } else {
+            Node::new_first(node_ip_addr, config)
+        };
+        let shutdown = ShutdownSignal::default();
+        node.set_shutdown_signal(shutdown.clone());
+        let routing_state_file = node.get_config().routing_state_file.clone();
+        if let Some(state) = routing_state_file.as_ref().and_then(|path| load_routing_state(path)) {
+            node.import_routing_state(state);
+        }
+        // let mut node = node::Node::new(node_ip_addr.clone());
+        let id = node.get_id().clone();
+        logging::set_node_id(&id);
+        let id_clone = id.clone();
+
+        let other_node = node.to_other_node();
+        let network = node.get_network().clone();
 
+        let arc = Arc::new(Mutex::new(node));
+
+        let arc_clone1 = arc.clone();
+        let (listener_shutdown, listener_stopped) = oneshot::channel();
+        let handle1 = logging::spawn("Listen", move || {
+            network::start_listening_on_socket(arc_clone1, listeners, id_clone, listener_stopped).expect("network_util::start_listening_on_socket failed");
+        }).unwrap();
+
+        let arc_join = arc.clone();
+        let join_trigger = arc_join.lock().unwrap().get_stabilize_trigger().clone();
+        if !entry_node_addrs.is_empty() {
+            thread::sleep(chord::NODE_INIT_SLEEP_INTERVAL);
+            let join_started = Instant::now();
+            let mut node_join = arc_join.lock().unwrap();
+            while !node_join.is_joined() {
+                drop(node_join);
+                if shutdown.is_requested() {
+                    let _ = listener_shutdown.send(());
+                    handle1.join().expect("handle1 failed");
+                    return Ok(());
+                }
+                if let Some(timeout) = join_timeout {
+                    if join_started.elapsed() >= timeout {
+                        error!(target: LOG_STABILIZE, "No join request was answered within {:?}, giving up", timeout);
+                        // Nothing but the listener runs before the node joined
+                        let _ = listener_shutdown.send(());
+                        handle1.join().expect("handle1 failed");
+                        return Err(ChordError::JoinTimeout(timeout));
+                    }
+                }
+                // The first answer lets me join, later ones are ignored
+                for entry_node_addr in &entry_node_addrs {
+                    chord::join(&network, id.clone(), other_node.clone(), *entry_node_addr);
+                }
+                wait_for_join(&arc_join, &join_trigger, chord::NODE_INIT_SLEEP_INTERVAL);
+                node_join = arc_join.lock().unwrap();
             }
 
-            let arc_clone2 = arc.clone();
-            let handle2 = thread::Builder::new().name("Stabilize".to_string())
-                .spawn(move || {
-                    chord::stabilize(arc_clone2);
-                }).unwrap();
-
-            let arc_clone3 = arc.clone();
-            let handle3 = thread::Builder::new().name("Fix_Fingers".to_string())
-                .spawn(move || {
-                    chord::fix_fingers(arc_clone3);
-                }).unwrap();
-
-            let arc_clone4 = arc.clone();
-            let handle4 = thread::Builder::new().name("Check_Predecessor".to_string())
-                .spawn(move || {
-                    chord::check_predecessor(arc_clone4);
-                }).unwrap();
-
-            let arc_clone5 = arc.clone();
-            let handle5 = thread::Builder::new().name("Print_Interact".to_string())
-                .spawn(move || {
-                    chord::print_and_interact(arc_clone5).expect("print_and_interact failed");
-                }).unwrap();
-
-            let arc_clone6 = arc.clone();
-            chord::listen_for_kill_signal(arc_clone6).expect("listen_for_kill_signal failed");
-
-            handle1.join().expect("handle1 failed");
-            handle2.join().expect("handle2 failed");
-            handle3.join().expect("handle3 failed");
-            handle4.join().expect("handle4 failed");
+        }
+        if discover {
+            advertise(node_ip_addr);
+        }
+
+        let handle2 = spawn_supervised("Stabilize", arc.clone(), move |arc_clone2| {
+            chord::stabilize(arc_clone2, entry_node_addrs.clone());
+        });
+
+        let handle3 = spawn_supervised("Fix_Fingers", arc.clone(), chord::fix_fingers);
+
+        let handle4 = spawn_supervised("Check_Predecessor", arc.clone(), chord::check_predecessor);
+
+        let handle6 = spawn_supervised("Check_Load", arc.clone(), chord::check_load);
+
+        let handle7 = spawn_supervised("Probe_Rings", arc.clone(), chord::probe_rings);
+
+        let arc_clone5 = arc.clone();
+        let handle5 = if headless {
+            None
+        } else {
+            Some(logging::spawn("Print_Interact", move || {
+                chord::print_and_interact(arc_clone5).expect("print_and_interact failed");
+            }).unwrap())
+        };
+
+        chord::listen_for_kill_signal(shutdown.clone()).expect("listen_for_kill_signal failed");
+
+        shutdown.wait_requested();
+        if let Some(path) = routing_state_file {
+            save_routing_state(&arc, &path);
+        }
+        leave_ring(&arc);
+        // The maintenance loops wake up from their waits, stabilize from its trigger
+        arc.lock().unwrap().trigger_stabilize();
+        let _ = listener_shutdown.send(());
+
+        handle1.join().expect("handle1 failed");
+        handle2.join().expect("handle2 failed");
+        handle3.join().expect("handle3 failed");
+        handle4.join().expect("handle4 failed");
+        handle6.join().expect("handle6 failed");
+        handle7.join().expect("handle7 failed");
+        if let Some(handle5) = handle5 {
             handle5.join().expect("handle5 failed");
-        })
-        .unwrap()
+        }
+        info!("Node #{} shut down", chord::short_id(&id));
+        Ok(())
+    }).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    use super::super::client::ChordClient;
+
+    fn ring_size() -> BigInt {
+        CHORD_RING_SIZE.to_bigint().unwrap()
+    }
+
+    // The edges of the ring and ids on both sides of the wrap-around
+    fn edge_ids() -> Vec<BigInt> {
+        let ring_size = ring_size();
+        vec![
+            BigInt::from(0),
+            BigInt::from(1),
+            BigInt::from(2),
+            &ring_size / 2,
+            &ring_size - 2,
+            &ring_size - 1,
+        ]
+    }
+
+    #[test]
+    fn chord_abs_is_the_clockwise_distance_in_the_ring() {
+        let ring_size = ring_size();
+        for a in &edge_ids() {
+            for b in &edge_ids() {
+                let distance = chord_abs(a, b);
+                assert!(distance >= BigInt::from(0) && distance < ring_size, "chord_abs({}, {}) = {}", a, b, distance);
+                assert_eq!(x_modulo_ring_size(b + &distance), *a);
+                let back = chord_abs(b, a);
+                if a == b {
+                    assert_eq!(distance, BigInt::from(0));
+                } else {
+                    assert_eq!(distance + back, ring_size);
+                }
+            }
+        }
+        assert_eq!(chord_abs(&BigInt::from(0), &(&ring_size - 1)), BigInt::from(1));
+        assert_eq!(chord_abs(&(&ring_size - 1), &BigInt::from(0)), &ring_size - 1);
+    }
+
+    #[test]
+    fn is_in_interval_matches_the_clockwise_distance() {
+        for first in &edge_ids() {
+            for second in &edge_ids() {
+                for id in &edge_ids() {
+                    let expected = first == second || chord_abs(id, first) < chord_abs(second, first);
+                    assert_eq!(is_in_interval(first, second, id), expected, "{} in [{}, {})", id, first, second);
+                }
+                assert!(is_in_interval(first, second, first));
+                assert_eq!(is_in_interval(first, second, second), first == second);
+            }
+        }
+    }
+
+    #[test]
+    fn is_in_interval_wraps_around_zero() {
+        let ring_size = ring_size();
+        let last = &ring_size - 1;
+        let zero = BigInt::from(0);
+        let one = BigInt::from(1);
+        assert!(is_in_interval(&last, &one, &last));
+        assert!(is_in_interval(&last, &one, &zero));
+        assert!(!is_in_interval(&last, &one, &one));
+        assert!(!is_in_interval(&last, &one, &(&ring_size / 2)));
+        // The same bounds the other way around hold everything except the wrap-around
+        assert!(!is_in_interval(&one, &last, &zero));
+        assert!(!is_in_interval(&one, &last, &last));
+        assert!(is_in_interval(&one, &last, &(&ring_size / 2)));
+    }
+
+    #[test]
+    fn is_my_key_includes_the_node_id_and_excludes_the_predecessor() {
+        let ring_size = ring_size();
+        let last = &ring_size - 1;
+        let zero = BigInt::from(0);
+        let one = BigInt::from(1);
+        assert!(is_my_key(&zero, &last, &zero));
+        assert!(!is_my_key(&zero, &last, &last));
+        assert!(is_my_key(&one, &last, &zero));
+        assert!(!is_my_key(&one, &last, &(&ring_size / 2)));
+        // A single node owns the whole ring
+        for id in &edge_ids() {
+            assert!(is_my_key(&one, &one, id));
+        }
+    }
+
+    // Number of keys out of `keys` whose key id another one already has
+    fn key_id_collisions(keys: &[String], key_space_bits: usize) -> usize {
+        let ids: HashSet<BigInt> = keys.iter().map(|key| create_key_id(key, key_space_bits)).collect();
+        keys.len() - ids.len()
+    }
+
+    #[test]
+    fn a_larger_key_space_makes_keys_collide_less_and_places_them_on_the_same_nodes() {
+        let keys: Vec<String> = (0..20_000).map(|i| format!("key{}", i)).collect();
+        assert!(key_id_collisions(&keys, CHORD_CIRCLE_BITS) > 0);
+        assert_eq!(key_id_collisions(&keys, 64), 0);
+        for key in &keys {
+            assert_eq!(key_placement_id(&create_key_id(key, 64)), create_key_id(key, CHORD_CIRCLE_BITS));
+        }
+    }
+
+    #[test]
+    fn the_stabilize_interval_grows_while_the_ring_is_quiet_and_shrinks_after_a_change() {
+        let config = ChordConfig::default();
+        let mut interval = config.stabilize_min_interval;
+        let mut intervals = Vec::new();
+        for _ in 0..6 {
+            interval = adapt_stabilize_interval(interval, false, &config);
+            intervals.push(interval);
+        }
+        let millis: Vec<time::Duration> = [1000, 2000, 4000, 8000, 8000, 8000].iter().map(|&ms| time::Duration::from_millis(ms)).collect();
+        assert_eq!(intervals, millis);
+        assert_eq!(adapt_stabilize_interval(interval, true, &config), config.stabilize_min_interval);
+    }
+
+    fn joining_node() -> Node {
+        Node::new(SocketAddr::from(([127, 0, 0, 1], 1)), ChordConfig::default())
+    }
+
+    fn found(successor: &OtherNode) -> Response {
+        Response::FoundSuccessor { successor: successor.clone(), lookup: LookupContext::new() }
+    }
+
+    // A node at a free address, nothing answers its pings
+    fn dead_node() -> OtherNode {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        OtherNode::new(BigInt::from(5), addr)
+    }
+
+    // A node answering one ping
+    fn live_node() -> OtherNode {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let node = OtherNode::new(BigInt::from(5), listener.local_addr().unwrap());
+        let pong = serde_json::to_string(&Message::Pong { sender: node.clone() }).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut ping = String::new();
+            stream.read_to_string(&mut ping).unwrap();
+            stream.write_all(pong.as_bytes()).unwrap();
+        });
+        node
+    }
+
+    // A node answering pings until it is killed, the first one after `first_pong_delay`
+    fn answering_node(id: BigInt, first_pong_delay: time::Duration) -> (OtherNode, Arc<AtomicBool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let node = OtherNode::new(id, listener.local_addr().unwrap());
+        let pong = serde_json::to_string(&Message::Pong { sender: node.clone() }).unwrap();
+        let alive = Arc::new(AtomicBool::new(true));
+        let alive_clone = alive.clone();
+        thread::spawn(move || {
+            let mut delay = first_pong_delay;
+            for stream in listener.incoming() {
+                if !alive_clone.load(Ordering::SeqCst) {
+                    break;
+                }
+                let mut stream = stream.unwrap();
+                let mut ping = String::new();
+                let _ = stream.read_to_string(&mut ping);
+                thread::sleep(delay);
+                delay = time::Duration::from_millis(0);
+                let _ = stream.write_all(pong.as_bytes());
+            }
+        });
+        (node, alive)
+    }
+
+    // Closes the listener of an `answering_node`, later pings are refused
+    fn kill(node: &OtherNode, alive: &AtomicBool) {
+        alive.store(false, Ordering::SeqCst);
+        drop(TcpStream::connect(node.get_ip_addr()));
+        while TcpStream::connect(node.get_ip_addr()).is_ok() {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    // A node answering pings and passing on the other messages it gets
+    fn recording_node(id: BigInt) -> (OtherNode, mpsc::Receiver<Message>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let node = OtherNode::new(id, listener.local_addr().unwrap());
+        let pong = serde_json::to_string(&Message::Pong { sender: node.clone() }).unwrap();
+        let (messages, received) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut msg = String::new();
+                let _ = stream.read_to_string(&mut msg);
+                match serde_json::from_str(msg.trim()) {
+                    Ok(Message::Ping { .. }) => {
+                        let _ = stream.write_all(pong.as_bytes());
+                    }
+                    Ok(msg) => {
+                        if messages.send(msg).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+        (node, received)
+    }
+
+    // The first request `wanted` picks something of, other messages are skipped
+    fn wait_for_request<T, F: Fn(Request) -> Option<T>>(received: &mpsc::Receiver<Message>, wanted: F) -> T {
+        loop {
+            match received.recv_timeout(time::Duration::from_secs(5)) {
+                Ok(Message::RequestMessage { request, .. }) => {
+                    if let Some(found) = wanted(request) {
+                        return found;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => panic!("the request was not received"),
+            }
+        }
+    }
+
+    #[test]
+    fn the_notify_after_a_dead_successor_reaches_the_next_live_one() {
+        let mut node = Node::new_first(SocketAddr::from(([127, 0, 0, 1], 1)), ChordConfig::default());
+        let me = node.to_other_node();
+        let successor = OtherNode::new(BigInt::from(6), *dead_node().get_ip_addr());
+        let (backup, received) = recording_node(BigInt::from(7));
+        node.update_successor_and_successor_list(successor);
+        node.process_incoming_response(Response::GetSuccessorListResponse { successor_list: vec![backup.clone()], reservations: Vec::new() });
+
+        let arc = Arc::new(Mutex::new(node));
+        let arc_stabilize = arc.clone();
+        let stabilizing = thread::spawn(move || stabilize(arc_stabilize, Vec::new()));
+        wait_for_request(&received, |request| match request {
+            Request::GetPredecessor => Some(()),
+            _ => None,
+        });
+        arc.lock().unwrap().process_incoming_response(Response::GetPredecessorResponse { predecessor: None, recently_dead: Vec::new() });
+        let notified = wait_for_request(&received, |request| match request {
+            Request::Notify { node } => Some(node),
+            _ => None,
+        });
+        assert_eq!(notified, me);
+        assert_eq!(arc.lock().unwrap().get_successor(), backup);
+
+        let node = arc.lock().unwrap();
+        node.get_shutdown_signal().request();
+        node.trigger_stabilize();
+        drop(node);
+        stabilizing.join().unwrap();
+    }
+
+    #[test]
+    fn a_found_successor_that_does_not_answer_pings_is_not_joined() {
+        let mut node = joining_node();
+        let successor = dead_node();
+        node.process_incoming_response(found(&successor));
+        // Not pinged while the listener holds the node
+        assert!(!node.is_joined());
+        assert_ne!(node.get_successor(), successor);
+
+        let arc = Arc::new(Mutex::new(node));
+        assert!(confirm_join_candidate(&arc));
+        assert!(!arc.lock().unwrap().is_joined());
+        assert_ne!(arc.lock().unwrap().get_successor(), successor);
+        // Nothing is left to confirm, so the join requests are sent again
+        assert!(!confirm_join_candidate(&arc));
+    }
+
+    #[test]
+    fn a_found_successor_is_joined_once_it_answers_a_ping() {
+        let mut node = joining_node();
+        let successor = live_node();
+        node.process_incoming_response(found(&successor));
+        assert!(!node.is_joined());
+
+        let arc = Arc::new(Mutex::new(node));
+        assert!(confirm_join_candidate(&arc));
+        assert!(arc.lock().unwrap().is_joined());
+        assert_eq!(arc.lock().unwrap().get_successor(), successor);
+    }
+
+    #[test]
+    fn a_slow_predecessor_is_kept_and_a_dead_one_only_dropped_at_the_failure_threshold() {
+        let config = ChordConfig { predecessor_failure_threshold: 2, ..ChordConfig::default() };
+        let mut node = Node::new_first(SocketAddr::from(([127, 0, 0, 1], 1)), config);
+        let (predecessor, predecessor_alive) = answering_node(BigInt::from(7), chord::PING_TIMEOUT_INTERVAL + time::Duration::from_millis(1000));
+        node.set_predecessor(Some(predecessor.clone()));
+        let arc = Arc::new(Mutex::new(node));
+        let arc_check = arc.clone();
+        let checking = thread::spawn(move || check_predecessor(arc_check));
+
+        // The first ping timed out, the check after it was answered
+        thread::sleep(chord::PING_TIMEOUT_INTERVAL + chord::NODE_CHECK_PREDECESSOR_INTERVAL * 3);
+        assert_eq!(arc.lock().unwrap().get_predecessor().clone(), Some(predecessor.clone()));
+
+        kill(&predecessor, &predecessor_alive);
+        let killed = Instant::now();
+        thread::sleep(chord::NODE_CHECK_PREDECESSOR_INTERVAL);
+        assert_eq!(arc.lock().unwrap().get_predecessor().clone(), Some(predecessor));
+        while arc.lock().unwrap().get_predecessor().is_some() {
+            assert!(killed.elapsed() < chord::NODE_CHECK_PREDECESSOR_INTERVAL * 10, "the dead predecessor was not dropped");
+            thread::sleep(time::Duration::from_millis(100));
+        }
+
+        arc.lock().unwrap().get_shutdown_signal().request();
+        checking.join().unwrap();
+    }
+
+    #[test]
+    fn routing_state_is_kept_while_maintenance_is_paused_and_repaired_after_resuming() {
+        let config = ChordConfig { predecessor_failure_threshold: 1, ..ChordConfig::default() };
+        let mut node = Node::new_first(SocketAddr::from(([127, 0, 0, 1], 1)), config);
+        // My successor was killed, the next one in the list answers
+        let successor = OtherNode::new(BigInt::from(6), *dead_node().get_ip_addr());
+        let next = live_node();
+        node.update_successor_and_successor_list(successor.clone());
+        node.process_incoming_response(Response::GetSuccessorListResponse { successor_list: vec![next.clone()], reservations: Vec::new() });
+        let predecessor = OtherNode::new(BigInt::from(7), *dead_node().get_ip_addr());
+        node.set_predecessor(Some(predecessor.clone()));
+        node.pause_maintenance();
+
+        let arc = Arc::new(Mutex::new(node));
+        let arc_stabilize = arc.clone();
+        let stabilizing = thread::spawn(move || stabilize(arc_stabilize, Vec::new()));
+        let arc_check = arc.clone();
+        let checking = thread::spawn(move || check_predecessor(arc_check));
+        thread::sleep(chord::NODE_STABILIZE_INTERVAL + chord::NODE_CHECK_PREDECESSOR_INTERVAL);
+        assert_eq!(arc.lock().unwrap().get_successor(), successor);
+        assert_eq!(arc.lock().unwrap().get_predecessor().clone(), Some(predecessor));
+
+        arc.lock().unwrap().resume_maintenance();
+        arc.lock().unwrap().trigger_stabilize();
+        let deadline = Instant::now() + chord::NODE_STABILIZE_INTERVAL * 5;
+        loop {
+            let repaired = {
+                let node = arc.lock().unwrap();
+                node.get_successor() == next && node.get_predecessor().is_none()
+            };
+            if repaired {
+                break;
+            }
+            assert!(Instant::now() < deadline, "the routing state was not repaired after resuming");
+            thread::sleep(time::Duration::from_millis(100));
+        }
+
+        let node = arc.lock().unwrap();
+        node.get_shutdown_signal().request();
+        node.trigger_stabilize();
+        drop(node);
+        stabilizing.join().unwrap();
+        checking.join().unwrap();
+    }
+
+    #[test]
+    fn a_triggered_stabilize_updates_the_successor_before_the_interval_is_over() {
+        let interval = time::Duration::from_secs(60);
+        let config = ChordConfig { stabilize_min_interval: interval, stabilize_max_interval: interval, ..ChordConfig::default() };
+        let mut node = Node::new_first(SocketAddr::from(([127, 0, 0, 1], 1)), config);
+        let (successor, successor_alive) = answering_node(BigInt::from(6), time::Duration::from_millis(0));
+        let (next, _next_alive) = answering_node(BigInt::from(7), time::Duration::from_millis(0));
+        node.update_successor_and_successor_list(successor.clone());
+        node.process_incoming_response(Response::GetSuccessorListResponse { successor_list: vec![next.clone()], reservations: Vec::new() });
+
+        let arc = Arc::new(Mutex::new(node));
+        let arc_stabilize = arc.clone();
+        let stabilizing = thread::spawn(move || stabilize(arc_stabilize, Vec::new()));
+        thread::sleep(time::Duration::from_millis(500));
+        assert_eq!(arc.lock().unwrap().get_successor(), successor);
+
+        kill(&successor, &successor_alive);
+        let triggered = Instant::now();
+        arc.lock().unwrap().trigger_stabilize();
+        while arc.lock().unwrap().get_successor() != next {
+            assert!(triggered.elapsed() < time::Duration::from_secs(5), "the triggered stabilize did not run");
+            thread::sleep(time::Duration::from_millis(50));
+        }
+
+        let node = arc.lock().unwrap();
+        node.get_shutdown_signal().request();
+        node.trigger_stabilize();
+        drop(node);
+        stabilizing.join().unwrap();
+    }
+
+    #[test]
+    fn joining_through_a_node_that_never_answers_fails_within_the_join_timeout() {
+        // Accepts join requests but never answers them
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let entry_node_addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut connections = Vec::new();
+            for connection in listener.incoming() {
+                connections.push(connection);
+            }
+        });
+        let timeout = time::Duration::from_millis(2000);
+        let config = ChordConfig { join_timeout: Some(timeout), headless: true, ..ChordConfig::default() };
+        let started = Instant::now();
+        let handle = spawn_node(SocketAddr::from(([127, 0, 0, 1], 0)), 0, Some(entry_node_addr), config);
+        match handle.join().unwrap() {
+            Err(ChordError::JoinTimeout(reported)) => assert_eq!(reported, timeout),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= timeout);
+        assert!(elapsed < timeout + chord::NODE_INIT_SLEEP_INTERVAL * 3, "the join gave up after {:?}", elapsed);
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    // Starts a headless node in this process and returns its address
+    fn start_node(entry_node_addr: Option<SocketAddr>) -> SocketAddr {
+        let addr = SocketAddr::from(([127, 0, 0, 1], free_port()));
+        let config = ChordConfig { headless: true, ..ChordConfig::default() };
+        spawn_node(addr, i32::from(addr.port()), entry_node_addr, config);
+        addr
+    }
+
+    #[test]
+    fn a_key_stored_in_one_ring_of_the_process_is_not_found_in_another() {
+        let ring_a = start_node(None);
+        let joined_a = start_node(Some(ring_a));
+        let ring_b = start_node(None);
+        thread::sleep(time::Duration::from_millis(500));
+        let key_space_bits = ChordConfig::default().key_space_bits;
+
+        ChordClient::new(ring_a, key_space_bits).store("key", "in ring a").unwrap();
+        let deadline = Instant::now() + time::Duration::from_secs(10);
+        while ChordClient::new(joined_a, key_space_bits).get("key") != Ok(Some("in ring a".to_string())) {
+            assert!(Instant::now() < deadline, "the key was not found through the other node of ring a");
+            thread::sleep(time::Duration::from_millis(200));
+        }
+        assert_eq!(ChordClient::new(ring_b, key_space_bits).get("key"), Ok(None));
+
+        ChordClient::new(ring_b, key_space_bits).store("key", "in ring b").unwrap();
+        assert_eq!(ChordClient::new(ring_a, key_space_bits).get("key"), Ok(Some("in ring a".to_string())));
+        assert_eq!(ChordClient::new(ring_b, key_space_bits).get("key"), Ok(Some("in ring b".to_string())));
+    }
+
+    #[test]
+    fn a_supervised_task_is_restarted_after_a_panic() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_clone = runs.clone();
+        let arc = Arc::new(Mutex::new(joining_node()));
+        let handle = spawn_supervised("Panics_Once", arc.clone(), move |_| {
+            if runs_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run");
+            }
+        });
+        handle.join().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        // The panic did not hold the node
+        assert!(!arc.is_poisoned());
+    }
 }
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+use num_bigint::BigInt;
+
+use super::chord;
+use super::encryption;
+use super::logging;
+use super::network::Network;
+use super::node::OtherNode;
+use super::protocols::*;
+use super::storage;
+use super::storage::DHTEntry;
+
+const READ_ONLY_ERROR: &str = "The owner of the key is read-only, try again later";
+
+/// What to do after a response: return the result or send the next request to another node
+enum Step<T> {
+    Done(Result<T, String>),
+    Forward(OtherNode, Box<Request>),
+}
+
+/// What `ChordClient::export_snapshot` found
+pub struct SnapshotSummary {
+    pub nodes: usize,
+    pub keys: usize,
+    /// Keys found on more than one node, because they moved while the ring was crawled
+    pub overlaps: usize,
+}
+
+/// Uses the DHT of a chord ring without joining it. Every operation starts at the bootstrap
+/// node and follows the `AskFurther*` answers itself, like a node would for its own lookups.
+#[derive(Clone)]
+pub struct ChordClient {
+    bootstrap: SocketAddr,
+    key_space_bits: usize,
+    network: Network,
+    /// Nodes a crawl of the ring asks at the same time, see `chord::crawl_ring`
+    crawl_parallelism: usize,
+    /// Passphrase values are sealed with, see `ChordConfig::value_encryption_key`
+    value_encryption_key: Option<String>,
+}
+
+impl ChordClient {
+    pub fn new(bootstrap: SocketAddr, key_space_bits: usize) -> ChordClient {
+        ChordClient { bootstrap, key_space_bits, network: Network::default(), crawl_parallelism: 1, value_encryption_key: None }
+    }
+
+    pub fn with_crawl_parallelism(self, crawl_parallelism: usize) -> ChordClient {
+        ChordClient { crawl_parallelism, ..self }
+    }
+
+    /// Stores values sealed with `value_encryption_key` and opens the values found with it
+    pub fn with_value_encryption_key(self, value_encryption_key: Option<String>) -> ChordClient {
+        ChordClient { value_encryption_key, ..self }
+    }
+
+    pub fn store(&self, key: &str, value: &str) -> Result<(), String> {
+        let data = storage::make_hashed_key_value_pair(key.to_string(), self.seal(value.as_bytes()), self.key_space_bits);
+        self.store_entry(data)
+    }
+
+    fn seal(&self, value: &[u8]) -> Vec<u8> {
+        match &self.value_encryption_key {
+            Some(passphrase) => encryption::encrypt_value(passphrase, value),
+            None => value.to_vec(),
+        }
+    }
+
+    fn open_found(&self, entry: Option<DHTEntry>) -> Result<Option<String>, String> {
+        match entry {
+            Some(entry) => self.open(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The value of `entry` as text, opened with the value encryption key if there is one
+    fn open(&self, entry: DHTEntry) -> Result<String, String> {
+        match &self.value_encryption_key {
+            Some(passphrase) => encryption::decrypt_value(passphrase, entry.get_value())
+                .map(|value| String::from_utf8_lossy(&value).into_owned())
+                .ok_or_else(|| format!("The value of key '{}' could not be decrypted with the value key", entry.get_key())),
+            None => Ok(entry.value_string()),
+        }
+    }
+
+    fn store_entry(&self, data: (BigInt, DHTEntry)) -> Result<(), String> {
+        self.route(Request::DHTStoreKey { data, moved: false }, |response| match response {
+            Response::DHTStoredKey { .. } => Step::Done(Ok(())),
+            Response::DHTReadOnly { .. } => Step::Done(Err(READ_ONLY_ERROR.to_string())),
+            Response::DHTRejectedKey { size, max_size, .. } =>
+                Step::Done(Err(format!("Value of {} bytes rejected, at most {} bytes are allowed", size, max_size))),
+            Response::DHTOverloaded { stored_bytes, .. } =>
+                Step::Done(Err(format!("The owner of the key is overloaded with {} bytes, try again later", stored_bytes))),
+            Response::DHTAskFurtherStore { next_node, data, moved } => Step::Forward(next_node, Box::new(Request::DHTStoreKey { data, moved })),
+            other => unexpected(other),
+        })
+    }
+
+    /// Appends `value` to the value of `key` on its owner, creating the key if absent, and
+    /// returns the length of the value afterwards. Of several concurrent appends none is lost.
+    pub fn append(&self, key: &str, value: &str) -> Result<usize, String> {
+        if self.value_encryption_key.is_some() {
+            // The owner would append to the ciphertext, which then cannot be opened anymore
+            return Err("Encrypted values cannot be appended to".to_string());
+        }
+        let key_id = chord::create_key_id(key, self.key_space_bits);
+        let req = Request::DHTAppend { key_id, key: key.to_string(), data: value.as_bytes().to_vec() };
+        self.route(req, |response| match response {
+            Response::DHTAppended { length, .. } => Step::Done(Ok(length)),
+            Response::DHTReadOnly { .. } => Step::Done(Err(READ_ONLY_ERROR.to_string())),
+            Response::DHTRejectedKey { size, max_size, .. } =>
+                Step::Done(Err(format!("Value of {} bytes rejected, at most {} bytes are allowed", size, max_size))),
+            Response::DHTOverloaded { stored_bytes, .. } =>
+                Step::Done(Err(format!("The owner of the key is overloaded with {} bytes, try again later", stored_bytes))),
+            Response::DHTAskFurtherAppend { next_node, key_id, key, data } =>
+                Step::Forward(next_node, Box::new(Request::DHTAppend { key_id, key, data })),
+            other => unexpected(other),
+        })
+    }
+
+    /// The value stored for `key`, `None` if the key does not exist
+    pub fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let key_id = chord::create_key_id(key, self.key_space_bits);
+        self.route(Request::DHTFindKey { key_id, lookup: LookupContext::new() }, |response| match response {
+            Response::DHTFoundKey { data, .. } => Step::Done(self.open_found(data.1)),
+            Response::DHTAskFurtherFind { next_node, key_id, lookup } =>
+                Step::Forward(next_node, Box::new(Request::DHTFindKey { key_id, lookup: lookup.forwarded() })),
+            other => unexpected(other),
+        })
+    }
+
+    /// Deletes `key` and returns whether it existed
+    pub fn delete(&self, key: &str) -> Result<bool, String> {
+        let key_id = chord::create_key_id(key, self.key_space_bits);
+        self.route(Request::DHTDeleteKey { key_id }, |response| match response {
+            Response::DHTDeletedKey { key_existed } => Step::Done(Ok(key_existed)),
+            Response::DHTReadOnly { .. } => Step::Done(Err(READ_ONLY_ERROR.to_string())),
+            Response::DHTAskFurtherDelete { next_node, key_id } => Step::Forward(next_node, Box::new(Request::DHTDeleteKey { key_id })),
+            other => unexpected(other),
+        })
+    }
+
+    /// Removes `key` and returns the value it had, `None` if the key does not exist.
+    /// Of several clients popping the same key only one gets the value.
+    pub fn pop(&self, key: &str) -> Result<Option<String>, String> {
+        let key_id = chord::create_key_id(key, self.key_space_bits);
+        self.route(Request::DHTGetAndDelete { key_id }, |response| match response {
+            Response::DHTPopped { data } => Step::Done(self.open_found(data)),
+            Response::DHTReadOnly { .. } => Step::Done(Err(READ_ONLY_ERROR.to_string())),
+            Response::DHTAskFurtherGetAndDelete { next_node, key_id } =>
+                Step::Forward(next_node, Box::new(Request::DHTGetAndDelete { key_id })),
+            other => unexpected(other),
+        })
+    }
+
+    /// The node responsible for `key`
+    pub fn owner(&self, key: &str) -> Result<OtherNode, String> {
+        let id = chord::key_placement_id(&chord::create_key_id(key, self.key_space_bits));
+        self.route(Request::FindSuccessor { id: id.clone(), lookup: LookupContext::new() }, |response| match response {
+            Response::FoundSuccessor { successor, .. } => Step::Done(Ok(successor)),
+            Response::AskFurther { next_node, lookup } =>
+                Step::Forward(next_node, Box::new(Request::FindSuccessor { id: id.clone(), lookup: lookup.forwarded() })),
+            other => unexpected(other),
+        })
+    }
+
+    /// Visits every node of the ring once, crawling from the bootstrap node along the successor
+    /// lists, and writes all entries to `path`, one JSON `[key_id, entry]` per line.
+    /// Each node's storage is dumped on its own, so a key moving during the crawl may be found
+    /// twice. The newer of both entries is kept. Fails if a node could not be dumped.
+    pub fn export_snapshot(&self, path: &Path) -> Result<SnapshotSummary, String> {
+        // The crawl needs the id of the bootstrap node to recognize it in the successor lists
+        let bootstrap = match self.network.send_client_request(self.bootstrap, Request::GetKeysOwned)? {
+            Response::KeysOwned { id, .. } => OtherNode::new(id, self.bootstrap),
+            other => return Err(format!("Unexpected response: {:?}", other)),
+        };
+        let client = self.clone();
+        let visits = chord::crawl_ring(bootstrap, self.crawl_parallelism, move |node: &OtherNode| {
+            let (_, data) = client.dump_node(*node.get_ip_addr())?;
+            match client.network.send_client_request(*node.get_ip_addr(), Request::GetSuccessorList)? {
+                Response::GetSuccessorListResponse { successor_list, .. } => Ok((data, successor_list)),
+                other => Err(format!("Unexpected response: {:?}", other)),
+            }
+        });
+
+        let mut entries: BTreeMap<BigInt, DHTEntry> = BTreeMap::new();
+        let mut overlaps = 0;
+        let nodes = visits.len();
+        for (node, result) in visits {
+            let data = result.map_err(|e| format!("Unable to dump node #{}: {}", chord::short_id(node.get_id()), e))?;
+            for (key_id, entry) in data {
+                match entries.entry(key_id) {
+                    Entry::Occupied(mut known) => {
+                        overlaps += 1;
+                        if entry.is_newer_than(known.get()) {
+                            known.insert(entry);
+                        }
+                    }
+                    Entry::Vacant(new) => {
+                        new.insert(entry);
+                    }
+                }
+            }
+        }
+
+        let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        for data in &entries {
+            serde_json::to_writer(&mut writer, &data)
+                .map_err(|e| e.to_string())
+                .and_then(|_| writer.write_all(b"\n").map_err(|e| e.to_string()))
+                .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        }
+        writer.flush().map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        Ok(SnapshotSummary { nodes, keys: entries.len(), overlaps })
+    }
+
+    /// Stores all entries of a file written by `export_snapshot` and returns how many there were.
+    /// The entries keep their timestamps, so they do not overwrite values written since the export.
+    pub fn import_snapshot(&self, path: &Path) -> Result<usize, String> {
+        let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+        let mut imported = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let data: (BigInt, DHTEntry) = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid entry in {}: {}", path.display(), e))?;
+            self.store_entry(data)?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// All entries stored on the node at `addr`, fetched page by page
+    fn dump_node(&self, addr: SocketAddr) -> Result<(OtherNode, Vec<(BigInt, DHTEntry)>), String> {
+        let mut entries = Vec::new();
+        let mut cursor = None;
+        loop {
+            let request = Request::DumpStorage { keys_only: false, cursor };
+            match self.network.send_client_request(addr, request)? {
+                Response::DumpedStoragePage { node, data, next_cursor, .. } => {
+                    entries.extend(data.into_iter().filter_map(|(key_id, entry)| entry.map(|entry| (key_id, entry))));
+                    match next_cursor {
+                        Some(next_cursor) => cursor = Some(next_cursor),
+                        None => return Ok((node, entries)),
+                    }
+                }
+                other => return Err(format!("Unexpected response: {:?}", other)),
+            }
+        }
+    }
+
+    /// Sends `request` to the bootstrap node and then to whichever node `handle` names next,
+    /// until `handle` returns a result or `chord::LOOKUP_TIMEOUT` passed. All hops are sent in
+    /// one span, so that they belong to the same trace.
+    fn route<T, F>(&self, request: Request, handle: F) -> Result<T, String>
+        where F: Fn(Response) -> Step<T> {
+        let previous = logging::current_trace();
+        logging::set_trace(Some(previous.map_or_else(TraceContext::new, |parent| parent.child())));
+        let result = self.follow(request, handle);
+        logging::set_trace(previous);
+        result
+    }
+
+    fn follow<T, F>(&self, request: Request, handle: F) -> Result<T, String>
+        where F: Fn(Response) -> Step<T> {
+        let deadline = Instant::now() + chord::LOOKUP_TIMEOUT;
+        let mut addr = self.bootstrap;
+        let mut request = request;
+        loop {
+            if Instant::now() >= deadline {
+                return Err(format!("Lookup timed out after {:?}", chord::LOOKUP_TIMEOUT));
+            }
+            let response = self.network.send_client_request(addr, request)?;
+            if let Response::LookupTimedOut { lookup, .. } = response {
+                return Err(format!("Lookup timed out after {} hops", lookup.get_hops()));
+            }
+            if let Response::LookupBusy { node, request: busy_request } = response {
+                debug!(target: chord::LOG_DHT, "Node #{} is busy, asking it again", chord::short_id(node.get_id()));
+                thread::sleep(chord::LOOKUP_BUSY_RETRY_INTERVAL);
+                request = *busy_request;
+                continue;
+            }
+            match handle(response) {
+                Step::Done(result) => return result,
+                Step::Forward(next_node, next_request) => {
+                    debug!(target: chord::LOG_DHT, "Client asks node #{} next", chord::short_id(next_node.get_id()));
+                    addr = *next_node.get_ip_addr();
+                    request = *next_request;
+                }
+            }
+        }
+    }
+}
+
+fn unexpected<T>(response: Response) -> Step<T> {
+    Step::Done(Err(format!("Unexpected response: {:?}", response)))
+}
@@ -0,0 +1,495 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use num_bigint::BigInt;
+
+use super::chord;
+
+/// Which live successor `stabilize` asks for its predecessor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuccessorSelection {
+    /// Always the first live successor in the successor list
+    FirstLive,
+    /// Each cycle the next live successor in the successor list
+    RoundRobin,
+}
+
+impl FromStr for SuccessorSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SuccessorSelection, String> {
+        match s {
+            "first-live" => Ok(SuccessorSelection::FirstLive),
+            "round-robin" => Ok(SuccessorSelection::RoundRobin),
+            _ => Err(format!("Unknown successor selection '{}'", s)),
+        }
+    }
+}
+
+/// When a node hands the keys of a new predecessor over to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MigrationStrategy {
+    /// All at once, as soon as the predecessor is taken
+    Eager,
+    /// A batch per `check_load` cycle. Until a key moved, the new owner asks its successor
+    /// for the key, which may hold it or a newer copy written while they did not know of each
+    /// other. The newer one is found and kept by the owner (read repair).
+    Lazy,
+}
+
+impl FromStr for MigrationStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MigrationStrategy, String> {
+        match s {
+            "eager" => Ok(MigrationStrategy::Eager),
+            "lazy" => Ok(MigrationStrategy::Lazy),
+            _ => Err(format!("Unknown migration strategy '{}'", s)),
+        }
+    }
+}
+
+/// How `Storage` compresses values of at least `ChordConfig::value_compression_threshold` bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueCompression {
+    None,
+    Deflate,
+    Gzip,
+}
+
+impl FromStr for ValueCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ValueCompression, String> {
+        match s {
+            "none" => Ok(ValueCompression::None),
+            "deflate" => Ok(ValueCompression::Deflate),
+            "gzip" => Ok(ValueCompression::Gzip),
+            _ => Err(format!("Unknown value compression '{}'", s)),
+        }
+    }
+}
+
+/// What the ring id of a node is the hash of, unless `ChordConfig::node_id` sets the id
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeIdentity {
+    /// The listening address, e.g. `10.0.0.5:34254`
+    SocketAddrString,
+    /// A configured name like a stable hostname, so that the id survives address changes.
+    /// Nodes with the same name get the same id on purpose, the name has to be unique in the ring.
+    Custom(String),
+}
+
+impl NodeIdentity {
+    /// The string hashed to the ring id of a node listening on `addr`
+    pub fn identity_string(&self, addr: SocketAddr) -> String {
+        match self {
+            NodeIdentity::SocketAddrString => addr.to_string(),
+            NodeIdentity::Custom(name) => name.clone(),
+        }
+    }
+}
+
+/// Ring ids [start, end) written as `START..END` in hex, as in the tables
+#[derive(Clone, Debug)]
+pub struct KeyRange {
+    pub start: BigInt,
+    pub end: BigInt,
+}
+
+/// Parses a ring id written in hex, as shown in the tables
+pub fn parse_ring_id(id: &str) -> Result<BigInt, String> {
+    match BigInt::parse_bytes(id.as_bytes(), 16) {
+        Some(ref parsed) if *parsed >= BigInt::from(0) && *parsed < BigInt::from(chord::CHORD_RING_SIZE) => Ok(parsed.clone()),
+        _ => Err(format!("Expected an id between 0 and {:x}, got '{}'", chord::CHORD_RING_SIZE - 1, id)),
+    }
+}
+
+impl FromStr for KeyRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeyRange, String> {
+        let mut ids = s.splitn(2, "..");
+        match (ids.next(), ids.next()) {
+            (Some(start), Some(end)) => Ok(KeyRange { start: parse_ring_id(start)?, end: parse_ring_id(end)? }),
+            _ => Err(format!("Expected a key range like 000000..100000, got '{}'", s)),
+        }
+    }
+}
+
+/// Runtime configuration of a node, filled from the command line arguments and optionally
+/// a configuration file, see `ChordConfig::from_toml_path`
+#[derive(Clone, Debug)]
+pub struct ChordConfig {
+    /// After a join, ask the found successor for its predecessor and only count
+    /// as joined if the placement in the ring is consistent
+    pub verify_join: bool,
+    /// Log message payloads at trace level and dump them at error level only
+    /// if handling or sending them fails
+    pub log_messages_on_error_only: bool,
+    pub successor_selection: SuccessorSelection,
+    /// Passphrase to encrypt values with before storing and to decrypt them with after
+    /// finding, so that the nodes of the ring only hold ciphertext
+    pub value_encryption_key: Option<String>,
+    /// Threads sending messages, `0` starts a thread for every message. Further messages wait
+    /// in a queue until one of the threads is free, the sender does not wait for them.
+    pub max_send_threads: usize,
+    /// Join the ring only to answer and forward lookups, without ever owning keys
+    pub observer: bool,
+    /// Combine GetPredecessor and Notify of each stabilize cycle into one Stabilize request
+    pub batch_stabilize: bool,
+    /// Size of the key id space in bits, at least `CHORD_CIRCLE_BITS`.
+    /// Key ids are folded onto the ring for placement, see `chord::key_placement_id`.
+    pub key_space_bits: usize,
+    /// Messages from peers speaking an older protocol version are ignored
+    pub min_protocol_version: u16,
+    /// Largest value in bytes this node stores as owner, `0` means unlimited
+    pub max_value_size: usize,
+    /// Values longer than this (in bytes) are stored and handed out as multiple chunks
+    pub chunk_size: usize,
+    /// Bytes of keys and values stored above which new values are refused until keys
+    /// were deleted or moved away, `0` means unlimited
+    pub storage_high_water_mark: usize,
+    /// Set TCP_NODELAY on all sockets, so that small messages are not delayed by Nagle's algorithm
+    pub tcp_nodelay: bool,
+    /// Enable TCP keep-alive with this interval on all sockets
+    pub tcp_keepalive: Option<Duration>,
+    /// Consecutive failed connects to a peer after which further connects fail right away for
+    /// `circuit_breaker_cooldown`, then a single probe decides whether the peer is back. `0` disables it.
+    pub circuit_breaker_threshold: usize,
+    pub circuit_breaker_cooldown: Duration,
+    /// Consecutive failed checks after which the predecessor counts as dead, so that
+    /// a single slow answer does not drop it
+    pub predecessor_failure_threshold: usize,
+    /// Without an entry point, join the ring members found in the local network, and
+    /// answer discovery queries of others (requires the `discovery` feature)
+    pub discover: bool,
+    /// Bounds of the stabilize interval, which doubles while successors and predecessor stay
+    /// the same and falls back to the minimum when they change
+    pub stabilize_min_interval: Duration,
+    pub stabilize_max_interval: Duration,
+    /// Key ranges this node owns regardless of its id, announced to the ring with the successor list
+    pub reserved_ranges: Vec<KeyRange>,
+    /// Most keys in one storage dump response, larger dumps are fetched page by page
+    pub dump_page_size: usize,
+    /// Nodes asked now and then whether they belong to the own ring, a node of another
+    /// ring lets the two rings merge after a partition healed
+    pub ring_probes: Vec<SocketAddr>,
+    /// Ring id to use instead of the hash of the address, so that a node restarted under
+    /// another address takes its old place in the ring
+    pub node_id: Option<BigInt>,
+    pub identity: NodeIdentity,
+    /// Give up instead of retrying forever if no join request was answered within this time
+    pub join_timeout: Option<Duration>,
+    /// How long a deleted key is remembered, so that an older copy moved in from another node
+    /// does not bring it back
+    pub tombstone_grace_period: Duration,
+    /// Pings the successor found by a join has to answer before the node counts as joined
+    pub join_pings: usize,
+    /// File the routing state is written to on shutdown and restored from on start, see `RoutingState`
+    pub routing_state_file: Option<PathBuf>,
+    /// Time a freshly joined node forwards writes for its keys to its successor, which owned them
+    /// until then. Ends early once the successor took the node as predecessor. `0` disables it.
+    pub write_warmup: Duration,
+    /// Lookups taking more hops than this factor times log2 of the estimated ring size are
+    /// reported as degraded routing, a sign of a stale or broken finger table. `0` disables it.
+    pub hop_alert_factor: f64,
+    pub migration_strategy: MigrationStrategy,
+    /// Nodes a crawl of the ring (e.g. the key distribution) asks at the same time
+    pub crawl_parallelism: usize,
+    /// Values are held compressed in memory, they are sent and handed out uncompressed
+    pub value_compression: ValueCompression,
+    /// Size in bytes from which on values are compressed
+    pub value_compression_threshold: usize,
+    /// Lookups of other nodes and clients in flight at the same time, from accepting them until
+    /// their answer is sent. Further ones are answered with `Response::LookupBusy` right away.
+    /// `0` means unbounded.
+    pub max_inflight_lookups: usize,
+    /// Neither print the node state periodically nor read the menu from stdin, for nodes
+    /// run from scripts or embedded in other programs
+    pub headless: bool,
+    /// Time a join request may take to connect and be sent, the join as a whole is bounded
+    /// by `join_timeout`
+    pub join_request_timeout: Duration,
+    /// Time for each hop of a lookup, the lookup as a whole is bounded by `chord::LOOKUP_TIMEOUT`
+    pub lookup_request_timeout: Duration,
+    /// Time for storing, changing or deleting a key, which may carry a large value
+    pub store_request_timeout: Duration,
+    /// Time for finding a key or fetching a page of a storage dump
+    pub find_request_timeout: Duration,
+    /// Time for handing all keys over to another node when leaving
+    pub handoff_request_timeout: Duration,
+    /// Answer finds but reject writes to the keys I own, e.g. during maintenance. Keys moving to
+    /// or from me as the ring changes are still taken and handed over.
+    pub read_only: bool,
+    /// Bytes per second of keys handed to other nodes, e.g. to a node that joined before me or
+    /// when leaving. `0` sends them all at once.
+    pub migration_rate_limit: usize,
+    /// Addresses to accept connections on, e.g. an IPv4 and an IPv6 one, all with the port of the
+    /// node. Empty accepts on `chord::LISTENING_ADDRESS`. Other nodes are told the address of the
+    /// command line either way, and the id is derived from it.
+    pub listen_addresses: Vec<IpAddr>,
+}
+
+/// The defaults of the command line options
+impl Default for ChordConfig {
+    fn default() -> ChordConfig {
+        ChordConfig {
+            verify_join: false,
+            log_messages_on_error_only: false,
+            successor_selection: SuccessorSelection::FirstLive,
+            value_encryption_key: None,
+            max_send_threads: 64,
+            observer: false,
+            batch_stabilize: false,
+            key_space_bits: chord::CHORD_CIRCLE_BITS,
+            min_protocol_version: 0,
+            max_value_size: 0,
+            chunk_size: 64 * 1024,
+            storage_high_water_mark: 0,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_cooldown: Duration::from_millis(10000),
+            predecessor_failure_threshold: 3,
+            discover: false,
+            stabilize_min_interval: Duration::from_millis(500),
+            stabilize_max_interval: Duration::from_millis(8000),
+            reserved_ranges: Vec::new(),
+            dump_page_size: 100,
+            ring_probes: Vec::new(),
+            node_id: None,
+            identity: NodeIdentity::SocketAddrString,
+            join_timeout: None,
+            tombstone_grace_period: Duration::from_millis(600_000),
+            join_pings: 1,
+            routing_state_file: None,
+            write_warmup: Duration::from_millis(0),
+            hop_alert_factor: 2.0,
+            migration_strategy: MigrationStrategy::Eager,
+            crawl_parallelism: 4,
+            value_compression: ValueCompression::None,
+            value_compression_threshold: 4096,
+            max_inflight_lookups: 0,
+            headless: false,
+            join_request_timeout: Duration::from_millis(5000),
+            lookup_request_timeout: Duration::from_millis(5000),
+            store_request_timeout: Duration::from_millis(30000),
+            find_request_timeout: Duration::from_millis(10000),
+            handoff_request_timeout: Duration::from_millis(60000),
+            read_only: false,
+            migration_rate_limit: 0,
+            listen_addresses: Vec::new(),
+        }
+    }
+}
+
+impl ChordConfig {
+    /// Reads a configuration file like `config/node.toml`. Its keys are the field names, durations
+    /// are given in milliseconds, and fields it leaves out keep their defaults.
+    pub fn from_toml_path(path: &Path) -> Result<ChordConfig, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        ChordConfig::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn from_toml(text: &str) -> Result<ChordConfig, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut config = ChordConfig::default();
+        file.apply_to(&mut config)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that are allowed by their types but make no sense
+    pub fn validate(&self) -> Result<(), String> {
+        if self.key_space_bits < chord::CHORD_CIRCLE_BITS {
+            return Err(format!("The key space must have at least {} bits, got {}", chord::CHORD_CIRCLE_BITS, self.key_space_bits));
+        }
+        if self.dump_page_size == 0 {
+            return Err("A storage dump page must hold at least one key".to_string());
+        }
+        if self.stabilize_min_interval > self.stabilize_max_interval {
+            return Err("The minimum stabilize interval must not exceed the maximum".to_string());
+        }
+        if self.crawl_parallelism == 0 {
+            return Err("The crawl parallelism must be at least 1".to_string());
+        }
+        let request_timeouts = [self.join_request_timeout, self.lookup_request_timeout, self.store_request_timeout,
+            self.find_request_timeout, self.handoff_request_timeout];
+        if request_timeouts.iter().any(|timeout| *timeout == Duration::from_millis(0)) {
+            return Err("Request timeouts must not be 0".to_string());
+        }
+        if !self.hop_alert_factor.is_finite() || self.hop_alert_factor < 0.0 {
+            return Err(format!("The hop alert factor must be a non-negative number, got {}", self.hop_alert_factor));
+        }
+        Ok(())
+    }
+}
+
+/// The keys of a configuration file, see `ChordConfig::from_toml_path`
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    verify_join: Option<bool>,
+    log_messages_on_error_only: Option<bool>,
+    successor_selection: Option<String>,
+    value_encryption_key: Option<String>,
+    max_send_threads: Option<usize>,
+    observer: Option<bool>,
+    batch_stabilize: Option<bool>,
+    key_space_bits: Option<usize>,
+    min_protocol_version: Option<u16>,
+    max_value_size: Option<usize>,
+    chunk_size: Option<usize>,
+    storage_high_water_mark: Option<usize>,
+    tcp_nodelay: Option<bool>,
+    tcp_keepalive: Option<u64>,
+    circuit_breaker_threshold: Option<usize>,
+    circuit_breaker_cooldown: Option<u64>,
+    predecessor_failure_threshold: Option<usize>,
+    discover: Option<bool>,
+    stabilize_min_interval: Option<u64>,
+    stabilize_max_interval: Option<u64>,
+    reserved_ranges: Option<Vec<String>>,
+    dump_page_size: Option<usize>,
+    ring_probes: Option<Vec<String>>,
+    node_id: Option<String>,
+    node_name: Option<String>,
+    join_timeout: Option<u64>,
+    tombstone_grace_period: Option<u64>,
+    join_pings: Option<usize>,
+    routing_state_file: Option<PathBuf>,
+    write_warmup: Option<u64>,
+    hop_alert_factor: Option<f64>,
+    migration_strategy: Option<String>,
+    crawl_parallelism: Option<usize>,
+    value_compression: Option<String>,
+    value_compression_threshold: Option<usize>,
+    max_inflight_lookups: Option<usize>,
+    headless: Option<bool>,
+    join_request_timeout: Option<u64>,
+    lookup_request_timeout: Option<u64>,
+    store_request_timeout: Option<u64>,
+    find_request_timeout: Option<u64>,
+    handoff_request_timeout: Option<u64>,
+    read_only: Option<bool>,
+    migration_rate_limit: Option<usize>,
+    listen_addresses: Option<Vec<String>>,
+}
+
+/// Sets the fields of the config to the values the file gives for them, as they are
+macro_rules! apply_values {
+    ($file:ident, $config:ident, $($field:ident),*) => {
+        $(if let Some(value) = $file.$field {
+            $config.$field = value;
+        })*
+    };
+}
+
+/// Sets the duration fields of the config to the milliseconds the file gives for them
+macro_rules! apply_millis {
+    ($file:ident, $config:ident, $($field:ident),*) => {
+        $(if let Some(millis) = $file.$field {
+            $config.$field = Duration::from_millis(millis);
+        })*
+    };
+}
+
+impl ConfigFile {
+    fn apply_to(self, config: &mut ChordConfig) -> Result<(), String> {
+        let file = self;
+        apply_values!(file, config, verify_join, log_messages_on_error_only, max_send_threads, observer, batch_stabilize,
+            key_space_bits, min_protocol_version, max_value_size, chunk_size, storage_high_water_mark, tcp_nodelay,
+            circuit_breaker_threshold, predecessor_failure_threshold, discover, dump_page_size, join_pings,
+            hop_alert_factor, crawl_parallelism, value_compression_threshold, max_inflight_lookups, headless, read_only, migration_rate_limit);
+        apply_millis!(file, config, circuit_breaker_cooldown, stabilize_min_interval, stabilize_max_interval,
+            tombstone_grace_period, write_warmup, join_request_timeout, lookup_request_timeout, store_request_timeout,
+            find_request_timeout, handoff_request_timeout);
+        if let Some(selection) = file.successor_selection {
+            config.successor_selection = selection.parse()?;
+        }
+        if let Some(key) = file.value_encryption_key {
+            config.value_encryption_key = Some(key);
+        }
+        if let Some(millis) = file.tcp_keepalive {
+            config.tcp_keepalive = Some(Duration::from_millis(millis));
+        }
+        if let Some(ranges) = file.reserved_ranges {
+            config.reserved_ranges = ranges.iter()
+                .map(|range| range.parse())
+                .collect::<Result<Vec<KeyRange>, String>>()?;
+        }
+        if let Some(probes) = file.ring_probes {
+            config.ring_probes = probes.iter()
+                .map(|probe| probe.parse().map_err(|_| format!("Expected an address like 127.0.0.1:10000, got '{}'", probe)))
+                .collect::<Result<Vec<SocketAddr>, String>>()?;
+        }
+        if let Some(id) = file.node_id {
+            config.node_id = Some(parse_ring_id(&id)?);
+        }
+        if let Some(name) = file.node_name {
+            config.identity = NodeIdentity::Custom(name);
+        }
+        if let Some(millis) = file.join_timeout {
+            config.join_timeout = Some(Duration::from_millis(millis));
+        }
+        if let Some(path) = file.routing_state_file {
+            config.routing_state_file = Some(path);
+        }
+        if let Some(strategy) = file.migration_strategy {
+            config.migration_strategy = strategy.parse()?;
+        }
+        if let Some(compression) = file.value_compression {
+            config.value_compression = compression.parse()?;
+        }
+        if let Some(addresses) = file.listen_addresses {
+            config.listen_addresses = addresses.iter()
+                .map(|address| address.parse().map_err(|_| format!("Expected an address like 0.0.0.0 or ::, got '{}'", address)))
+                .collect::<Result<Vec<IpAddr>, String>>()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sample_file_is_read() {
+        let config = ChordConfig::from_toml_path(Path::new("config/node.toml")).unwrap();
+        assert_eq!(config.stabilize_max_interval, Duration::from_millis(8000));
+        assert_eq!(config.successor_selection, SuccessorSelection::FirstLive);
+    }
+
+    #[test]
+    fn keys_left_out_keep_their_defaults() {
+        let config = ChordConfig::from_toml("node_name = \"node-a\"\ntcp_keepalive = 30000\nring_probes = [\"127.0.0.1:10000\"]").unwrap();
+        assert_eq!(config.identity, NodeIdentity::Custom("node-a".to_string()));
+        assert_eq!(config.tcp_keepalive, Some(Duration::from_millis(30000)));
+        assert_eq!(config.ring_probes, vec!["127.0.0.1:10000".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.max_send_threads, ChordConfig::default().max_send_threads);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(ChordConfig::from_toml("max_sned_threads = 4").is_err());
+        assert!(ChordConfig::from_toml("max_send_threads = -1").is_err());
+        assert!(ChordConfig::from_toml("max_send_threads = \"4\"").is_err());
+        assert!(ChordConfig::from_toml("successor_selection = \"random\"").is_err());
+        assert!(ChordConfig::from_toml("[network]\nmax_send_threads = 4").is_err());
+    }
+
+    #[test]
+    fn the_hop_alert_factor_has_to_be_a_number() {
+        assert_eq!(ChordConfig::from_toml("hop_alert_factor = 3").unwrap().hop_alert_factor, 3.0);
+        assert!(ChordConfig::from_toml("hop_alert_factor = nan").is_err());
+        assert!(ChordConfig::from_toml("hop_alert_factor = inf").is_err());
+        assert!(ChordConfig::from_toml("hop_alert_factor = -1.0").is_err());
+        let config = ChordConfig { hop_alert_factor: std::f64::NAN, ..ChordConfig::default() };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::str;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use net2::UdpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixUdpBuilderExt;
+
+use super::chord;
+use super::logging;
+
+/// Administratively scoped multicast group nodes started with `--discover` listen on for queries
+pub const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 67, 72);
+
+pub const DISCOVERY_PORT: u16 = 6772;
+
+/// Time to collect answers after sending a query
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const DISCOVERY_QUERY: &[u8] = b"hll-rust-chord discover";
+
+const DISCOVERY_ANSWER_PREFIX: &str = "hll-rust-chord node ";
+
+/// Answers discovery queries with `node_ip_addr` until the process exits
+pub fn advertise(node_ip_addr: SocketAddr) -> io::Result<JoinHandle<()>> {
+    let builder = UdpBuilder::new_v4()?;
+    builder.reuse_address(true)?;
+    // Lets several nodes on one machine answer
+    #[cfg(unix)]
+    builder.reuse_port(true)?;
+    let socket = builder.bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+    socket.join_multicast_v4(&DISCOVERY_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    info!(target: chord::LOG_NETWORK, "Advertising {} in the local network", node_ip_addr);
+
+    let answer = format!("{}{}", DISCOVERY_ANSWER_PREFIX, node_ip_addr);
+    logging::spawn("Discovery", move || {
+        let mut buf = [0; 64];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, peer)) if &buf[..len] == DISCOVERY_QUERY => {
+                    debug!(target: chord::LOG_NETWORK, "Answering discovery query of {}", peer);
+                    if let Err(e) = socket.send_to(answer.as_bytes(), peer) {
+                        warn!(target: chord::LOG_NETWORK, "Unable to answer discovery query of {}: {}", peer, e);
+                    }
+                }
+                Ok((_, peer)) => debug!(target: chord::LOG_NETWORK, "Ignoring unknown discovery datagram from {}", peer),
+                Err(e) => warn!(target: chord::LOG_NETWORK, "Unable to receive discovery query: {}", e),
+            }
+        }
+    })
+}
+
+/// Asks the local network for ring members and returns the addresses of all that answered within `timeout`
+pub fn discover(timeout: Duration) -> io::Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.send_to(DISCOVERY_QUERY, (DISCOVERY_GROUP, DISCOVERY_PORT))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut found = HashSet::new();
+    let mut buf = [0; 64];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, peer)) => {
+                let addr = str::from_utf8(&buf[..len]).ok()
+                    .filter(|answer| answer.starts_with(DISCOVERY_ANSWER_PREFIX))
+                    .and_then(|answer| answer[DISCOVERY_ANSWER_PREFIX.len()..].parse::<SocketAddr>().ok());
+                match addr {
+                    Some(addr) => {
+                        debug!(target: chord::LOG_NETWORK, "Discovered node {}", addr);
+                        found.insert(addr);
+                    }
+                    None => debug!(target: chord::LOG_NETWORK, "Ignoring invalid discovery answer from {}", peer),
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(found.into_iter().collect())
+}
@@ -0,0 +1,91 @@
+use crypto::aes::{self, KeySize};
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+
+const NONCE_SIZE: usize = 16;
+
+const TAG_SIZE: usize = 32;
+
+/// Encrypts `plaintext` with AES-256-CTR and authenticates it with HMAC-SHA256.
+/// Returns `hex(nonce | ciphertext | tag)`, so that the sealed value is text for any plaintext.
+pub fn encrypt_value(passphrase: &str, plaintext: &[u8]) -> Vec<u8> {
+    let nonce: [u8; NONCE_SIZE] = rand::random();
+    let mut ciphertext = vec![0; plaintext.len()];
+    aes::ctr(KeySize::KeySize256, &derive_key("enc", passphrase), &nonce)
+        .process(plaintext, &mut ciphertext);
+
+    let mut sealed = nonce.to_vec();
+    sealed.append(&mut ciphertext);
+    let tag = create_tag(passphrase, &sealed);
+    sealed.extend_from_slice(tag.code());
+    to_hex(&sealed).into_bytes()
+}
+
+/// Reverses `encrypt_value`. Returns `None` if the value was not encrypted with `passphrase`.
+pub fn decrypt_value(passphrase: &str, sealed_hex: &[u8]) -> Option<Vec<u8>> {
+    let sealed = from_hex(sealed_hex)?;
+    if sealed.len() < NONCE_SIZE + TAG_SIZE {
+        return None;
+    }
+    let (authenticated, tag) = sealed.split_at(sealed.len() - TAG_SIZE);
+    if create_tag(passphrase, authenticated) != MacResult::new(tag) {
+        return None;
+    }
+
+    let (nonce, ciphertext) = authenticated.split_at(NONCE_SIZE);
+    let mut plaintext = vec![0; ciphertext.len()];
+    aes::ctr(KeySize::KeySize256, &derive_key("enc", passphrase), nonce)
+        .process(ciphertext, &mut plaintext);
+    Some(plaintext)
+}
+
+fn derive_key(purpose: &str, passphrase: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input_str(purpose);
+    hasher.input_str(passphrase);
+    let mut key = [0; 32];
+    hasher.result(&mut key);
+    key
+}
+
+fn create_tag(passphrase: &str, data: &[u8]) -> MacResult {
+    let mut hmac = Hmac::new(Sha256::new(), &derive_key("mac", passphrase));
+    hmac.input(data);
+    hmac.result()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    hex.chunks(2)
+        .map(|digits| match digits {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_which_are_no_text_survive_the_round_trip() {
+        let plaintext = [0xff, 0x00, 0xc3, 0x28];
+        let sealed = encrypt_value("secret", &plaintext);
+        assert!(std::str::from_utf8(&sealed).is_ok());
+        assert_eq!(decrypt_value("secret", &sealed), Some(plaintext.to_vec()));
+    }
+
+    #[test]
+    fn values_sealed_with_another_passphrase_are_not_opened() {
+        let sealed = encrypt_value("secret", b"value");
+        assert_eq!(decrypt_value("other", &sealed), None);
+        assert_eq!(decrypt_value("secret", &sealed[1..]), None);
+        assert_eq!(decrypt_value("secret", b"value"), None);
+    }
+}
@@ -7,8 +7,8 @@ use super::node::OtherNode;
 // Represents a single finger table entry
 #[derive(Clone)]
 pub struct FingerEntry {
+    // Start of the finger, see `finger_start`
     id: BigInt,
-    // ID hash of (n + 2^i) mod (2^m)
     node: OtherNode,
 }
 
@@ -40,7 +40,7 @@ impl FingerTable {
     pub fn new_first(parent_node_id: BigInt, successor: OtherNode) -> FingerTable {
         let mut entries: Vec<FingerEntry> = Vec::with_capacity(chord::FINGERTABLE_SIZE);
         entries.push(FingerEntry::new(
-            get_finger_id(&parent_node_id, 0),
+            finger_start(&parent_node_id, 1),
             successor,
         ));
         FingerTable { parent_node_id, entries }
@@ -62,7 +62,7 @@ impl FingerTable {
     pub fn set_successor(&mut self, successor: OtherNode) {
         if self.entries.is_empty() {
             self.entries.push(FingerEntry::new(
-                get_finger_id(&self.parent_node_id, 0),
+                finger_start(&self.parent_node_id, 1),
                 successor,
             ));
         } else {
@@ -77,14 +77,64 @@ impl FingerTable {
     pub fn length(&self) -> usize {
         self.entries.len()
     }
+
+    pub fn get_nodes(&self) -> Vec<OtherNode> {
+        self.entries.iter().map(|entry| entry.node.clone()).collect()
+    }
+
+    /// Fills the missing fingers with the best guesses among `candidates`, i.e. for each finger
+    /// the candidate following its start most closely. `fix_fingers` corrects them later on.
+    pub fn seed(&mut self, candidates: &[OtherNode]) {
+        if candidates.is_empty() {
+            return;
+        }
+        for index in self.entries.len()..chord::FINGERTABLE_SIZE {
+            let start = finger_start(&self.parent_node_id, index + 1);
+            let node = candidates.iter()
+                .min_by_key(|candidate| chord::chord_abs(candidate.get_id(), &start))
+                .unwrap()
+                .clone();
+            self.entries.push(FingerEntry::new(start, node));
+        }
+    }
 }
 
-pub fn get_finger_id(n: &BigInt, exponent: usize) -> BigInt {
+/// Start of the `k`-th finger of node `n` as in the chord paper: (n + 2^(k-1)) mod 2^m,
+/// with `k` from 1 to `chord::FINGERTABLE_SIZE`. The finger table stores finger `k` at index `k - 1`,
+/// so index 0 is the successor.
+pub fn finger_start(n: &BigInt, k: usize) -> BigInt {
     // Get the offset
     let two: BigInt = 2.to_bigint().unwrap();
-    let offset: BigInt = pow(two.clone(), exponent);
+    let offset: BigInt = pow(two.clone(), k - 1);
 
-    // Sum
-    n + offset
+    chord::x_modulo_ring_size(n + offset)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finger_start_is_the_node_id_plus_a_power_of_two_in_the_ring() {
+        let ring_size = chord::CHORD_RING_SIZE.to_bigint().unwrap();
+        let ids = vec![BigInt::from(0), BigInt::from(1), &ring_size / 2, &ring_size - 2, &ring_size - 1];
+        for n in &ids {
+            for k in 1..=chord::FINGERTABLE_SIZE {
+                let start = finger_start(n, k);
+                assert!(start >= BigInt::from(0) && start < ring_size, "finger_start({}, {}) = {}", n, k, start);
+                assert_eq!(chord::chord_abs(&start, n), pow(2.to_bigint().unwrap(), k - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn finger_start_wraps_around_zero() {
+        let ring_size = chord::CHORD_RING_SIZE.to_bigint().unwrap();
+        let last = &ring_size - 1;
+        assert_eq!(finger_start(&last, 1), BigInt::from(0));
+        assert_eq!(finger_start(&last, 2), BigInt::from(1));
+        assert_eq!(finger_start(&BigInt::from(0), chord::FINGERTABLE_SIZE), &ring_size / 2);
+        assert_eq!(finger_start(&last, chord::FINGERTABLE_SIZE), &ring_size / 2 - 1);
+    }
+}
@@ -1,14 +1,23 @@
 use std::{error::Error};
 use std::io::stdin;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Stream};
+use num_bigint::BigInt;
 
 use super::chord;
-use super::network;
-use super::node::OtherNode;
+use super::config::ChordConfig;
+use super::encryption;
+use super::logging;
+use super::network::Network;
+use super::node::{Node, OtherNode, OwnershipEvent};
 use super::protocols::*;
 use super::storage;
+use super::storage::DHTEntry;
 
-pub fn perform_user_interaction(node_as_other: OtherNode) -> Result<(), Box<Error>> {
+pub fn perform_user_interaction(arc: Arc<Mutex<Node>>, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let network = arc.lock().unwrap().get_network().clone();
     let info =
         "\n \nHello there! What do you want to do?\n \n\
         1 - Store a key/value pair in the Chord network\n\
@@ -17,7 +26,24 @@ pub fn perform_user_interaction(node_as_other: OtherNode) -> Result<(), Box<Erro
         4 - Kill a Chord network peer\n\n\
         5 - Cancel interaction\n\
         6 - Terminate Node\n\n\
-        Choose 1, 2, 3, 4, 5 or 6 and press Enter!";
+        7 - Dump the storage of a Chord network peer\n\
+        8 - Store a key/value pair only if the key is not present yet\n\
+        9 - Pause or resume the maintenance of a Chord network peer\n\
+        10 - Replace the value of a key only if it still has an expected value\n\
+        11 - Stream the value for a given key in the Chord network\n\
+        12 - Watch which keys of a range this node gains or loses\n\
+        13 - Delete all keys of a namespace in the Chord network\n\
+        14 - Stabilize this node now\n\
+        15 - Trace the nodes a lookup for an id visits\n\
+        16 - Show how many keys each node of the Chord network owns\n\
+        17 - Show the predecessors of this node\n\
+        18 - Store several key/value pairs all-or-nothing\n\
+        19 - Store, find and delete a canary key to test the ring\n\
+        20 - List the live nodes of the Chord network\n\
+        21 - Switch a Chord network peer to read-only or back\n\
+        22 - Store a value under an address derived from its content\n\
+        23 - Find a value by its content address\n\n\
+        Choose 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22 or 23 and press Enter!";
     print!("{}[2J", 27 as char);
     info!("{}", info);
 
@@ -26,30 +52,98 @@ pub fn perform_user_interaction(node_as_other: OtherNode) -> Result<(), Box<Erro
         stdin().read_line(buffer).unwrap();
         match buffer.trim_right() {
             "1" => {
-                store(node_as_other.clone()).expect("store failed");
+                store(&network, node_as_other.clone(), config).expect("store failed");
                 break;
             }
             "2" => {
-                find(node_as_other.clone()).expect("find failed");
+                find(&network, node_as_other.clone(), config).expect("find failed");
                 break;
             }
             "3" => {
-                delete(node_as_other.clone()).expect("delete failed");
+                delete(&network, node_as_other.clone(), config).expect("delete failed");
                 break;
             }
             "4" => {
-                kill().expect("kill failed");
+                kill(&network).expect("kill failed");
                 break;
             }
             "5" => {
                 break;
             }
             "6" => {
-                kill_node(*node_as_other.get_ip_addr());
+                kill_node(&network, *node_as_other.get_ip_addr());
+                break;
+            }
+            "7" => {
+                dump_storage(&network, node_as_other.clone()).expect("dump_storage failed");
+                break;
+            }
+            "8" => {
+                store_if_absent(&network, node_as_other.clone(), config).expect("store_if_absent failed");
+                break;
+            }
+            "9" => {
+                set_maintenance_paused(&network, node_as_other.clone()).expect("set_maintenance_paused failed");
+                break;
+            }
+            "10" => {
+                compare_and_swap(&network, node_as_other.clone(), config).expect("compare_and_swap failed");
+                break;
+            }
+            "11" => {
+                find_stream(arc.clone()).expect("find_stream failed");
+                break;
+            }
+            "12" => {
+                watch_range(arc.clone()).expect("watch_range failed");
+                break;
+            }
+            "13" => {
+                delete_namespace(arc.clone()).expect("delete_namespace failed");
+                break;
+            }
+            "14" => {
+                arc.lock().unwrap().trigger_stabilize();
+                break;
+            }
+            "15" => {
+                trace_lookup(arc.clone()).expect("trace_lookup failed");
+                break;
+            }
+            "16" => {
+                key_distribution(arc.clone()).expect("key_distribution failed");
+                break;
+            }
+            "17" => {
+                predecessor_chain(arc.clone()).expect("predecessor_chain failed");
+                break;
+            }
+            "18" => {
+                store_transaction(&network, node_as_other.clone(), config).expect("store_transaction failed");
+                break;
+            }
+            "19" => {
+                self_test(arc.clone()).expect("self_test failed");
+                break;
+            }
+            "20" => {
+                list_members(arc.clone()).expect("list_members failed");
+                break;
+            }
+            "21" => {
+                set_read_only(&network, node_as_other.clone()).expect("set_read_only failed");
+                break;
+            }
+            "22" => {
+                store_content(arc.clone()).expect("store_content failed");
+                break;
+            }
+            "23" => {
+                find_content(arc.clone(), config).expect("find_content failed");
                 break;
             }
             _ => {
-                println!("Please choose an valid option [1,2,3,4,5]");
+                println!("Please choose an valid option [1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23]");
             }
         };
     }
@@ -57,7 +151,72 @@ pub fn perform_user_interaction(node_as_other: OtherNode) -> Result<(), Box<Erro
     Ok(())
 }
 
-fn store(node_as_other: OtherNode) -> Result<(), Box<Error>> {
+fn store(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let (key, value) = read_key_value()?;
+    store_key_value(network, key, seal_value(config, value), node_as_other, config);
+    Ok(())
+}
+
+fn store_if_absent(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let (key, value) = read_key_value()?;
+    store_key_value_if_absent(network, key, seal_value(config, value), node_as_other, config.key_space_bits);
+    Ok(())
+}
+
+/// Stores all pairs or none of them. The keys have to be owned by the same node,
+/// e.g. through a reserved key range, otherwise the transaction is rejected.
+fn store_transaction(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let mut ops = Vec::new();
+    loop {
+        println!("Enter a KEY=VALUE pair to store (empty to send the {} pairs entered):", ops.len());
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        let line = buffer.trim_right();
+        if line.is_empty() {
+            break;
+        }
+        match line.find('=') {
+            Some(separator) if separator > 0 => {
+                let data = storage::make_hashed_key_value_pair(line[..separator].to_string(),
+                                                               seal_value(config, line[separator + 1..].to_string()),
+                                                               config.key_space_bits);
+                ops.push(DHTOp::Store { data });
+            }
+            _ => println!("Please enter KEY=VALUE."),
+        }
+    }
+    if ops.is_empty() {
+        return Ok(());
+    }
+    let req = Request::DHTTransaction { ops };
+    info!("Trying to apply transaction {:?}", req.clone());
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+    Ok(())
+}
+
+fn compare_and_swap(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let (key, value) = read_key_value()?;
+    println!("Enter the value key {} is expected to have now (empty if it must be absent):", key.clone());
+    let buffer = &mut String::new();
+    stdin().read_line(buffer)?;
+    let expected = match buffer.trim_right() {
+        "" => None,
+        v => Some(v.to_owned()),
+    };
+    compare_and_swap_key_value(network, key, expected, seal_value(config, value), node_as_other, config.key_space_bits);
+    Ok(())
+}
+
+/// Encrypts the value if a value encryption key is configured
+fn seal_value(config: &ChordConfig, value: String) -> String {
+    match &config.value_encryption_key {
+        // Sealed values are hex digits
+        Some(passphrase) => String::from_utf8(encryption::encrypt_value(passphrase, value.as_bytes())).unwrap(),
+        None => value,
+    }
+}
+
+fn read_key_value() -> Result<(String, String), Box<Error>> {
     loop {
         println!("Enter the string that should be used as a KEY\n\
         (p.e.: A name):");
@@ -69,7 +228,6 @@ fn store(node_as_other: OtherNode) -> Result<(), Box<Error>> {
             }
             k => {
                 let key = k.to_string();
-                let value;
                 loop {
                     println!("Enter the string that should be stored as value for key {} \n\
                     (p.e.: A phone number)", key.clone());
@@ -80,12 +238,27 @@ fn store(node_as_other: OtherNode) -> Result<(), Box<Error>> {
                             println!("Please Enter a valid value.");
                         }
                         v => {
-                            value = v.to_owned();
-                            break;
+                            return Ok((key, v.to_owned()));
                         }
                     }
                 }
-                store_key_value(key, value, node_as_other);
+            }
+        }
+    }
+}
+
+fn find(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
+    loop {
+        println!("Enter a Key to look for in the network:");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right() {
+            "" => {
+                println!("Please Enter a valid Key name.");
+            }
+            k => {
+                let key = k.to_string();
+                find_key(network, key, node_as_other, config.key_space_bits);
                 break;
             }
         }
@@ -93,9 +266,9 @@ fn store(node_as_other: OtherNode) -> Result<(), Box<Error>> {
     Ok(())
 }
 
-fn find(node_as_other: OtherNode) -> Result<(), Box<Error>> {
+fn find_stream(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
     loop {
-        println!("Enter a Key to look for in the network:");
+        println!("Enter a Key to stream the value of:");
         let buffer = &mut String::new();
         stdin().read_line(buffer)?;
         match buffer.trim_right() {
@@ -104,7 +277,17 @@ fn find(node_as_other: OtherNode) -> Result<(), Box<Error>> {
             }
             k => {
                 let key = k.to_string();
-                find_key(key, node_as_other);
+                let stream = arc.lock().unwrap().find_key_stream(&key);
+                // Consumed in the background, so the menu does not wait for the transfer
+                logging::spawn("ValueStream", move || {
+                    let mut received = 0;
+                    for chunk in stream.wait() {
+                        let chunk = chunk.unwrap();
+                        received += chunk.len();
+                        info!("Received {} bytes of the value for key '{}' so far", received, key);
+                    }
+                    info!("Stream of key '{}' ended after {} bytes", key, received);
+                })?;
                 break;
             }
         }
@@ -112,7 +295,159 @@ fn find(node_as_other: OtherNode) -> Result<(), Box<Error>> {
     Ok(())
 }
 
-fn delete(node_as_other: OtherNode) -> Result<(), Box<Error>> {
+fn delete_namespace(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    loop {
+        println!("Enter the namespace to delete all keys of (keys are written as NAMESPACE{}KEY):", storage::NAMESPACE_SEPARATOR);
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right() {
+            "" => {
+                println!("Please Enter a valid namespace.");
+            }
+            ns => {
+                let namespace = ns.to_string();
+                let deletion = arc.lock().unwrap().delete_namespace(&namespace);
+                // Awaited in the background, so the menu does not wait for the walk around the ring
+                logging::spawn("DeleteNamespace", move || {
+                    if let Ok(deleted) = deletion.wait() {
+                        info!("Deleted {} keys of namespace '{}'", deleted, namespace);
+                    }
+                })?;
+                break;
+            }
+        }
+    };
+    Ok(())
+}
+
+fn watch_range(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    let start = read_ring_id("Enter the first id of the range to watch (hex, as in the tables):")?;
+    let end = read_ring_id("Enter the id after the last id of the range to watch (equal to the first for the whole ring):")?;
+    let watch = arc.lock().unwrap().watch_range(start, end);
+    // Logged in the background for as long as the node runs
+    logging::spawn("RangeWatch", move || {
+        for event in watch.wait() {
+            match event.unwrap() {
+                OwnershipEvent::Gained { start, end } =>
+                    info!("Now responsible for the watched keys {}..{}", chord::short_id(&start), chord::short_id(&end)),
+                OwnershipEvent::Lost { start, end } =>
+                    info!("No longer responsible for the watched keys {}..{}", chord::short_id(&start), chord::short_id(&end)),
+            }
+        }
+    })?;
+    Ok(())
+}
+
+fn self_test(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    // The test waits for the ring, so it runs on a copy and the node goes on meanwhile
+    let node = arc.lock().unwrap().clone();
+    logging::spawn("SelfTest", move || {
+        node.self_test();
+    })?;
+    Ok(())
+}
+
+fn trace_lookup(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    let id = read_ring_id("Enter the id to trace the lookup of (hex, as in the tables):")?;
+    // The trace waits for every hop, so it runs on a copy and the node goes on meanwhile
+    let node = arc.lock().unwrap().clone();
+    logging::spawn("TraceLookup", move || {
+        let trace = node.trace_lookup(&id);
+        let path = trace.iter().map(|hop| format!("#{}", chord::short_id(hop.get_id()))).collect::<Vec<String>>().join(" -> ");
+        info!("Lookup of id {} takes {} hops: {}", chord::short_id(&id), trace.len() - 1, path);
+    })?;
+    Ok(())
+}
+
+fn key_distribution(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    // Asks every node of the ring, so it runs on a copy like the trace
+    let node = arc.lock().unwrap().clone();
+    logging::spawn("KeyDistribution", move || {
+        let mut distribution: Vec<(BigInt, usize)> = node.key_distribution().into_iter().collect();
+        distribution.sort();
+        let total: usize = distribution.iter().map(|(_, count)| count).sum();
+        info!("{} keys on {} nodes:", total, distribution.len());
+        for (id, count) in distribution {
+            info!("  Node #{} owns {} keys", chord::short_id(&id), count);
+        }
+    })?;
+    Ok(())
+}
+
+fn list_members(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    // Asks every node of the ring, so it runs on a copy like the trace
+    let node = arc.lock().unwrap().clone();
+    logging::spawn("ListMembers", move || {
+        let member_list = node.list_members();
+        if member_list.complete {
+            info!("{} live nodes:", member_list.members.len());
+        } else {
+            info!("{} live nodes found, the list is partial:", member_list.members.len());
+        }
+        for member in member_list.members {
+            info!("  Node #{} at {}", chord::short_id(member.get_id()), member.get_ip_addr());
+        }
+    })?;
+    Ok(())
+}
+
+fn predecessor_chain(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    let depth = loop {
+        println!("Enter how many predecessors to show:");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right().parse::<usize>() {
+            Ok(depth) if depth > 0 => break depth,
+            _ => println!("Please enter a positive number."),
+        }
+    };
+    // Asks one predecessor after the other, so it runs on a copy like the trace
+    let node = arc.lock().unwrap().clone();
+    logging::spawn("PredecessorChain", move || {
+        let chain = node.predecessor_chain(depth);
+        let path = chain.iter().map(|pre| format!("#{}", chord::short_id(pre.get_id()))).collect::<Vec<String>>().join(" <- ");
+        info!("{} of {} predecessors of node #{}: {}", chain.len(), depth, chord::short_id(node.get_id()), path);
+    })?;
+    Ok(())
+}
+
+fn store_content(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+    println!("Enter the VALUE to store under its content address:");
+    let buffer = &mut String::new();
+    stdin().read_line(buffer)?;
+    let address = arc.lock().unwrap().store_content(buffer.trim_right().as_bytes());
+    info!("Content address: {:x}", address);
+    Ok(())
+}
+
+fn find_content(arc: Arc<Mutex<Node>>, config: &ChordConfig) -> Result<(), Box<Error>> {
+    let key_space = BigInt::from(1) << config.key_space_bits;
+    let address = loop {
+        println!("Enter the content address to look for (hex):");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match BigInt::parse_bytes(buffer.trim_right().as_bytes(), 16) {
+            Some(ref address) if *address >= BigInt::from(0) && *address < key_space => break address.clone(),
+            _ => println!("Please enter an address between 0 and {:x}.", &key_space - 1),
+        }
+    };
+    arc.lock().unwrap().find_content(&address);
+    Ok(())
+}
+
+fn read_ring_id(prompt: &str) -> Result<BigInt, Box<Error>> {
+    loop {
+        println!("{}", prompt);
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match BigInt::parse_bytes(buffer.trim_right().as_bytes(), 16) {
+            Some(ref id) if *id >= BigInt::from(0) && *id < BigInt::from(chord::CHORD_RING_SIZE) => return Ok(id.clone()),
+            _ => println!("Please enter an id between 0 and {:x}.", chord::CHORD_RING_SIZE - 1),
+        }
+    }
+}
+
+fn delete(network: &Network, node_as_other: OtherNode, config: &ChordConfig) -> Result<(), Box<Error>> {
     loop {
         println!("Enter a Key to look for in the network:");
         let buffer = &mut String::new();
@@ -123,7 +458,7 @@ fn delete(node_as_other: OtherNode) -> Result<(), Box<Error>> {
             }
             k => {
                 let key = k.to_string();
-                delete_key(key, node_as_other);
+                delete_key(network, key, node_as_other, config.key_space_bits);
                 break;
             }
         }
@@ -131,7 +466,7 @@ fn delete(node_as_other: OtherNode) -> Result<(), Box<Error>> {
     Ok(())
 }
 
-fn kill() -> Result<(), Box<Error>> {
+fn kill(network: &Network) -> Result<(), Box<Error>> {
     loop {
         println!("Enter <IP>:<Port> (i.e. 127.0.0.1:10000) of a to be killed chord network peer:");
         let buffer = &mut String::new();
@@ -143,7 +478,7 @@ fn kill() -> Result<(), Box<Error>> {
             k => {
                 let ip_string = k.to_string();
                 let target_ip = ip_string.parse::<SocketAddr>().unwrap();
-                kill_node(target_ip);
+                kill_node(network, target_ip);
                 break;
             }
         }
@@ -151,24 +486,125 @@ fn kill() -> Result<(), Box<Error>> {
     Ok(())
 }
 
-fn kill_node(target_ip: SocketAddr) {
-    network::send_kill(target_ip);
+fn dump_storage(network: &Network, node_as_other: OtherNode) -> Result<(), Box<Error>> {
+    loop {
+        println!("Enter <IP>:<Port> (i.e. 127.0.0.1:10000) of the chord network peer to dump the storage of:");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right().parse::<SocketAddr>() {
+            Ok(target_ip) => {
+                println!("Only dump the key ids? [y/N]");
+                let buffer2 = &mut String::new();
+                stdin().read_line(buffer2)?;
+                let keys_only = buffer2.trim_right() == "y";
+                let req = Request::DumpStorage { keys_only, cursor: None };
+                network.send_request(node_as_other, target_ip, req);
+                break;
+            }
+            Err(_) => {
+                println!("Please enter a valid SocketAddr.");
+            }
+        }
+    };
+    Ok(())
+}
+
+fn set_maintenance_paused(network: &Network, node_as_other: OtherNode) -> Result<(), Box<Error>> {
+    loop {
+        println!("Enter <IP>:<Port> (i.e. 127.0.0.1:10000) of the chord network peer to pause or resume:");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right().parse::<SocketAddr>() {
+            Ok(target_ip) => {
+                println!("Resume instead of pause the maintenance? [y/N]");
+                let buffer2 = &mut String::new();
+                stdin().read_line(buffer2)?;
+                let paused = buffer2.trim_right() != "y";
+                let req = Request::SetMaintenancePaused { paused };
+                network.send_request(node_as_other, target_ip, req);
+                break;
+            }
+            Err(_) => {
+                println!("Please enter a valid SocketAddr.");
+            }
+        }
+    };
+    Ok(())
+}
+
+fn set_read_only(network: &Network, node_as_other: OtherNode) -> Result<(), Box<Error>> {
+    loop {
+        println!("Enter <IP>:<Port> (i.e. 127.0.0.1:10000) of the chord network peer to switch:");
+        let buffer = &mut String::new();
+        stdin().read_line(buffer)?;
+        match buffer.trim_right().parse::<SocketAddr>() {
+            Ok(target_ip) => {
+                println!("Accept writes again instead of rejecting them? [y/N]");
+                let buffer2 = &mut String::new();
+                stdin().read_line(buffer2)?;
+                let read_only = buffer2.trim_right() != "y";
+                let req = Request::SetReadOnly { read_only };
+                network.send_request(node_as_other, target_ip, req);
+                break;
+            }
+            Err(_) => {
+                println!("Please enter a valid SocketAddr.");
+            }
+        }
+    };
+    Ok(())
 }
 
-fn store_key_value(key: String, value: String, node_as_other: OtherNode) {
-    let req = Request::DHTStoreKey { data: storage::make_hashed_key_value_pair(key, value) };
+fn kill_node(network: &Network, target_ip: SocketAddr) {
+    network.send_kill(target_ip);
+}
+
+fn store_key_value(network: &Network, key: String, value: String, node_as_other: OtherNode, config: &ChordConfig) {
+    if value.len() > config.chunk_size {
+        store_key_value_chunked(network, key, value, node_as_other, config);
+        return;
+    }
+    let req = Request::DHTStoreKey { data: storage::make_hashed_key_value_pair(key, value, config.key_space_bits), moved: false };
     info!("Trying to store data {:?}", req.clone());
-    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+}
+
+fn store_key_value_chunked(network: &Network, key: String, value: String, node_as_other: OtherNode, config: &ChordConfig) {
+    let chunks = storage::split_value_into_chunks(value.as_bytes(), config.chunk_size);
+    let total_chunks = chunks.len();
+    info!("Trying to store key '{}' in {} chunks", key, total_chunks);
+    for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+        let req = Request::DHTStoreChunk {
+            chunk_index,
+            total_chunks,
+            data: storage::make_hashed_key_value_pair(key.clone(), chunk, config.key_space_bits),
+        };
+        network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+    }
+}
+
+fn store_key_value_if_absent(network: &Network, key: String, value: String, node_as_other: OtherNode, key_space_bits: usize) {
+    let req = Request::DHTStoreIfAbsent { data: storage::make_hashed_key_value_pair(key, value, key_space_bits) };
+    info!("Trying to store data {:?} if absent", req.clone());
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+}
+
+fn compare_and_swap_key_value(network: &Network, key: String, expected: Option<String>, value: String, node_as_other: OtherNode, key_space_bits: usize) {
+    let (key_id, new) = storage::make_hashed_key_value_pair(key.clone(), value, key_space_bits);
+    let expected = expected.map(|expected| DHTEntry::new(key, expected));
+    let req = Request::DHTCompareAndSwap { key_id, expected, new };
+    info!("Trying to compare and swap {:?}", req.clone());
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
 
-fn find_key(key: String, node_as_other: OtherNode) {
-    let key_id = chord::create_id(&key);
-    let req = Request::DHTFindKey { key_id };
-    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+fn find_key(network: &Network, key: String, node_as_other: OtherNode, key_space_bits: usize) {
+    let key_id = chord::create_key_id(&key, key_space_bits);
+    let req = Request::DHTFindKey { key_id, lookup: LookupContext::new() };
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
 
-fn delete_key(key: String, node_as_other: OtherNode) {
-    let key_id = chord::create_id(&key);
+fn delete_key(network: &Network, key: String, node_as_other: OtherNode, key_space_bits: usize) {
+    let key_id = chord::create_key_id(&key, key_space_bits);
     let req = Request::DHTDeleteKey { key_id };
-    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+    network.send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
@@ -0,0 +1,185 @@
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+use chrono::Local;
+use log::Record;
+use log4rs::encode::{Encode, Write};
+use log4rs::file::{Deserialize, Deserializers};
+use num::ToPrimitive;
+use num_bigint::BigInt;
+
+use super::chord;
+use super::protocols::TraceContext;
+
+pub const LOG_CONFIG_FILE: &str = "config/log4rs.yaml";
+
+/// Used instead of `LOG_CONFIG_FILE` if the `JSON_LOGS_ENV` environment variable is set
+pub const JSON_LOG_CONFIG_FILE: &str = "config/log4rs-json.yaml";
+
+pub const JSON_LOGS_ENV: &str = "HLL_RUST_JSON_LOGS";
+
+thread_local! {
+    /// Id of the node the current thread works for, several nodes may run in one process
+    static NODE_ID: Cell<Option<usize>> = Cell::default();
+    /// Span the current thread handles a message in, see `TraceContext`
+    static TRACE: Cell<Option<TraceContext>> = Cell::default();
+}
+
+/// Adds `id` to all following JSON log records of the current thread
+pub fn set_node_id(id: &BigInt) {
+    NODE_ID.with(|node_id| node_id.set(id.to_usize()));
+}
+
+pub fn current_node_id() -> Option<usize> {
+    NODE_ID.with(|node_id| node_id.get())
+}
+
+/// Sets the node id of another thread, as returned by `current_node_id`
+pub fn inherit_node_id(id: Option<usize>) {
+    NODE_ID.with(|node_id| node_id.set(id));
+}
+
+/// Sets the span all following messages of the current thread are sent in and its log
+/// records are tagged with, `None` once the message it was started for is handled
+pub fn set_trace(trace: Option<TraceContext>) {
+    TRACE.with(|current| current.set(trace));
+}
+
+pub fn current_trace() -> Option<TraceContext> {
+    TRACE.with(|current| current.get())
+}
+
+/// Runs `f` with the node id and span of the calling thread, for threads started on behalf of a node
+fn with_node_id<F, T>(f: F) -> impl FnOnce() -> T + Send + 'static
+    where F: FnOnce() -> T + Send + 'static {
+    let id = current_node_id();
+    let trace = current_trace();
+    move || {
+        inherit_node_id(id);
+        set_trace(trace);
+        f()
+    }
+}
+
+/// Number of threads started with `spawn` that did not finish yet, of all nodes in the process
+static LIVE_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Decrements `LIVE_THREADS` when the thread ends, also if it panics
+struct LiveThread;
+
+impl Drop for LiveThread {
+    fn drop(&mut self) {
+        LIVE_THREADS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn live_threads() -> usize {
+    LIVE_THREADS.load(Ordering::SeqCst)
+}
+
+/// `role` followed by the short id of the calling thread's node, e.g. `Stabilize #c0ffee`
+pub fn thread_name(role: &str) -> String {
+    match node_id() {
+        Some(id) => format!("{} #{}", role, id),
+        None => role.to_string(),
+    }
+}
+
+/// Starts a thread named by `thread_name`, which runs `f` with the node id of the calling
+/// thread and counts as live until it ends
+pub fn spawn<F, T>(role: &str, f: F) -> io::Result<JoinHandle<T>>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    let name = thread_name(role);
+    let count = LIVE_THREADS.fetch_add(1, Ordering::SeqCst) + 1;
+    trace!("Starting thread {}, {} threads live", name, count);
+    let f = with_node_id(f);
+    let result = thread::Builder::new().name(name).spawn(move || {
+        let _live = LiveThread;
+        f()
+    });
+    if result.is_err() {
+        LIVE_THREADS.fetch_sub(1, Ordering::SeqCst);
+    }
+    result
+}
+
+fn node_id() -> Option<String> {
+    current_node_id().map(|id| chord::short_id(&BigInt::from(id)))
+}
+
+/// Initializes log4rs with the human readable or, if `JSON_LOGS_ENV` is set, the JSON lines configuration
+pub fn init() -> Result<(), log4rs::Error> {
+    let path = if env::var_os(JSON_LOGS_ENV).is_some() {
+        JSON_LOG_CONFIG_FILE
+    } else {
+        LOG_CONFIG_FILE
+    };
+    let mut deserializers = Deserializers::default();
+    deserializers.insert("chord_json", ChordJsonEncoderDeserializer);
+    log4rs::init_file(path, deserializers)
+}
+
+/// One log record as a line of JSON
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: String,
+    level: String,
+    node: Option<String>,
+    /// Trace and span id in hex, see `TraceContext`
+    trace: Option<String>,
+    span: Option<String>,
+    /// Name of the thread, i.e. its role like `Stabilize` or `Listen`
+    thread: Option<&'a str>,
+    target: &'a str,
+    message: String,
+    file: Option<&'a str>,
+    line: Option<u32>,
+}
+
+/// Encoder of the `chord_json` kind, writes every record as a line of JSON including the node id
+#[derive(Debug)]
+pub struct ChordJsonEncoder;
+
+impl Encode for ChordJsonEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let thread = thread::current();
+        let trace = current_trace();
+        let json_record = JsonRecord {
+            time: Local::now().to_rfc3339(),
+            level: record.level().to_string(),
+            node: node_id(),
+            trace: trace.map(|trace| format!("{:016x}", trace.get_trace_id())),
+            span: trace.map(|trace| format!("{:016x}", trace.get_span_id())),
+            thread: thread.name(),
+            target: record.target(),
+            message: record.args().to_string(),
+            file: record.file(),
+            line: record.line(),
+        };
+        serde_json::to_writer(&mut *w, &json_record)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChordJsonEncoderConfig {}
+
+struct ChordJsonEncoderDeserializer;
+
+impl Deserialize for ChordJsonEncoderDeserializer {
+    type Trait = dyn Encode;
+
+    type Config = ChordJsonEncoderConfig;
+
+    fn deserialize(&self,
+                   _config: ChordJsonEncoderConfig,
+                   _deserializers: &Deserializers) -> Result<Box<dyn Encode>, Box<dyn Error + Sync + Send>> {
+        Ok(Box::new(ChordJsonEncoder))
+    }
+}
@@ -1,31 +1,45 @@
 extern crate chrono;
 extern crate clap;
 extern crate crypto;
+extern crate flate2;
 extern crate futures;
 extern crate get_if_addrs;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
+extern crate net2;
 extern crate num;
 extern crate num_bigint;
 #[macro_use]
 extern crate prettytable;
+extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate signal_hook;
 extern crate tokio;
+extern crate toml;
 
+use std::fmt::Debug;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 mod input;
 mod print;
 
 mod chord;
+mod client;
+mod config;
+#[cfg(feature = "discovery")]
+mod discovery;
+mod encryption;
 mod fingertable;
+mod logging;
 mod node;
 mod storage;
 
@@ -34,7 +48,7 @@ mod protocols;
 
 fn main() {
     // Init logger
-    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
+    logging::init().unwrap();
     debug!("Booting...");
 
     // Find 'en0' interface on macOS, extract Ip4Addr and add it CLI help
@@ -54,10 +68,43 @@ fn main() {
     debug!("en0 interface IP4ADDR is: {}", local_ip4addr);
 
     // CLI incl. required arguments
-    let matches = App::new("hll_rust_chord")
+    let app = App::new("hll_rust_chord")
         .version("1.0")
         .author("Andreas Ellwanger, Timo Erdelt and Andreas Griesbeck")
         .about("High level languages: Rust - Group project (2018/2019)")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("client")
+                .about("Uses the DHT of an existing chord ring without joining it")
+                .arg(
+                    Arg::with_name("entry_point")
+                        .value_name("IP4ADDR:PORT")
+                        .help("Sets the node of the chord ring to send the request to")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("operation")
+                        .value_name("OPERATION")
+                        .possible_values(&["store", "append", "get", "delete", "pop", "owner", "export", "import"])
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .value_name("KEY")
+                        .help("Sets the key, or the snapshot file to export to or import from")
+                        .required(true)
+                        .index(3),
+                )
+                .arg(
+                    Arg::with_name("value")
+                        .value_name("VALUE")
+                        .help("Sets the value to store or append")
+                        .required_ifs(&[("operation", "store"), ("operation", "append")])
+                        .index(4),
+                ),
+        )
         .arg(
             Arg::with_name("ip4_addr")
                 .short("i")
@@ -73,7 +120,7 @@ fn main() {
                 .short("p")
                 .long("port")
                 .value_name("PORT")
-                .help("Sets the port to use")
+                .help("Sets the port to use (0 lets the OS pick a free port)")
                 .takes_value(true)
                 .required(true)
                 .index(2),
@@ -88,7 +135,361 @@ fn main() {
                 .required(false)
                 .index(3),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("batch_stabilize")
+                .long("batch-stabilize")
+                .help("Asks for the successor's predecessor and notifies it in one message"),
+        )
+        .arg(
+            Arg::with_name("verify_join")
+                .long("verify-join")
+                .help("Verifies the placement in the chord ring after joining"),
+        )
+        .arg(
+            Arg::with_name("no_tcp_nodelay")
+                .long("no-tcp-nodelay")
+                .help("Keeps Nagle's algorithm enabled on all sockets"),
+        )
+        .arg(
+            Arg::with_name("tcp_keepalive")
+                .long("tcp-keepalive")
+                .value_name("MILLIS")
+                .help("Enables TCP keep-alive with the given interval on all sockets")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("observer")
+                .long("observer")
+                .requires("entry_point")
+                .help("Joins the chord ring to route lookups only, without storing keys"),
+        )
+        .arg(
+            Arg::with_name("log_messages_on_error_only")
+                .long("log-messages-on-error-only")
+                .help("Logs full messages only if handling them fails"),
+        )
+        .arg(
+            Arg::with_name("successor_selection")
+                .long("successor-selection")
+                .value_name("STRATEGY")
+                .help("Sets which live successor is stabilized against")
+                .takes_value(true)
+                .possible_values(&["first-live", "round-robin"])
+                .default_value("first-live"),
+        )
+        .arg(
+            Arg::with_name("value_key")
+                .long("value-key")
+                .value_name("PASSPHRASE")
+                .help("Encrypts stored values with the passphrase, so that peers only hold ciphertext")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("key_space_bits")
+                .long("key-space-bits")
+                .value_name("BITS")
+                .help("Sets the size of the key id space in bits, keys collide less often in a larger space")
+                .takes_value(true)
+                .default_value("24"),
+        )
+        .arg(
+            Arg::with_name("max_value_size")
+                .long("max-value-size")
+                .value_name("BYTES")
+                .help("Rejects storing values larger than this as owner of their key (0 for unlimited)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("chunk_size")
+                .long("chunk-size")
+                .value_name("BYTES")
+                .help("Sets above which size values are stored and handed out in chunks of this size")
+                .takes_value(true)
+                .default_value("65536"),
+        )
+        .arg(
+            Arg::with_name("storage_high_water_mark")
+                .long("storage-high-water-mark")
+                .value_name("BYTES")
+                .help("Refuses new values while the keys and values stored take up more than this (0 for unlimited)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("min_protocol_version")
+                .long("min-protocol-version")
+                .value_name("VERSION")
+                .help("Ignores peers speaking an older protocol version")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("predecessor_failures")
+                .long("predecessor-failures")
+                .value_name("COUNT")
+                .help("Sets after how many failed checks in a row the predecessor counts as dead")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("stabilize_min_interval")
+                .long("stabilize-min-interval")
+                .value_name("MILLIS")
+                .help("Sets how often to stabilize at most, e.g. while nodes join or leave")
+                .takes_value(true)
+                .default_value("500"),
+        )
+        .arg(
+            Arg::with_name("stabilize_max_interval")
+                .long("stabilize-max-interval")
+                .value_name("MILLIS")
+                .help("Sets how often to stabilize at least, once the chord ring is quiet")
+                .takes_value(true)
+                .default_value("8000"),
+        )
+        .arg(
+            Arg::with_name("reserve_range")
+                .long("reserve-range")
+                .value_name("START..END")
+                .help("Owns the keys with ring ids from START to before END (hex), whichever node they hash to")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("observer"),
+        )
+        .arg(
+            Arg::with_name("dump_page_size")
+                .long("dump-page-size")
+                .value_name("KEYS")
+                .help("Sets the number of keys per page of a storage dump, so that large storages fit into messages")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .arg(
+            Arg::with_name("probe")
+                .long("probe")
+                .value_name("IP:PORT")
+                .help("Checks now and then whether this node is in another ring and merges the rings if so")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("node_id")
+                .long("node-id")
+                .value_name("ID")
+                .help("Sets the ring id (hex) instead of hashing the address, so that a restart under another address keeps it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("node_name")
+                .long("node-name")
+                .value_name("NAME")
+                .help("Hashes this name (e.g. a stable hostname) instead of the address to get the ring id")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("join_timeout")
+                .long("join-timeout")
+                .value_name("MILLIS")
+                .help("Exits with an error if the node could not join the ring within this time, instead of retrying forever")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tombstone_grace_period")
+                .long("tombstone-grace-period")
+                .value_name("MILLIS")
+                .help("Sets how long deleted keys are remembered, so that older copies moved in later stay deleted")
+                .takes_value(true)
+                .default_value("600000"),
+        )
+        .arg(
+            Arg::with_name("join_pings")
+                .long("join-pings")
+                .value_name("COUNT")
+                .help("Sets how many pings the successor found by the join has to answer before joining it (0 to skip)")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("routing_state")
+                .long("routing-state")
+                .value_name("FILE")
+                .help("Saves successors, predecessor and fingers to FILE on shutdown and starts with the ones still alive")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("write_warmup")
+                .long("write-warmup")
+                .value_name("MILLIS")
+                .help("Sets how long a joined node forwards writes to its successor until the successor took it as predecessor")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("hop_alert_factor")
+                .long("hop-alert-factor")
+                .value_name("FACTOR")
+                .help("Sets above how many times log2(ring size) hops a lookup is reported as degraded routing (0 to disable)")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("migration_strategy")
+                .long("migration-strategy")
+                .value_name("STRATEGY")
+                .help("Sets whether keys move to a new predecessor at once or in batches, served on demand meanwhile")
+                .takes_value(true)
+                .possible_values(&["eager", "lazy"])
+                .default_value("eager"),
+        )
+        .arg(
+            Arg::with_name("crawl_parallelism")
+                .long("crawl-parallelism")
+                .value_name("NODES")
+                .help("Sets how many nodes a crawl of the ring (key distribution, client export) asks at the same time")
+                .takes_value(true)
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("value_compression")
+                .long("value-compression")
+                .value_name("ALGORITHM")
+                .help("Holds large values compressed in memory")
+                .takes_value(true)
+                .possible_values(&["none", "deflate", "gzip"])
+                .default_value("none"),
+        )
+        .arg(
+            Arg::with_name("value_compression_threshold")
+                .long("value-compression-threshold")
+                .value_name("BYTES")
+                .help("Sets from which value size on values are compressed")
+                .takes_value(true)
+                .default_value("4096"),
+        )
+        .arg(
+            Arg::with_name("max_inflight_lookups")
+                .long("max-inflight-lookups")
+                .value_name("COUNT")
+                .help("Sets how many lookups of others are answered at the same time, further ones are answered busy (0 for unbounded)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("migration_rate_limit")
+                .long("migration-rate-limit")
+                .value_name("BYTES_PER_SEC")
+                .help("Sets how fast keys are handed to other nodes as the ring changes or on leaving (0 for unlimited)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("read_only")
+                .long("read-only")
+                .help("Answers finds but rejects writes to the keys this node owns, until switched back in the menu"),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("IP")
+                .help("Accepts connections on IP instead of all IPv4 addresses, e.g. given twice for IPv4 and IPv6")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Neither prints the node state nor reads the menu from stdin"),
+        )
+        .arg(
+            Arg::with_name("join_request_timeout")
+                .long("join-request-timeout")
+                .value_name("MILLIS")
+                .help("Sets how long sending a join request may take")
+                .takes_value(true)
+                .default_value("5000"),
+        )
+        .arg(
+            Arg::with_name("lookup_request_timeout")
+                .long("lookup-request-timeout")
+                .value_name("MILLIS")
+                .help("Sets how long each hop of a lookup may take")
+                .takes_value(true)
+                .default_value("5000"),
+        )
+        .arg(
+            Arg::with_name("store_request_timeout")
+                .long("store-request-timeout")
+                .value_name("MILLIS")
+                .help("Sets how long storing, changing or deleting a key may take")
+                .takes_value(true)
+                .default_value("30000"),
+        )
+        .arg(
+            Arg::with_name("find_request_timeout")
+                .long("find-request-timeout")
+                .value_name("MILLIS")
+                .help("Sets how long finding a key or a page of a storage dump may take")
+                .takes_value(true)
+                .default_value("10000"),
+        )
+        .arg(
+            Arg::with_name("handoff_request_timeout")
+                .long("handoff-request-timeout")
+                .value_name("MILLIS")
+                .help("Sets how long handing all keys over when leaving may take")
+                .takes_value(true)
+                .default_value("60000"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Reads the node options from a TOML file, options given on the command line take precedence")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("circuit_breaker_threshold")
+                .long("circuit-breaker-threshold")
+                .value_name("FAILURES")
+                .help("Sets after how many failed connects in a row a peer is not contacted for the cooldown (0 to disable)")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("circuit_breaker_cooldown")
+                .long("circuit-breaker-cooldown")
+                .value_name("MILLIS")
+                .help("Sets how long connects to a failing peer fail right away before one is tried again")
+                .takes_value(true)
+                .default_value("10000"),
+        )
+        .arg(
+            Arg::with_name("max_send_threads")
+                .long("max-send-threads")
+                .value_name("COUNT")
+                .help("Sets how many messages are sent at the same time at most (0 for unbounded)")
+                .takes_value(true)
+                .default_value("64"),
+        );
+    #[cfg(feature = "discovery")]
+    let app = app.arg(
+        Arg::with_name("discover")
+            .long("discover")
+            .help("Joins the nodes found in the local network if no entry point is given, and lets others find this node"),
+    );
+    let matches = app.get_matches();
+
+    if let ("client", Some(client_matches)) = matches.subcommand() {
+        let key_space_bits = matches.value_of("key_space_bits").unwrap().parse().unwrap();
+        let crawl_parallelism = matches.value_of("crawl_parallelism").unwrap().parse().unwrap();
+        let value_encryption_key = matches.value_of("value_key").map(|key| key.to_string());
+        run_client(client_matches, key_space_bits, crawl_parallelism, value_encryption_key);
+        return;
+    }
 
     // Validate, parse CLI arguments
     let ip4_addr = match matches.value_of("ip4_addr").unwrap().parse::<Ipv4Addr>() {
@@ -107,6 +508,78 @@ fn main() {
     };
     debug!("listening_ip: {}", listening_ip);
 
+    // Options given on the command line take precedence over the configuration file
+    let base = match matches.value_of("config") {
+        Some(path) => match config::ChordConfig::from_toml_path(Path::new(path)) {
+            Ok(config) => config,
+            Err(e) => panic!(e),
+        },
+        None => config::ChordConfig::default(),
+    };
+    let config = config::ChordConfig {
+        verify_join: base.verify_join || matches.is_present("verify_join"),
+        log_messages_on_error_only: base.log_messages_on_error_only || matches.is_present("log_messages_on_error_only"),
+        successor_selection: given_value(&matches, "successor_selection").unwrap_or(base.successor_selection),
+        value_encryption_key: matches.value_of("value_key").map(|key| key.to_string()).or(base.value_encryption_key),
+        max_send_threads: given_value(&matches, "max_send_threads").unwrap_or(base.max_send_threads),
+        observer: base.observer || matches.is_present("observer"),
+        batch_stabilize: base.batch_stabilize || matches.is_present("batch_stabilize"),
+        key_space_bits: given_value(&matches, "key_space_bits").unwrap_or(base.key_space_bits),
+        min_protocol_version: given_value(&matches, "min_protocol_version").unwrap_or(base.min_protocol_version),
+        max_value_size: given_value(&matches, "max_value_size").unwrap_or(base.max_value_size),
+        chunk_size: given_value(&matches, "chunk_size").unwrap_or(base.chunk_size),
+        storage_high_water_mark: given_value(&matches, "storage_high_water_mark").unwrap_or(base.storage_high_water_mark),
+        tcp_nodelay: base.tcp_nodelay && !matches.is_present("no_tcp_nodelay"),
+        tcp_keepalive: given_millis(&matches, "tcp_keepalive").or(base.tcp_keepalive),
+        predecessor_failure_threshold: given_value(&matches, "predecessor_failures").unwrap_or(base.predecessor_failure_threshold),
+        discover: base.discover || matches.is_present("discover"),
+        stabilize_min_interval: given_millis(&matches, "stabilize_min_interval").unwrap_or(base.stabilize_min_interval),
+        stabilize_max_interval: given_millis(&matches, "stabilize_max_interval").unwrap_or(base.stabilize_max_interval),
+        reserved_ranges: match matches.values_of("reserve_range") {
+            Some(ranges) => ranges.map(|range| range.parse().unwrap()).collect(),
+            None => base.reserved_ranges,
+        },
+        dump_page_size: given_value(&matches, "dump_page_size").unwrap_or(base.dump_page_size),
+        ring_probes: match matches.values_of("probe") {
+            Some(probes) => probes.map(|probe| probe.parse().unwrap()).collect(),
+            None => base.ring_probes,
+        },
+        node_id: matches.value_of("node_id").map(|id| config::parse_ring_id(id).unwrap()).or(base.node_id),
+        identity: match matches.value_of("node_name") {
+            Some(name) => config::NodeIdentity::Custom(name.to_string()),
+            None => base.identity,
+        },
+        join_timeout: given_millis(&matches, "join_timeout").or(base.join_timeout),
+        tombstone_grace_period: given_millis(&matches, "tombstone_grace_period").unwrap_or(base.tombstone_grace_period),
+        join_pings: given_value(&matches, "join_pings").unwrap_or(base.join_pings),
+        circuit_breaker_threshold: given_value(&matches, "circuit_breaker_threshold").unwrap_or(base.circuit_breaker_threshold),
+        circuit_breaker_cooldown: given_millis(&matches, "circuit_breaker_cooldown").unwrap_or(base.circuit_breaker_cooldown),
+        routing_state_file: matches.value_of("routing_state").map(PathBuf::from).or(base.routing_state_file),
+        write_warmup: given_millis(&matches, "write_warmup").unwrap_or(base.write_warmup),
+        hop_alert_factor: given_value(&matches, "hop_alert_factor").unwrap_or(base.hop_alert_factor),
+        migration_strategy: given_value(&matches, "migration_strategy").unwrap_or(base.migration_strategy),
+        crawl_parallelism: given_value(&matches, "crawl_parallelism").unwrap_or(base.crawl_parallelism),
+        value_compression: given_value(&matches, "value_compression").unwrap_or(base.value_compression),
+        value_compression_threshold: given_value(&matches, "value_compression_threshold").unwrap_or(base.value_compression_threshold),
+        max_inflight_lookups: given_value(&matches, "max_inflight_lookups").unwrap_or(base.max_inflight_lookups),
+        headless: base.headless || matches.is_present("headless"),
+        read_only: base.read_only || matches.is_present("read_only"),
+        migration_rate_limit: given_value(&matches, "migration_rate_limit").unwrap_or(base.migration_rate_limit),
+        listen_addresses: match matches.values_of("listen") {
+            Some(addresses) => addresses.map(|address| address.parse().unwrap()).collect(),
+            None => base.listen_addresses,
+        },
+        join_request_timeout: given_millis(&matches, "join_request_timeout").unwrap_or(base.join_request_timeout),
+        lookup_request_timeout: given_millis(&matches, "lookup_request_timeout").unwrap_or(base.lookup_request_timeout),
+        store_request_timeout: given_millis(&matches, "store_request_timeout").unwrap_or(base.store_request_timeout),
+        find_request_timeout: given_millis(&matches, "find_request_timeout").unwrap_or(base.find_request_timeout),
+        handoff_request_timeout: given_millis(&matches, "handoff_request_timeout").unwrap_or(base.handoff_request_timeout),
+    };
+    if let Err(e) = config.validate() {
+        panic!(e);
+    }
+    debug!("config: {:?}", config);
+
     // Join existing chord ring, or create new chord ring as first node
     if matches.is_present("entry_point") {
         let entry_point = match matches
@@ -119,8 +592,11 @@ fn main() {
         };
         debug!("entry_point: {}", entry_point);
         if listening_ip != entry_point {
-            let node_handle = chord::spawn_node(listening_ip, port, Some(entry_point));
-            node_handle.join().expect("node_handle.join() failed");
+            let node_handle = chord::spawn_node(listening_ip, port, Some(entry_point), config);
+            if let Err(e) = node_handle.join().expect("node_handle.join() failed") {
+                error!("{}", e);
+                std::process::exit(1);
+            }
         } else {
             panic!(
                 "listening_ip != entry_point = {}",
@@ -128,9 +604,62 @@ fn main() {
             );
         }
     } else {
-        let first_node_handle = chord::spawn_node(listening_ip, port, None);
-        first_node_handle
-            .join()
-            .expect("first_node_handle.join() failed");
+        let first_node_handle = chord::spawn_node(listening_ip, port, None, config);
+        if let Err(e) = first_node_handle.join().expect("first_node_handle.join() failed") {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The value of the option `name` if it was given on the command line rather than defaulted
+fn given_value<T: FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> where T::Err: Debug {
+    if matches.occurrences_of(name) > 0 {
+        matches.value_of(name).map(|value| value.parse().unwrap())
+    } else {
+        None
+    }
+}
+
+fn given_millis(matches: &clap::ArgMatches, name: &str) -> Option<Duration> {
+    given_value(matches, name).map(Duration::from_millis)
+}
+
+/// Performs the operation of the `client` subcommand and prints its result
+fn run_client(matches: &clap::ArgMatches, key_space_bits: usize, crawl_parallelism: usize, value_encryption_key: Option<String>) {
+    let entry_point = match matches.value_of("entry_point").unwrap().parse::<SocketAddr>() {
+        Ok(m) => m,
+        Err(f) => panic!(f.to_string()),
+    };
+    let client = client::ChordClient::new(entry_point, key_space_bits)
+        .with_crawl_parallelism(crawl_parallelism)
+        .with_value_encryption_key(value_encryption_key);
+    let key = matches.value_of("key").unwrap();
+    let result = match matches.value_of("operation").unwrap() {
+        "store" => client.store(key, matches.value_of("value").unwrap())
+            .map(|_| format!("Stored key '{}'", key)),
+        "append" => client.append(key, matches.value_of("value").unwrap())
+            .map(|length| format!("Appended to key '{}', now {} bytes", key, length)),
+        "get" => client.get(key)
+            .map(|value| value.unwrap_or_else(|| format!("Key '{}' not found", key))),
+        "delete" => client.delete(key)
+            .map(|existed| if existed { format!("Deleted key '{}'", key) } else { format!("Key '{}' not found", key) }),
+        "pop" => client.pop(key)
+            .map(|value| value.unwrap_or_else(|| format!("Key '{}' not found", key))),
+        "export" => client.export_snapshot(Path::new(key))
+            .map(|summary| format!("Exported {} keys of {} nodes to {} ({} found on more than one node)",
+                                   summary.keys, summary.nodes, key, summary.overlaps)),
+        "import" => client.import_snapshot(Path::new(key))
+            .map(|imported| format!("Imported {} keys from {}", imported, key)),
+        _ => client.owner(key)
+            .map(|owner| format!("Key '{}' is owned by node #{} ({})",
+                                 key, chord::short_id(owner.get_id()), owner.get_ip_addr())),
+    };
+    match result {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -1,63 +1,609 @@
-use std::{process, str, thread};
-use std::io::{BufWriter, Write};
+use std::cmp;
+use std::thread;
+use std::collections::HashMap;
+use std::io::{BufWriter, ErrorKind, Read, Write};
 use std::io::BufReader;
 use std::net;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use futures::{Future, Stream};
+use futures::future::Either;
+use futures::sync::oneshot;
+use log::Level;
+use net2::{TcpBuilder, TcpStreamExt};
 use num_bigint::BigInt;
 use tokio::io;
 use tokio::net::TcpListener;
+use tokio::reactor::Handle;
 
 use super::chord;
+use super::config::ChordConfig;
+use super::logging;
 use super::node::*;
 use super::protocols::*;
 
-pub fn send_kill(target: SocketAddr) -> JoinHandle<()> {
-    let msg = Message::Kill;
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
+/// Ends every message, so that the listener can tell a complete message from a connection
+/// closed halfway through. Compact JSON never contains it, peers predating it end their
+/// messages by closing the connection.
+const MESSAGE_DELIMITER: u8 = b'\n';
+
+/// Circuit breaker of one peer, peers without one are closed with no failures
+#[derive(Clone, Copy, Debug)]
+enum Breaker {
+    /// Connections go through, `failures` is the number of consecutive failed connects
+    Closed { failures: usize },
+    /// Connections fail right away until the cooldown since `opened` is over
+    Open { opened: Instant },
+    /// A single probe connection is on its way, which closes or opens the breaker again
+    HalfOpen,
+}
+
+/// Time a request may take to connect, be sent and, if waited for, be answered
+#[derive(Clone, Debug)]
+struct RequestTimeouts {
+    join: Duration,
+    lookup: Duration,
+    store: Duration,
+    find: Duration,
+    handoff: Duration,
 }
 
-pub fn send_response(sender: OtherNode, target:SocketAddr, response: Response) -> JoinHandle<()> {
-    let msg = Message::ResponseMessage { sender, response };
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
+impl RequestTimeouts {
+    fn new(config: &ChordConfig) -> RequestTimeouts {
+        RequestTimeouts {
+            join: config.join_request_timeout,
+            lookup: config.lookup_request_timeout,
+            store: config.store_request_timeout,
+            find: config.find_request_timeout,
+            handoff: config.handoff_request_timeout,
+        }
+    }
+
+    /// Requests about the ring itself (e.g. GetPredecessor) are as quick as a ping
+    fn for_request(&self, request: &Request) -> Duration {
+        match request {
+            Request::DHTFindKey { .. } | Request::DHTFindKeyStream { .. } | Request::DumpStorage { .. } => self.find,
+            Request::DHTStoreKey { .. } | Request::DHTStoreChunk { .. } | Request::DHTStoreIfAbsent { .. }
+            | Request::DHTAppend { .. } | Request::DHTCompareAndSwap { .. } | Request::DHTTransaction { .. }
+            | Request::DHTDeleteKey { .. } | Request::DHTGetAndDelete { .. } | Request::DHTDeleteNamespace { .. } => self.store,
+            Request::DHTTakeOverKeys { .. } => self.handoff,
+            request if request.is_lookup() => self.lookup,
+            _ => chord::PING_TIMEOUT_INTERVAL,
+        }
+    }
 }
 
-pub fn send_request(sender: OtherNode, target:SocketAddr, request: Request) -> JoinHandle<()> {
-    let msg = Message::RequestMessage { sender, request };
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
+/// Socket options and send limit of one node. Each node has its own, so that several nodes
+/// (e.g. of different rings) can run in one process with different settings.
+#[derive(Clone, Debug)]
+pub struct Network {
+    /// Log messages at trace level and dump them in full only if handling them fails
+    log_messages_on_error_only: bool,
+    /// Threads sending the queued messages, `0` starts a thread for every message
+    max_send_threads: usize,
+    // Queue of the send threads, started with the first message. Shared by all clones, i.e. by
+    // all threads of the node.
+    send_queue: Arc<Mutex<Option<mpsc::Sender<SendJob>>>>,
+    /// Disable Nagle's algorithm, so that small messages are sent without delay
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    /// Consecutive failed connects to a peer after which its breaker opens, `0` disables them
+    circuit_breaker_threshold: usize,
+    circuit_breaker_cooldown: Duration,
+    // Shared by all clones, so that every thread of the node sees an open breaker
+    breakers: Arc<Mutex<HashMap<SocketAddr, Breaker>>>,
+    /// Upper bound of lookups handled at the same time, `0` means unbounded
+    max_inflight_lookups: usize,
+    // Shared by all clones like the send threads
+    inflight_lookups: Arc<AtomicUsize>,
+    /// Lookups answered busy since the start
+    shed_lookups: Arc<AtomicUsize>,
+    request_timeouts: RequestTimeouts,
+    /// Bytes per second of key migrations, `0` means unlimited
+    migration_rate_limit: usize,
+    // When the next migration message may be sent, shared by all clones so that concurrent
+    // migrations share the limit
+    migration_next_send: Arc<Mutex<Instant>>,
+    // Protocol versions of the peers heard from, by their listening address. Shared by all
+    // clones, so that every thread of the node encodes messages in the form the peer reads.
+    peer_versions: Arc<Mutex<HashMap<SocketAddr, u16>>>,
 }
 
-fn send_string_to_socket(addr: SocketAddr, msg: String) -> JoinHandle<()> {
-    let builder = thread::Builder::new().name("Send".to_string());
-    builder.spawn(move || {
-        match net::TcpStream::connect(addr) {
+/// The settings of a node started without options, for use outside of a node
+impl Default for Network {
+    fn default() -> Network {
+        Network {
+            log_messages_on_error_only: false,
+            max_send_threads: 0,
+            send_queue: Arc::new(Mutex::new(None)),
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            circuit_breaker_threshold: 0,
+            circuit_breaker_cooldown: Duration::default(),
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+            max_inflight_lookups: 0,
+            inflight_lookups: Arc::new(AtomicUsize::new(0)),
+            shed_lookups: Arc::new(AtomicUsize::new(0)),
+            request_timeouts: RequestTimeouts::new(&ChordConfig::default()),
+            migration_rate_limit: 0,
+            migration_next_send: Arc::new(Mutex::new(Instant::now())),
+            peer_versions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Network {
+    pub fn new(config: &ChordConfig) -> Network {
+        Network {
+            log_messages_on_error_only: config.log_messages_on_error_only,
+            max_send_threads: config.max_send_threads,
+            send_queue: Arc::new(Mutex::new(None)),
+            tcp_nodelay: config.tcp_nodelay,
+            tcp_keepalive: config.tcp_keepalive,
+            circuit_breaker_threshold: config.circuit_breaker_threshold,
+            circuit_breaker_cooldown: config.circuit_breaker_cooldown,
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+            max_inflight_lookups: config.max_inflight_lookups,
+            inflight_lookups: Arc::new(AtomicUsize::new(0)),
+            shed_lookups: Arc::new(AtomicUsize::new(0)),
+            request_timeouts: RequestTimeouts::new(config),
+            migration_rate_limit: config.migration_rate_limit,
+            migration_next_send: Arc::new(Mutex::new(Instant::now())),
+            peer_versions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn shed_lookups(&self) -> usize {
+        self.shed_lookups.load(Ordering::SeqCst)
+    }
+
+    /// A slot for handling a lookup, `None` if `max_inflight_lookups` are in flight already
+    fn try_acquire_lookup_slot(&self) -> Option<LookupSlot> {
+        let active = self.inflight_lookups.fetch_add(1, Ordering::SeqCst);
+        let slot = LookupSlot { inflight_lookups: self.inflight_lookups.clone() };
+        if self.max_inflight_lookups != 0 && active >= self.max_inflight_lookups {
+            self.shed_lookups.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(slot)
+    }
+
+    /// Connects to `addr` with the configured socket options, every outgoing message goes through here
+    fn open_stream(&self, addr: SocketAddr, timeout: Option<Duration>) -> std::io::Result<net::TcpStream> {
+        self.check_breaker(addr)?;
+        let result = match timeout {
+            Some(timeout) => net::TcpStream::connect_timeout(&addr, timeout),
+            None => net::TcpStream::connect(addr),
+        };
+        self.record_connect(addr, result.is_ok());
+        let stream = result?;
+        if let Err(e) = stream.set_nodelay(self.tcp_nodelay)
+            .and_then(|_| TcpStreamExt::set_keepalive(&stream, self.tcp_keepalive)) {
+            warn!(target: chord::LOG_NETWORK, "Unable to set socket options: {}", e);
+        }
+        Ok(stream)
+    }
+
+    /// Fails right away while the breaker of `addr` is open. Once the cooldown is over the
+    /// breaker turns half-open and lets one probe connect through.
+    fn check_breaker(&self, addr: SocketAddr) -> std::io::Result<()> {
+        if self.circuit_breaker_threshold == 0 {
+            return Ok(());
+        }
+        let mut breakers = self.breakers.lock().unwrap();
+        match breakers.get(&addr).cloned() {
+            Some(Breaker::Open { opened }) if opened.elapsed() >= self.circuit_breaker_cooldown => {
+                debug!(target: chord::LOG_NETWORK, "Circuit breaker of {} is half-open, probing", addr);
+                breakers.insert(addr, Breaker::HalfOpen);
+                Ok(())
+            }
+            Some(Breaker::Open { .. }) | Some(Breaker::HalfOpen) =>
+                Err(std::io::Error::new(ErrorKind::ConnectionRefused, format!("circuit breaker of {} is open", addr))),
+            Some(Breaker::Closed { .. }) | None => Ok(()),
+        }
+    }
+
+    /// Counts a failed connect towards opening the breaker of `addr`, a successful one closes it
+    fn record_connect(&self, addr: SocketAddr, connected: bool) {
+        if self.circuit_breaker_threshold == 0 {
+            return;
+        }
+        let mut breakers = self.breakers.lock().unwrap();
+        let failures = match breakers.get(&addr).cloned() {
+            Some(Breaker::HalfOpen) if connected => {
+                info!(target: chord::LOG_NETWORK, "Circuit breaker of {} closed, the peer is reachable again", addr);
+                breakers.remove(&addr);
+                return;
+            }
+            Some(Breaker::HalfOpen) => {
+                debug!(target: chord::LOG_NETWORK, "Circuit breaker of {} opened again, the probe failed", addr);
+                breakers.insert(addr, Breaker::Open { opened: Instant::now() });
+                return;
+            }
+            _ if connected => {
+                breakers.remove(&addr);
+                return;
+            }
+            // A connect which started before the breaker opened
+            Some(Breaker::Open { .. }) => return,
+            Some(Breaker::Closed { failures }) => failures + 1,
+            None => 1,
+        };
+        if failures >= self.circuit_breaker_threshold {
+            warn!(target: chord::LOG_NETWORK, "Circuit breaker of {} opened after {} failed connects, retrying in {:?}",
+                  addr, failures, self.circuit_breaker_cooldown);
+            breakers.insert(addr, Breaker::Open { opened: Instant::now() });
+        } else {
+            breakers.insert(addr, Breaker::Closed { failures });
+        }
+    }
+
+    /// Number of peers whose breaker is open or half-open
+    pub fn open_breakers(&self) -> usize {
+        self.breakers.lock().unwrap().values()
+            .filter(|breaker| match breaker {
+                Breaker::Closed { .. } => false,
+                Breaker::Open { .. } | Breaker::HalfOpen => true,
+            })
+            .count()
+    }
+
+    /// Remembers the protocol version of the node `sender`, so that messages to it are encoded
+    /// in a form it reads
+    fn record_peer_version(&self, sender: &OtherNode) {
+        self.peer_versions.lock().unwrap().insert(*sender.get_ip_addr(), sender.get_protocol_version());
+    }
+
+    /// Encodes `msg` for the node listening on `target`. Peers not heard from yet are treated
+    /// like peers predating versioning.
+    fn encode_for(&self, target: SocketAddr, msg: &Message) -> String {
+        let peer_version = self.peer_versions.lock().unwrap().get(&target).cloned().unwrap_or(0);
+        encode_message(peer_version, msg)
+    }
+
+    /// Level for logging message payloads on the happy path
+    pub fn message_log_level(&self, level: Level) -> Level {
+        if self.log_messages_on_error_only {
+            Level::Trace
+        } else {
+            level
+        }
+    }
+
+    /// Level for logging messages which could not be handled
+    fn failed_message_log_level(&self, level: Level) -> Level {
+        if self.log_messages_on_error_only {
+            Level::Error
+        } else {
+            level
+        }
+    }
+
+    pub fn send_kill(&self, target: SocketAddr) -> SendHandle {
+        let msg = Message::Kill;
+        self.send_string_to_socket(target, self.encode_for(target, &msg), None)
+    }
+
+    pub fn send_response(&self, sender: OtherNode, target: SocketAddr, response: Response) -> SendHandle {
+        self.send_answer(sender, target, response, None)
+    }
+
+    /// Like `send_response`, a lookup answered with `response` stays in flight until it is sent
+    fn send_answer(&self, sender: OtherNode, target: SocketAddr, response: Response, lookup_slot: Option<LookupSlot>) -> SendHandle {
+        let msg = Message::ResponseMessage { sender, response, trace: Some(outgoing_trace()) };
+        self.queue_send(target, self.encode_for(target, &msg), None, lookup_slot)
+    }
+
+    pub fn send_request(&self, sender: OtherNode, target: SocketAddr, request: Request) -> SendHandle {
+        let timeout = self.request_timeouts.for_request(&request);
+        let msg = Message::RequestMessage { sender, request, trace: Some(outgoing_trace()) };
+        self.send_string_to_socket(target, self.encode_for(target, &msg), Some(timeout))
+    }
+
+    /// Sends the requests moving keys to `target` from a thread of their own. With a
+    /// `migration_rate_limit` they are sent one after the other at that rate, otherwise all at once.
+    pub fn send_migration(&self, sender: OtherNode, target: SocketAddr, requests: Vec<Request>) -> JoinHandle<()> {
+        let network = self.clone();
+        logging::spawn("Migrate", move || {
+            if network.migration_rate_limit == 0 {
+                let sends: Vec<_> = requests.into_iter()
+                    .map(|request| network.send_request(sender.clone(), target, request))
+                    .collect();
+                for send in sends {
+                    send.wait();
+                }
+                return;
+            }
+            for request in requests {
+                let timeout = network.request_timeouts.for_request(&request);
+                let msg = network.encode_for(target, &Message::RequestMessage { sender: sender.clone(), request, trace: Some(outgoing_trace()) });
+                let send_at = network.reserve_migration_slot(msg.len());
+                let now = Instant::now();
+                if send_at > now {
+                    thread::sleep(send_at - now);
+                }
+                network.send_string_to_socket(target, msg, Some(timeout)).wait();
+            }
+        }).unwrap()
+    }
+
+    /// The time a migration message of `size` bytes may be sent at, which pushes the next
+    /// slot back by the time the message takes at `migration_rate_limit`
+    fn reserve_migration_slot(&self, size: usize) -> Instant {
+        let mut next_send = self.migration_next_send.lock().unwrap();
+        let send_at = cmp::max(*next_send, Instant::now());
+        let micros = size as u64 * 1_000_000 / self.migration_rate_limit as u64;
+        *next_send = send_at + Duration::from_micros(micros);
+        send_at
+    }
+
+    /// Sends the FindSuccessor of a join, which may take `ChordConfig::join_request_timeout`
+    pub fn send_join_request(&self, sender: OtherNode, target: SocketAddr, request: Request) -> SendHandle {
+        let msg = Message::RequestMessage { sender, request, trace: Some(outgoing_trace()) };
+        self.send_string_to_socket(target, self.encode_for(target, &msg), Some(self.request_timeouts.join))
+    }
+
+    /// Sends the request after `delay`, e.g. to retry a lookup answered busy
+    pub fn send_request_later(&self, sender: OtherNode, target: SocketAddr, request: Request, delay: Duration) {
+        let network = self.clone();
+        if let Err(e) = logging::spawn("Retry", move || {
+            thread::sleep(delay);
+            network.send_request(sender, target, request);
+        }) {
+            error!(target: chord::LOG_NETWORK, "Unable to start a thread to send a request to {} later: {}", target, e);
+        }
+    }
+
+    /// Hands `msg` to a send thread and returns right away, so that it may be called while
+    /// holding the node lock
+    fn send_string_to_socket(&self, addr: SocketAddr, msg: String, timeout: Option<Duration>) -> SendHandle {
+        self.queue_send(addr, msg, timeout, None)
+    }
+
+    fn queue_send(&self, addr: SocketAddr, msg: String, timeout: Option<Duration>, lookup_slot: Option<LookupSlot>) -> SendHandle {
+        let (done, handle) = mpsc::channel();
+        let job = SendJob { addr, msg, timeout, lookup_slot, done };
+        if self.max_send_threads == 0 {
+            let network = self.clone();
+            logging::spawn("Send", move || network.send_job(job)).unwrap();
+        } else if let Err(mpsc::SendError(job)) = self.send_queue().send(job) {
+            error!(target: chord::LOG_NETWORK, "Unable to send msg to {} - No send thread is running. Message: {}", job.addr, job.msg);
+        }
+        SendHandle(handle)
+    }
+
+    /// The queue of the send threads, starting them on the first call
+    fn send_queue(&self) -> mpsc::Sender<SendJob> {
+        let mut send_queue = self.send_queue.lock().unwrap();
+        if let Some(ref queue) = *send_queue {
+            return queue.clone();
+        }
+        let (queue, jobs) = mpsc::channel::<SendJob>();
+        let jobs = Arc::new(Mutex::new(jobs));
+        // The threads do not hold the queue themselves, so that they stop once the node is gone
+        let network = Network { send_queue: Arc::new(Mutex::new(None)), ..self.clone() };
+        for _ in 0..self.max_send_threads {
+            let jobs = jobs.clone();
+            let network = network.clone();
+            let started = logging::spawn("Send", move || loop {
+                let job = jobs.lock().unwrap().recv();
+                match job {
+                    Ok(job) => network.send_job(job),
+                    Err(_) => return,
+                }
+            });
+            if let Err(e) = started {
+                error!(target: chord::LOG_NETWORK, "Unable to start a send thread: {}", e);
+            }
+        }
+        *send_queue = Some(queue.clone());
+        queue
+    }
+
+    /// Without a `timeout` connecting and writing may block as long as the OS lets them
+    fn send_job(&self, job: SendJob) {
+        let SendJob { addr, msg, timeout, .. } = job;
+        match self.open_stream(addr, timeout) {
             Ok(stream) => {
+                if let Err(e) = stream.set_write_timeout(timeout) {
+                    warn!(target: chord::LOG_NETWORK, "Unable to set the write timeout: {}", e);
+                }
                 let mut writer = BufWriter::new(stream);
-                writer.write_all(msg.as_bytes()).unwrap();
-                debug!("Sent msg: {}", msg);
+                match writer.write_all(msg.as_bytes())
+                    .and_then(|_| writer.write_all(&[MESSAGE_DELIMITER]))
+                    .and_then(|_| writer.flush()) {
+                    Ok(_) => log!(target: chord::LOG_NETWORK, self.message_log_level(Level::Debug), "Sent msg: {}", msg),
+                    Err(e) => error!(target: chord::LOG_NETWORK, "Unable to send msg to {} - Failed to write: {}. Message: {}", addr, e, msg),
+                }
             }
             Err(e) => {
-                debug!("Unable to send msg to {} - Failed to connect: {}", addr, e);
+                log!(target: chord::LOG_NETWORK, self.failed_message_log_level(Level::Debug), "Unable to send msg to {} - Failed to connect: {}. Message: {}", addr, e, msg);
             }
         }
-    }).unwrap()
+    }
+
+    pub fn check_alive(&self, addr: SocketAddr, sender: OtherNode) -> bool {
+        self.ping(addr, sender).is_some()
+    }
+
+    /// Pings `addr` and returns the measured round trip time
+    pub fn ping_rtt(&self, addr: SocketAddr, sender: OtherNode) -> Option<Duration> {
+        self.ping(addr, sender).map(|(_, rtt)| rtt)
+    }
+
+    /// Pings `addr` and waits for its `Pong`, returning the answering node and the round trip time
+    pub fn ping(&self, addr: SocketAddr, sender: OtherNode) -> Option<(OtherNode, Duration)> {
+        let started = Instant::now();
+        match self.open_stream(addr, Some(chord::PING_TIMEOUT_INTERVAL)) {
+            Ok(mut stream) => {
+                let msg = self.encode_for(addr, &Message::Ping { sender });
+                let mut reply = String::new();
+                let result = stream.set_read_timeout(Some(chord::PING_TIMEOUT_INTERVAL))
+                    .and_then(|_| stream.write_all(msg.as_bytes()))
+                    .and_then(|_| stream.write_all(&[MESSAGE_DELIMITER]))
+                    .and_then(|_| stream.shutdown(net::Shutdown::Write))
+                    .and_then(|_| stream.read_to_string(&mut reply));
+                match result {
+                    Ok(_) => match serde_json::from_str(&reply) {
+                        Ok(Message::Pong { sender }) => Some((sender, started.elapsed())),
+                        _ => {
+                            debug!(target: chord::LOG_NETWORK, "Check alive got no pong from {}: {}", addr, reply);
+                            None
+                        }
+                    },
+                    Err(e) => {
+                        debug!(target: chord::LOG_NETWORK, "Check alive timeouted waiting for pong: {:?}", e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                debug!(target: chord::LOG_NETWORK, "Check alive timeouted: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Sends `request` as a client and waits for the response on the same connection, as long as
+    /// the timeout for its kind of request allows
+    pub fn send_client_request(&self, addr: SocketAddr, request: Request) -> Result<Response, String> {
+        let timeout = self.request_timeouts.for_request(&request);
+        let mut stream = self.open_stream(addr, Some(timeout)).map_err(|e| format!("Unable to connect to {}: {}", addr, e))?;
+        let msg = self.encode_for(addr, &Message::ClientRequest { request, trace: Some(outgoing_trace()) });
+        let mut reply = String::new();
+        stream.set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .and_then(|_| stream.write_all(msg.as_bytes()))
+            .and_then(|_| stream.write_all(&[MESSAGE_DELIMITER]))
+            .and_then(|_| stream.shutdown(net::Shutdown::Write))
+            .and_then(|_| stream.read_to_string(&mut reply))
+            .map_err(|e| format!("No response from {}: {}", addr, e))?;
+        match serde_json::from_str(&reply) {
+            Ok(Message::ResponseMessage { response, .. }) => Ok(response),
+            _ => Err(format!("Unexpected reply from {}: {}", addr, reply)),
+        }
+    }
+}
+
+/// A message waiting for a send thread
+struct SendJob {
+    addr: SocketAddr,
+    msg: String,
+    timeout: Option<Duration>,
+    // Of the lookup the message answers. Fields are dropped in order, so it is released
+    // before `done` tells the waiting caller the message is sent.
+    #[allow(dead_code)]
+    lookup_slot: Option<LookupSlot>,
+    // Dropped once the message is sent or given up on, see `SendHandle`
+    #[allow(dead_code)]
+    done: mpsc::Sender<()>,
+}
+
+/// Lets the caller wait for a message to be sent, most callers just drop it
+pub struct SendHandle(mpsc::Receiver<()>);
+
+impl SendHandle {
+    /// Blocks until the message is sent or given up on
+    pub fn wait(self) {
+        let _ = self.0.recv();
+    }
 }
 
-pub fn check_alive(addr: SocketAddr, sender: OtherNode) -> bool {
-    match net::TcpStream::connect_timeout(&addr, chord::PING_TIMEOUT_INTERVAL) {
-        Ok(stream) => {
-            let msg = serde_json::to_string(&Message::Ping { sender }).unwrap();
-            let mut writer = BufWriter::new(stream);
-            writer.write_all(msg.as_bytes()).unwrap();
-            true
+/// Counts a lookup as in flight from accepting it until its answer is sent
+struct LookupSlot {
+    inflight_lookups: Arc<AtomicUsize>,
+}
+
+impl Drop for LookupSlot {
+    fn drop(&mut self) {
+        self.inflight_lookups.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The span of the current thread, or a new trace for messages sent outside of any, e.g. by
+/// the stabilize loop
+fn outgoing_trace() -> TraceContext {
+    logging::current_trace().unwrap_or_else(TraceContext::new)
+}
+
+/// Handles the following message in a span following the one it was sent in, or in a new
+/// trace if the sender predates tracing
+fn start_span(parent: Option<TraceContext>) {
+    let span = match parent {
+        Some(parent) => {
+            let span = parent.child();
+            trace!(target: chord::LOG_NETWORK, "Span {:016x} of trace {:016x} follows span {:016x}",
+                   span.get_span_id(), span.get_trace_id(), parent.get_span_id());
+            span
         }
-        Err(e) => {
-            debug!("Check alive timeouted: {:?}", e);
-            false
+        None => TraceContext::new(),
+    };
+    logging::set_trace(Some(span));
+}
+
+/// Answers the lookup `message` with `Response::LookupBusy`, returns the reply to write back
+/// on the connection if it came from a client
+fn shed_lookup(network: &Network, me: OtherNode, message: Message) -> Option<Message> {
+    match message {
+        Message::RequestMessage { sender, request, .. } => {
+            debug!(target: chord::LOG_NETWORK, "Too many lookups in flight, Node #{} may retry later: {:?}", chord::short_id(sender.get_id()), request);
+            let response = Response::LookupBusy { node: me.clone(), request: Box::new(request) };
+            network.send_response(me, *sender.get_ip_addr(), response);
+            None
         }
+        Message::ClientRequest { request, trace } => {
+            debug!(target: chord::LOG_NETWORK, "Too many lookups in flight, the client may retry later: {:?}", request);
+            let response = Response::LookupBusy { node: me.clone(), request: Box::new(request) };
+            Some(Message::ResponseMessage { sender: me, response, trace })
+        }
+        _ => None,
+    }
+}
+
+/// Encodes `msg` for a peer speaking `peer_version`
+fn encode_message(peer_version: u16, msg: &Message) -> String {
+    id_encoding::with_peer_version(peer_version, || serde_json::to_string(msg).unwrap())
+}
+
+fn get_sender(message: &Message) -> Option<&OtherNode> {
+    match message {
+        Message::Kill | Message::ClientRequest { .. } => None,
+        Message::Ping { sender } | Message::Pong { sender } => Some(sender),
+        Message::RequestMessage { sender, .. } | Message::ResponseMessage { sender, .. } => Some(sender),
+    }
+}
+
+/// Binds the listening sockets up front, one per address of `listen_addresses` or one on
+/// `chord::LISTENING_ADDRESS` if there are none, so that with port `0` the node can learn the
+/// port assigned by the OS before its id is derived from the address. All of them use the
+/// port assigned to the first one.
+pub fn bind_listeners(port: i32, listen_addresses: &[IpAddr]) -> std::io::Result<Vec<net::TcpListener>> {
+    let default_addresses = [chord::LISTENING_ADDRESS.parse::<IpAddr>().unwrap()];
+    let addresses = if listen_addresses.is_empty() { &default_addresses[..] } else { listen_addresses };
+    let mut port = port as u16;
+    let mut listeners = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let listener = bind_listener(SocketAddr::new(*address, port))?;
+        port = listener.local_addr()?.port();
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// An IPv6 socket accepts IPv6 only, so that another one can listen on the IPv4 addresses
+fn bind_listener(listen_ip: SocketAddr) -> std::io::Result<net::TcpListener> {
+    match listen_ip {
+        SocketAddr::V4(_) => net::TcpListener::bind(listen_ip),
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?
+            .only_v6(true)?
+            .reuse_address(true)?
+            .bind(listen_ip)?
+            .listen(128),
     }
 }
 
@@ -65,70 +611,597 @@ pub fn check_alive(addr: SocketAddr, sender: OtherNode) -> bool {
 // nc 127.0.0.1 34254
 // can be killed by sending "Kill" (with apostrophes)
 // afterwards every message will be echoed in the console by handle_request
-pub fn start_listening_on_socket(node_arc: Arc<Mutex<Node>>, port: i32, id: BigInt) -> Result<(), Box<std::error::Error>> {
-    let listen_ip = format!("{}:{}", chord::LISTENING_ADDRESS, port)
-        .parse::<SocketAddr>()
-        .unwrap();
-
-    let listener = TcpListener::bind(&listen_ip).unwrap();
+/// Serves `listeners` until `shutdown` fires or its sender is dropped, then waits for the
+/// connections in progress. Connections to any of them reach the same node.
+pub fn start_listening_on_socket(node_arc: Arc<Mutex<Node>>, listeners: Vec<net::TcpListener>, id: BigInt,
+                                 shutdown: oneshot::Receiver<()>) -> Result<(), Box<std::error::Error>> {
+    let listen_ips = listeners.iter().map(|listener| listener.local_addr()).collect::<std::io::Result<Vec<SocketAddr>>>()?;
+    let mut incoming: Box<dyn Stream<Item = tokio::net::TcpStream, Error = std::io::Error> + Send> = Box::new(futures::stream::empty());
+    for listener in listeners {
+        incoming = Box::new(incoming.select(TcpListener::from_std(listener, &Handle::default())?.incoming()));
+    }
 
-    debug!("[Node #{}] Starting to listen on socket: {}", id.clone(), listen_ip);
+    debug!(target: chord::LOG_NETWORK, "[Node #{}] Starting to listen on sockets: {:?}", chord::short_id(&id), listen_ips);
+    let network = node_arc.lock().unwrap().get_network().clone();
+    // Answers shed lookups without waiting for the node
+    let me = node_arc.lock().unwrap().to_other_node();
 
-    let server = listener.incoming().for_each(move |socket| {
-        //debug!("[Node #{}] accepted socket; addr={:?}", id, socket.peer_addr()?);
+    let server = incoming.for_each(move |socket| {
+        //debug!(target: chord::LOG_NETWORK, "[Node #{}] accepted socket; addr={:?}", id, socket.peer_addr()?);
 
+        let peer_addr = socket.peer_addr().ok();
+        if let Err(e) = socket.set_nodelay(network.tcp_nodelay)
+            .and_then(|_| socket.set_keepalive(network.tcp_keepalive)) {
+            warn!(target: chord::LOG_NETWORK, "Unable to set socket options for {:?}: {}", peer_addr, e);
+        }
         let buf = vec![];
         let buf_reader = BufReader::new(socket);
 
         let arc_clone = node_arc.clone();
+        let network = network.clone();
+        let me = me.clone();
 
-        let connection = io::read_until(buf_reader, b'\n', buf)
-            .and_then(move |(_socket, buf)| {
-                let msg_string = str::from_utf8(&buf).unwrap();
-                let message = serde_json::from_str(msg_string).unwrap();
-                //info!("Look at me: {:?}",serde_json::to_string(&Message::Kill{}).unwrap());
+        // Reads across as many segments as it takes, until the delimiter or the end of the connection
+        let connection = io::read_until(buf_reader, MESSAGE_DELIMITER, buf)
+            .and_then(move |(socket, buf)| {
+                if buf.is_empty() {
+                    // E.g. a port scan or a health check that only tests the connect
+                    debug!(target: chord::LOG_NETWORK, "Connection from {:?} was closed without a msg", peer_addr);
+                    return Ok((socket, None, None));
+                }
+                let complete = buf.last() == Some(&MESSAGE_DELIMITER);
+                let message = match serde_json::from_slice(&buf) {
+                    Ok(message) => message,
+                    // Without the delimiter a message cut off by the connection closing looks like any other
+                    Err(ref e) if !complete && e.is_eof() => {
+                        warn!(target: chord::LOG_NETWORK, "Ignoring incomplete msg from {:?}, the connection was closed after {} bytes",
+                              peer_addr, buf.len());
+                        return Ok((socket, None, None));
+                    }
+                    Err(e) => {
+                        // Peers running a newer version may send message kinds unknown to this node
+                        if Message::is_of_unknown_kind(&buf) {
+                            warn!(target: chord::LOG_NETWORK, "Ignoring msg from {:?}, the sender probably runs a newer version: {}", peer_addr, e);
+                        } else {
+                            error!(target: chord::LOG_NETWORK, "Failed to parse msg from {:?}: {}. Message: {}", peer_addr, e, String::from_utf8_lossy(&buf));
+                        }
+                        return Ok((socket, None, None));
+                    }
+                };
+                //info!(target: chord::LOG_NETWORK, "Look at me: {:?}",serde_json::to_string(&Message::Kill{}).unwrap());
+                // Held until the lookup is answered, see `ChordConfig::max_inflight_lookups`
+                let mut lookup_slot = match &message {
+                    Message::RequestMessage { request, .. } | Message::ClientRequest { request, .. } if request.is_lookup() => {
+                        match network.try_acquire_lookup_slot() {
+                            Some(slot) => Some(slot),
+                            None => return Ok((socket, shed_lookup(&network, me, message).map(|reply| encode_message(0, &reply)), None)),
+                        }
+                    }
+                    _ => None,
+                };
                 let mut node = arc_clone.lock().unwrap();
-                match message {
+                if let Some(sender) = get_sender(&message) {
+                    if sender.get_protocol_version() < node.get_config().min_protocol_version {
+                        warn!(target: chord::LOG_NETWORK, "Rejecting msg from Node #{} speaking protocol version {}, at least {} is required",
+                              chord::short_id(sender.get_id()), sender.get_protocol_version(), node.get_config().min_protocol_version);
+                        return Ok((socket, None, None));
+                    }
+                    network.record_peer_version(sender);
+                }
+                // Clients send no version, ids in the old form are read by every version
+                let reply_version = get_sender(&message).map_or(0, |sender| sender.get_protocol_version());
+                let reply = match message {
                     Message::Kill => {
-                        info!("Got kill message, shutting down...");
-                        let node_clone = node.clone();
+                        info!(target: chord::LOG_NETWORK, "Got kill message, shutting down...");
+                        node.get_shutdown_signal().request();
                         drop(node);
-
-                        let handle_opt = node_clone.clone().graceful_shutdown();
-                        if let Some(handle) = handle_opt {
-                            handle.join().expect("handle_graceful_shutdown failed");
-                        }
-                        process::exit(0);
+                        Ok((socket, None))
                     }
                     Message::Ping { sender } => {
-                        debug!("Got pinged from Node #{}", sender.get_id());
-                        Ok(())
+                        debug!(target: chord::LOG_NETWORK, "Got pinged from Node #{}", chord::short_id(sender.get_id()));
+                        let pong = Message::Pong { sender: node.to_other_node() };
+                        drop(node);
+                        Ok((socket, Some(pong)))
                     }
-                    Message::RequestMessage { sender, request } => {
-                        debug!("[Node #{}] Got request from Node #{}: {:?}", node.get_id().clone(), sender.get_id(), request.clone());
+                    Message::Pong { sender } => {
+                        debug!(target: chord::LOG_NETWORK, "Got unexpected pong from Node #{}", chord::short_id(sender.get_id()));
+                        Ok((socket, None))
+                    }
+                    Message::RequestMessage { sender, request, trace } => {
+                        start_span(trace);
+                        log!(target: chord::LOG_NETWORK, network.message_log_level(Level::Debug), "[Node #{}] Got request from Node #{}: {:?}", chord::short_id(node.get_id()), chord::short_id(sender.get_id()), request.clone());
                         let response_option = node.process_incoming_request(request);
                         let node_as_other_node = node.to_other_node();
                         drop(node);
                         if let Some(response) = response_option {
-                            send_response(node_as_other_node, *sender.get_ip_addr(), response);
+                            network.send_answer(node_as_other_node, *sender.get_ip_addr(), response, lookup_slot.take());
                         }
-                        Ok(())
+                        Ok((socket, None))
                     }
-                    Message::ResponseMessage { sender, response } => {
-                        debug!("[Node #{}] Got response from Node #{}: {:?}", node.get_id().clone(), sender.get_id(), response.clone());
+                    Message::ClientRequest { request, trace } => {
+                        start_span(trace);
+                        log!(target: chord::LOG_NETWORK, network.message_log_level(Level::Debug), "[Node #{}] Got client request from {:?}: {:?}", chord::short_id(node.get_id()), peer_addr, request.clone());
+                        let response_option = node.process_incoming_request(request);
+                        let sender = node.to_other_node();
+                        drop(node);
+                        Ok((socket, response_option.map(|response| Message::ResponseMessage { sender, response, trace: logging::current_trace() })))
+                    }
+                    Message::ResponseMessage { sender, response, trace } => {
+                        start_span(trace);
+                        log!(target: chord::LOG_NETWORK, network.message_log_level(Level::Debug), "[Node #{}] Got response from Node #{}: {:?}", chord::short_id(node.get_id()), chord::short_id(sender.get_id()), response.clone());
                         node.process_incoming_response(response);
                         drop(node);
-                        Ok(())
+                        Ok((socket, None))
+                    }
+                };
+                // The worker thread goes on with other connections
+                logging::set_trace(None);
+                reply.map(|(socket, reply)| (socket, reply.map(|reply| encode_message(reply_version, &reply)), lookup_slot))
+            })
+            .and_then(|(socket, reply, lookup_slot)| {
+                // Replies (e.g. pongs) are written back on the same connection
+                match reply {
+                    Some(mut reply_string) => {
+                        reply_string.push(MESSAGE_DELIMITER as char);
+                        Either::A(io::write_all(socket.into_inner(), reply_string).map(move |_| drop(lookup_slot)))
                     }
+                    None => Either::B(futures::future::ok(())),
                 }
             })
-            .then(|_| Ok(())); // Just discard the socket and buffer
+            .then(move |result| {
+                match result {
+                    Err(ref e) if e.kind() == ErrorKind::ConnectionReset =>
+                        warn!(target: chord::LOG_NETWORK, "Connection from {:?} was reset by the peer", peer_addr),
+                    Err(e) => warn!(target: chord::LOG_NETWORK, "Connection from {:?} failed: {}", peer_addr, e),
+                    Ok(()) => {}
+                }
+                // Just discard the socket and buffer
+                Ok(())
+            });
 
         // Spawn a new task that processes the socket:
         tokio::spawn(connection);
 
         Ok(())
     }).map_err(|e| println!("failed to accept socket; error = {:?}", e));
-    tokio::run(server);
+    let server = server.select2(shutdown).then(move |_| {
+        debug!(target: chord::LOG_NETWORK, "[Node #{}] Stopped listening on sockets: {:?}", chord::short_id(&id), listen_ips);
+        Ok(())
+    });
+    // Like tokio::run, but the worker threads log with the id of this node
+    let node_id = logging::current_node_id();
+    let mut runtime = tokio::runtime::Builder::new()
+        .after_start(move || logging::inherit_node_id(node_id))
+        .build()?;
+    runtime.spawn(server);
+    runtime.shutdown_on_idle().wait().unwrap();
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+
+    use super::super::storage;
+
+    // Answers a single connection with `reply` once the ping was read
+    fn answer_once(reply: String) -> SocketAddr {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut ping = String::new();
+            stream.read_to_string(&mut ping).unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+        });
+        addr
+    }
+
+    // Like `answer_once`, but takes `delay` to answer
+    fn answer_once_after(reply: String, delay: Duration) -> SocketAddr {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            thread::sleep(delay);
+            // The client may have given up already
+            let _ = stream.write_all(reply.as_bytes());
+        });
+        addr
+    }
+
+    fn sender() -> OtherNode {
+        OtherNode::new(BigInt::from(1), SocketAddr::from(([127, 0, 0, 1], 1)))
+    }
+
+    fn with_inflight_lookups(max_inflight_lookups: usize) -> Network {
+        Network::new(&ChordConfig { max_inflight_lookups, ..ChordConfig::default() })
+    }
+
+    fn find_successor() -> Request {
+        Request::FindSuccessor { id: BigInt::from(3), lookup: LookupContext::new() }
+    }
+
+    #[test]
+    fn lookups_beyond_the_cap_are_answered_busy() {
+        let network = with_inflight_lookups(3);
+        let admitted: Vec<LookupSlot> = (0..10).filter_map(|_| network.try_acquire_lookup_slot()).collect();
+        assert_eq!(admitted.len(), 3);
+        assert_eq!(network.shed_lookups(), 7);
+
+        let message = Message::ClientRequest { request: find_successor(), trace: None };
+        match shed_lookup(&network, sender(), message) {
+            Some(Message::ResponseMessage { response: Response::LookupBusy { node, request }, .. }) => {
+                assert_eq!(node, sender());
+                assert!(request.is_lookup());
+            }
+            reply => panic!("expected a busy reply, got {:?}", reply),
+        }
+
+        // Lookups within the cap are served again once others are answered
+        drop(admitted);
+        assert!(network.try_acquire_lookup_slot().is_some());
+        assert_eq!(network.shed_lookups(), 7);
+    }
+
+    #[test]
+    fn a_lookup_stays_in_flight_until_its_answer_is_sent() {
+        let network = with_inflight_lookups(1);
+        let slot = network.try_acquire_lookup_slot().unwrap();
+        assert!(network.try_acquire_lookup_slot().is_none());
+        let addr = answer_once(String::new());
+        let response = Response::FoundSuccessor { successor: sender(), lookup: LookupContext::new() };
+        network.send_answer(sender(), addr, response, Some(slot)).wait();
+        assert!(network.try_acquire_lookup_slot().is_some());
+    }
+
+    fn with_breaker(circuit_breaker_threshold: usize, cooldown_millis: u64) -> Network {
+        Network::new(&ChordConfig {
+            circuit_breaker_threshold,
+            circuit_breaker_cooldown: Duration::from_millis(cooldown_millis),
+            ..ChordConfig::default()
+        })
+    }
+
+    fn assert_breaker_open(network: &Network, addr: SocketAddr) {
+        match network.breakers.lock().unwrap().get(&addr) {
+            Some(Breaker::Open { .. }) => {}
+            breaker => panic!("the breaker of {} is {:?} instead of open", addr, breaker),
+        }
+    }
+
+    #[test]
+    fn a_breaker_opens_after_the_threshold_of_failed_connects_in_a_row() {
+        let network = with_breaker(3, 60000);
+        // Nothing listens there anymore
+        let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        network.record_connect(addr, false);
+        network.record_connect(addr, false);
+        // A successful connect starts the count over
+        network.record_connect(addr, true);
+        network.record_connect(addr, false);
+        network.record_connect(addr, false);
+        assert!(network.check_breaker(addr).is_ok());
+
+        let error = network.open_stream(addr, Some(Duration::from_millis(1000))).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        assert_breaker_open(&network, addr);
+        let error = network.open_stream(addr, Some(Duration::from_millis(1000))).unwrap_err();
+        assert!(error.to_string().contains("circuit breaker"), "connected despite the open breaker: {}", error);
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_breaker_again_and_a_successful_one_closes_it() {
+        let network = with_breaker(1, 50);
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        network.record_connect(addr, false);
+        assert!(network.check_breaker(addr).is_err());
+
+        // After the cooldown a single probe goes through
+        thread::sleep(Duration::from_millis(60));
+        assert!(network.check_breaker(addr).is_ok());
+        assert!(network.check_breaker(addr).is_err());
+        network.record_connect(addr, false);
+        assert_breaker_open(&network, addr);
+        assert!(network.check_breaker(addr).is_err());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(network.open_stream(addr, Some(Duration::from_millis(1000))).is_ok());
+        assert!(network.breakers.lock().unwrap().get(&addr).is_none());
+        assert!(network.check_breaker(addr).is_ok());
+    }
+
+    // Accepts every connection but never reads from it, so that large messages block their
+    // send thread, and counts the connections
+    fn blackhole() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counted = connections.clone();
+        thread::spawn(move || {
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                streams.push(stream);
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        (addr, connections)
+    }
+
+    #[test]
+    fn sends_beyond_the_send_threads_wait_for_one_to_become_free() {
+        let network = Network::new(&ChordConfig { max_send_threads: 2, ..ChordConfig::default() });
+        let (addr, connections) = blackhole();
+        // Far more than the socket buffers hold
+        let msg = "x".repeat(8 * 1024 * 1024);
+        let handles: Vec<SendHandle> = (0..3)
+            .map(|_| network.send_string_to_socket(addr, msg.clone(), Some(Duration::from_millis(500))))
+            .collect();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+        // The third one is sent once a write timed out, and not dropped
+        for handle in handles {
+            handle.wait();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    // A first node listening on a local port, until the returned sender fires
+    fn listening_node() -> (OtherNode, oneshot::Sender<()>, JoinHandle<()>) {
+        listening_node_on(&[IpAddr::from([127, 0, 0, 1])])
+    }
+
+    // Like `listening_node`, on the port of the first address on all of `addresses`
+    fn listening_node_on(addresses: &[IpAddr]) -> (OtherNode, oneshot::Sender<()>, JoinHandle<()>) {
+        listening_node_with(ChordConfig::default(), addresses)
+    }
+
+    fn listening_node_with(config: ChordConfig, addresses: &[IpAddr]) -> (OtherNode, oneshot::Sender<()>, JoinHandle<()>) {
+        let listeners = bind_listeners(0, addresses).unwrap();
+        let node = Node::new_first(listeners[0].local_addr().unwrap(), config);
+        let me = node.to_other_node();
+        let id = node.get_id().clone();
+        let (shutdown, stopped) = oneshot::channel();
+        let listening = thread::spawn(move || {
+            start_listening_on_socket(Arc::new(Mutex::new(node)), listeners, id, stopped).expect("listening failed");
+        });
+        (me, shutdown, listening)
+    }
+
+    // Sends `request` as a client and returns the response written back
+    fn client_request(addr: SocketAddr, request: Request) -> Response {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let mut msg = encode_message(PROTOCOL_VERSION, &Message::ClientRequest { request, trace: None });
+        msg.push(MESSAGE_DELIMITER as char);
+        stream.write_all(msg.as_bytes()).unwrap();
+        stream.shutdown(net::Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        match serde_json::from_str(reply.trim()) {
+            Ok(Message::ResponseMessage { response, .. }) => response,
+            reply => panic!("expected a response, got {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn msgs_to_any_listen_address_reach_the_same_node() {
+        // Any address of the loopback network is local, not only the first one
+        let (me, shutdown, listening) = listening_node_on(&[IpAddr::from([127, 0, 0, 1]), IpAddr::from([127, 0, 0, 2])]);
+        let first = *me.get_ip_addr();
+        let second = SocketAddr::new(IpAddr::from([127, 0, 0, 2]), first.port());
+        let network = Network::new(&ChordConfig::default());
+        for &addr in &[first, second] {
+            let (answered, _) = network.ping(addr, sender()).expect("no pong");
+            assert_eq!(answered, me);
+            // The first address is advertised
+            assert_eq!(answered.get_ip_addr(), &first);
+        }
+
+        let data = storage::make_hashed_key_value_pair("key".to_string(), "value", ChordConfig::default().key_space_bits);
+        let key_id = data.0.clone();
+        match client_request(first, Request::DHTStoreKey { data, moved: false }) {
+            Response::DHTStoredKey { .. } => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        match client_request(second, Request::DHTFindKey { key_id, lookup: LookupContext::new() }) {
+            Response::DHTFoundKey { data: (_, Some(entry)), .. } => assert_eq!(entry.get_value(), b"value"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        shutdown.send(()).unwrap();
+        listening.join().unwrap();
+    }
+
+    #[test]
+    fn the_listener_keeps_serving_after_connections_closed_or_reset_without_a_msg() {
+        let (me, shutdown, listening) = listening_node();
+        let addr = *me.get_ip_addr();
+
+        drop(net::TcpStream::connect(addr).unwrap());
+        let reset = net::TcpStream::connect(addr).unwrap();
+        TcpStreamExt::set_linger(&reset, Some(Duration::from_secs(0))).unwrap();
+        drop(reset);
+
+        let network = Network::new(&ChordConfig::default());
+        let (answered, _) = network.ping(addr, sender()).expect("no pong after the empty connections");
+        assert_eq!(answered, me);
+        shutdown.send(()).unwrap();
+        listening.join().unwrap();
+    }
+
+    // `sender()` as a node speaking `version`
+    fn sender_of_version(version: u16) -> OtherNode {
+        let mut sender = serde_json::to_value(sender()).unwrap();
+        sender["protocol_version"] = version.into();
+        serde_json::from_value(sender).unwrap()
+    }
+
+    // Pings `addr` from a node speaking `version` and returns the reply as sent
+    fn ping_from_version(addr: SocketAddr, version: u16) -> String {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let mut ping = encode_message(version, &Message::Ping { sender: sender_of_version(version) });
+        ping.push(MESSAGE_DELIMITER as char);
+        stream.write_all(ping.as_bytes()).unwrap();
+        stream.shutdown(net::Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        reply
+    }
+
+    #[test]
+    fn nodes_of_different_versions_speak_the_lower_one_and_older_than_the_minimum_are_rejected() {
+        let config = ChordConfig { min_protocol_version: 1, ..ChordConfig::default() };
+        let (me, shutdown, listening) = listening_node_with(config, &[IpAddr::from([127, 0, 0, 1])]);
+        let addr = *me.get_ip_addr();
+        let id_in_old_form = format!(r#""id":{}"#, serde_json::to_string(me.get_id()).unwrap());
+        let id_as_bytes = format!(r#""id":{}"#, serde_json::to_string(&id_encoding::id_to_bytes(me.get_id())).unwrap());
+
+        let pong = ping_from_version(addr, PROTOCOL_VERSION);
+        assert!(pong.contains(&id_as_bytes), "{}", pong);
+        let pong = ping_from_version(addr, 1);
+        assert!(pong.contains(&id_in_old_form), "{}", pong);
+        assert_eq!(ping_from_version(addr, 0), "");
+
+        // Messages to a peer use the version it advertised, the lowest one until it is known
+        let network = Network::new(&ChordConfig::default());
+        let ping = Message::Ping { sender: me.clone() };
+        assert!(network.encode_for(addr, &ping).contains(&id_in_old_form));
+        network.record_peer_version(&OtherNode::new(BigInt::from(1), addr));
+        assert!(network.encode_for(addr, &ping).contains(&id_as_bytes));
+        shutdown.send(()).unwrap();
+        listening.join().unwrap();
+    }
+
+    #[test]
+    fn a_msg_split_over_several_writes_is_read_up_to_its_delimiter() {
+        let (me, shutdown, listening) = listening_node();
+        let addr = *me.get_ip_addr();
+        let mut ping = encode_message(PROTOCOL_VERSION, &Message::Ping { sender: sender() });
+        ping.push(MESSAGE_DELIMITER as char);
+        let (first, second) = ping.split_at(ping.len() / 2);
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream.write_all(first.as_bytes()).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        stream.write_all(second.as_bytes()).unwrap();
+        // The pong comes without closing the connection, the delimiter ends the msg
+        let mut pong = String::new();
+        BufReader::new(stream).read_line(&mut pong).unwrap();
+        match serde_json::from_str(pong.trim()) {
+            Ok(Message::Pong { sender }) => assert_eq!(sender, me),
+            reply => panic!("expected a pong, got {:?}", reply),
+        }
+
+        // Cut off by closing the connection it is not answered
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        stream.write_all(first.as_bytes()).unwrap();
+        stream.shutdown(net::Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "");
+
+        let network = Network::new(&ChordConfig::default());
+        assert!(network.ping(addr, sender()).is_some());
+        shutdown.send(()).unwrap();
+        listening.join().unwrap();
+    }
+
+    #[test]
+    fn a_slow_store_is_waited_for_longer_than_a_slow_lookup() {
+        let network = Network::new(&ChordConfig {
+            lookup_request_timeout: Duration::from_millis(300),
+            store_request_timeout: Duration::from_millis(3000),
+            ..ChordConfig::default()
+        });
+        let delay = Duration::from_millis(1000);
+        let stored = Message::ResponseMessage { sender: sender(), response: Response::DHTStoredKey { key: "key".to_string() }, trace: None };
+        let addr = answer_once_after(encode_message(PROTOCOL_VERSION, &stored), delay);
+        let data = storage::make_hashed_key_value_pair("key".to_string(), "value", ChordConfig::default().key_space_bits);
+        match network.send_client_request(addr, Request::DHTStoreKey { data, moved: false }) {
+            Ok(Response::DHTStoredKey { key }) => assert_eq!(key, "key"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let found = Message::ResponseMessage { sender: sender(), response: Response::FoundSuccessor { successor: sender(), lookup: LookupContext::new() }, trace: None };
+        let addr = answer_once_after(encode_message(PROTOCOL_VERSION, &found), delay);
+        let started = Instant::now();
+        assert!(network.send_client_request(addr, find_successor()).is_err());
+        assert!(started.elapsed() < delay, "the lookup waited {:?}", started.elapsed());
+    }
+
+    // Reads every connection to its end, and hands the msgs on with the time they arrived
+    fn receive_all() -> (SocketAddr, mpsc::Receiver<(Instant, Message)>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received, msgs) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut msg = String::new();
+                stream.unwrap().read_to_string(&mut msg).unwrap();
+                if received.send((Instant::now(), serde_json::from_str(msg.trim()).unwrap())).is_err() {
+                    break;
+                }
+            }
+        });
+        (addr, msgs)
+    }
+
+    #[test]
+    fn migration_is_paced_at_the_rate_limit_and_lookups_pass_it() {
+        let network = Network::new(&ChordConfig { migration_rate_limit: 50_000, ..ChordConfig::default() });
+        let (addr, msgs) = receive_all();
+        // About 10 kB each, so one is sent every 0.2s
+        let requests: Vec<Request> = (0..5)
+            .map(|i| {
+                let data = storage::make_hashed_key_value_pair(format!("key{}", i), "x".repeat(10_000), ChordConfig::default().key_space_bits);
+                Request::DHTTakeOverKeys { data: vec![data] }
+            })
+            .collect();
+        let migration = network.send_migration(sender(), addr, requests);
+        thread::sleep(Duration::from_millis(100));
+        network.send_request(sender(), addr, find_successor());
+        migration.join().unwrap();
+
+        let received: Vec<(Instant, Message)> = (0..6).map(|_| msgs.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        let migrated: Vec<Instant> = received.iter()
+            .filter_map(|(arrived, msg)| match msg {
+                Message::RequestMessage { request: Request::DHTTakeOverKeys { .. }, .. } => Some(*arrived),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(migrated.len(), 5);
+        let took = migrated[4] - migrated[0];
+        assert!(took >= Duration::from_millis(700), "5 migration msgs of 10 kB at 50 kB/s arrived within {:?}", took);
+        // The lookup was not queued behind the migration
+        let lookup_position = received.iter().position(|(_, msg)| match msg {
+            Message::RequestMessage { request, .. } => request.is_lookup(),
+            _ => false,
+        });
+        assert!(lookup_position.expect("the lookup did not arrive") < 5);
+    }
+
+    #[test]
+    fn ping_measures_the_round_trip_time_to_a_pong() {
+        let network = Network::new(&ChordConfig::default());
+        let peer = OtherNode::new(BigInt::from(2), SocketAddr::from(([127, 0, 0, 1], 2)));
+        let addr = answer_once(encode_message(PROTOCOL_VERSION, &Message::Pong { sender: peer.clone() }));
+        let started = Instant::now();
+        let (answered, rtt) = network.ping(addr, sender()).expect("no pong");
+        assert_eq!(answered, peer);
+        assert!(rtt <= started.elapsed());
+    }
+
+    #[test]
+    fn ping_without_a_pong_has_no_round_trip_time() {
+        let network = Network::new(&ChordConfig::default());
+        let addr = answer_once(encode_message(PROTOCOL_VERSION, &Message::Kill));
+        assert_eq!(network.ping_rtt(addr, sender()), None);
+        // Nothing listens there anymore
+        assert_eq!(network.ping_rtt(addr, sender()), None);
+    }
+}
@@ -1,25 +1,46 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::str;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use futures::{Future, Stream};
+use futures::sync::mpsc;
+use log::Level;
+use num::ToPrimitive;
 use num_bigint::{BigInt, Sign};
 
 use super::chord;
-use super::fingertable::FingerTable;
-use super::network;
+use super::client::ChordClient;
+use super::config::{ChordConfig, MigrationStrategy};
+use super::encryption;
+use super::fingertable::{finger_start, FingerTable};
+use super::logging;
+use super::network::Network;
 use super::protocols::*;
+use super::storage;
 use super::storage::{DHTEntry, Storage};
 
 /// Simple representation of an external node in the network
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct OtherNode {
+    #[serde(with = "id_encoding")]
     id: BigInt,
     ip_addr: SocketAddr,
+    // Set while the node is shutting down and handing off its keys
+    #[serde(default)]
+    leaving: bool,
+    #[serde(default)]
+    protocol_version: u16,
 }
 
 impl OtherNode {
     pub fn new(id: BigInt, ip: SocketAddr) -> OtherNode {
-        OtherNode { id, ip_addr: ip }
+        OtherNode { id, ip_addr: ip, leaving: false, protocol_version: PROTOCOL_VERSION }
     }
 
     pub fn get_id(&self) -> &BigInt {
@@ -29,11 +50,203 @@ impl OtherNode {
     pub fn get_ip_addr(&self) -> &SocketAddr {
         &self.ip_addr
     }
+
+    pub fn is_leaving(&self) -> bool {
+        self.leaving
+    }
+
+    pub fn get_protocol_version(&self) -> u16 {
+        self.protocol_version
+    }
+}
+
+/// Nodes are identified by their id alone. The address, the leaving flag and the protocol
+/// version are details that may be outdated in the copy at hand, so a node seen with another
+/// address is still the same node.
+impl PartialEq for OtherNode {
+    fn eq(&self, other: &OtherNode) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for OtherNode {}
+
+impl Hash for OtherNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// Value being streamed to a local consumer, chunks are passed on in order
+#[derive(Clone)]
+struct ValueStream {
+    chunks: mpsc::UnboundedSender<Vec<u8>>,
+    next_index: usize,
+    // Chunks which arrived ahead of `next_index`
+    pending: HashMap<usize, Vec<u8>>,
+    // Evicted by `expire_outstanding_requests` unless the next chunk arrives before
+    deadline: Instant,
+}
+
+/// Namespace deletion of `delete_namespace` waiting for the request to come around the ring
+#[derive(Clone)]
+struct NamespaceDeletion {
+    result: mpsc::UnboundedSender<usize>,
+    // Evicted by `expire_outstanding_requests` unless the request came around before
+    deadline: Instant,
+}
+
+/// Change of the keys a node is responsible for within a watched range.
+/// `start..end` are ring ids (see `chord::key_placement_id`) and never wrap around.
+#[derive(Clone, Debug)]
+pub enum OwnershipEvent {
+    Gained { start: BigInt, end: BigInt },
+    Lost { start: BigInt, end: BigInt },
+}
+
+/// Range passed to `watch_range` with the part of it owned when the watch was last updated
+#[derive(Clone)]
+struct RangeWatch {
+    segments: Vec<(BigInt, BigInt)>,
+    owned: Vec<(BigInt, BigInt)>,
+    events: mpsc::UnboundedSender<OwnershipEvent>,
+}
+
+/// Routing knowledge of a node, written when it shuts down and read when it starts again,
+/// so that it can route right away instead of joining from scratch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoutingState {
+    /// Starting with the successor
+    pub successor_list: Vec<OtherNode>,
+    pub predecessor: Option<OtherNode>,
+    /// Finger `k` at index `k - 1`, see `fingertable::finger_start`
+    pub fingers: Vec<OtherNode>,
+}
+
+/// The live nodes of the ring, see `Node::list_members`
+#[derive(Clone, Debug)]
+pub struct MemberList {
+    /// Sorted by id
+    pub members: Vec<OtherNode>,
+    /// False if a node did not answer or the crawl stopped at `chord::CRAWL_MAX_NODES`, so that
+    /// members behind them may be missing
+    pub complete: bool,
+}
+
+/// Wakes the stabilize loop before its interval is over, shared by all clones
+#[derive(Clone, Default)]
+pub struct StabilizeTrigger {
+    // Set by `fire` until the waiting loop took it
+    fired: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl StabilizeTrigger {
+    pub fn fire(&self) {
+        let (fired, condvar) = &*self.fired;
+        *fired.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Sleeps up to `timeout` and returns whether `fire` ended the wait early
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (fired, condvar) = &*self.fired;
+        let deadline = Instant::now() + timeout;
+        let mut fired = fired.lock().unwrap();
+        while !*fired {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            fired = condvar.wait_timeout(fired, deadline - now).unwrap().0;
+        }
+        *fired = false;
+        true
+    }
+}
+
+/// Asks all threads of a node to end, shared by all clones and the `chord::NodeHandle`
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShutdownSignal {
+    pub fn request(&self) {
+        let (requested, condvar) = &*self.requested;
+        *requested.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.0.lock().unwrap()
+    }
+
+    /// Sleeps up to `timeout` and returns whether a shutdown was requested, which
+    /// ends the wait early. Maintenance loops sleep with it instead of `thread::sleep`.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (requested, condvar) = &*self.requested;
+        let deadline = Instant::now() + timeout;
+        let mut requested = requested.lock().unwrap();
+        while !*requested {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            requested = condvar.wait_timeout(requested, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    /// Blocks until a shutdown was requested
+    pub fn wait_requested(&self) {
+        let (requested, condvar) = &*self.requested;
+        let mut requested = requested.lock().unwrap();
+        while !*requested {
+            requested = condvar.wait(requested).unwrap();
+        }
+    }
+}
+
+/// The keys of a leaving node and where they may go, taken from the node under its lock so
+/// that the successors are pinged without holding it
+pub struct Handoff {
+    me: OtherNode,
+    successors: Vec<OtherNode>,
+    network: Network,
+    requests: Vec<Request>,
+}
+
+impl Handoff {
+    /// Sends the keys to the first live successor which is not leaving as well
+    pub fn send(self) -> Option<JoinHandle<()>> {
+        if let Some(target) = find_handoff_target(&self.network, self.me.clone(), &self.successors) {
+            info!(target: chord::LOG_DHT, "Shutting down, keys are taken over by node #{}.", chord::short_id(&target.id));
+            Some(self.network.send_migration(self.me, target.ip_addr, self.requests))
+        } else {
+            error!(target: chord::LOG_DHT, "No live successor to take over the keys found. Keys are lost!");
+            None
+        }
+    }
+}
+
+fn find_handoff_target(network: &Network, me: OtherNode, successors: &[OtherNode]) -> Option<OtherNode> {
+    for succ in successors {
+        if succ.id == me.id {
+            continue;
+        }
+        match network.ping(succ.ip_addr, me.clone()) {
+            Some((pong_sender, _)) if !pong_sender.is_leaving() => return Some(succ.clone()),
+            Some(_) => debug!(target: chord::LOG_STABILIZE, "Successor #{} is leaving as well, skipping it", chord::short_id(&succ.id)),
+            None => debug!(target: chord::LOG_STABILIZE, "Successor #{} is dead, skipping it", chord::short_id(&succ.id)),
+        }
+    }
+    None
 }
 
 /// Complete representation of internal node
 #[derive(Clone)]
 pub struct Node {
+    config: ChordConfig,
     id: BigInt,
     ip_addr: SocketAddr,
     finger_table: FingerTable,
@@ -41,37 +254,147 @@ pub struct Node {
     successor_list: Vec<OtherNode>,
     storage: Storage,
     joined: bool,
+    // Node claiming the id of my predecessor from a higher address, see `accept_notify`
+    predecessor_claim: Option<OtherNode>,
+    // Successor found while joining, joined once it answered `ChordConfig::join_pings` pings,
+    // see `chord::confirm_join_candidate`
+    join_candidate: Option<OtherNode>,
+    leaving: bool,
+    // End of the write warmup after joining, see `ChordConfig::write_warmup`
+    warmup_until: Option<Instant>,
+    // Last measured ping round trip time per peer id
+    rtts: HashMap<BigInt, Duration>,
+    // Nodes to ask for my successor while rejoining
+    bootstrap: Vec<SocketAddr>,
+    // Number of completed lookups per hop count
+    lookup_hops: HashMap<usize, usize>,
+    // Lookups that took more hops than `ChordConfig::hop_alert_factor` allows
+    degraded_lookups: usize,
+    // Value streams and namespace deletions evicted after getting no response in time
+    failed_requests: usize,
+    // Last reported number of owned keys per successor id
+    peer_keys_owned: HashMap<BigInt, usize>,
+    // Nodes I found dead myself and when, newest last, gossiped to my predecessor
+    recently_dead: Vec<(BigInt, Instant)>,
+    // Shared by all clones, so the maintenance loops see changes without locking the node
+    maintenance_paused: Arc<AtomicBool>,
+    // Shared by all clones like `maintenance_paused`, see `ChordConfig::read_only`
+    read_only: Arc<AtomicBool>,
+    stabilize_trigger: StabilizeTrigger,
+    shutdown: ShutdownSignal,
+    // Streamed lookups waiting for their chunks, by key id
+    // By the stream of their lookup, see `LookupContext::for_stream`
+    value_streams: HashMap<u64, ValueStream>,
+    range_watches: Vec<RangeWatch>,
+    // Namespace deletions walking the ring, by namespace
+    namespace_deletions: HashMap<String, NamespaceDeletion>,
+    network: Network,
+    // Key ranges pinned to a node regardless of its id, my own ones and those learned from my successor
+    reservations: Vec<KeyRangeReservation>,
+}
+
+/// The key ranges `config` reserves to `me`
+fn own_reservations(config: &ChordConfig, me: OtherNode) -> Vec<KeyRangeReservation> {
+    config.reserved_ranges.iter()
+        .map(|range| KeyRangeReservation::new(range.start.clone(), range.end.clone(), me.clone()))
+        .collect()
+}
+
+/// The configured id, or the hash of `addr` if there is none
+fn node_id(config: &ChordConfig, addr: SocketAddr) -> BigInt {
+    match config.node_id {
+        Some(ref id) => id.clone(),
+        None => chord::create_node_id(&config.identity.identity_string(addr)),
+    }
 }
 
 /// `Node` implementation
 impl Node {
-    pub fn new(node_ip_addr: SocketAddr) -> Node {
-        let id = chord::create_node_id(node_ip_addr);
+    pub fn new(node_ip_addr: SocketAddr, config: ChordConfig) -> Node {
+        let id = node_id(&config, node_ip_addr);
+        let reservations = own_reservations(&config, OtherNode::new(id.clone(), node_ip_addr));
+        let network = Network::new(&config);
+        let storage = Storage::new(&config);
+        let read_only = config.read_only;
         Node {
+            config,
             id: id.clone(),
             ip_addr: node_ip_addr,
             finger_table: FingerTable::new(id.clone()),
             predecessor: None,
             successor_list: Vec::with_capacity(chord::SUCCESSORLIST_SIZE),
-            storage: Storage::new(),
+            storage,
             joined: false,
+            predecessor_claim: None,
+            join_candidate: None,
+            leaving: false,
+            warmup_until: None,
+            rtts: HashMap::new(),
+            bootstrap: Vec::new(),
+            lookup_hops: HashMap::new(),
+            degraded_lookups: 0,
+            failed_requests: 0,
+            peer_keys_owned: HashMap::new(),
+            recently_dead: Vec::new(),
+            maintenance_paused: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(AtomicBool::new(read_only)),
+            stabilize_trigger: StabilizeTrigger::default(),
+            shutdown: ShutdownSignal::default(),
+            value_streams: HashMap::new(),
+            range_watches: Vec::new(),
+            namespace_deletions: HashMap::new(),
+            network,
+            reservations,
         }
     }
 
-    pub fn new_first(node_ip_addr: SocketAddr) -> Node {
-        let id = chord::create_node_id(node_ip_addr);
+    pub fn new_first(node_ip_addr: SocketAddr, config: ChordConfig) -> Node {
+        let id = node_id(&config, node_ip_addr);
         let successor = OtherNode::new(id.clone(), node_ip_addr);
+        let reservations = own_reservations(&config, successor.clone());
+        let network = Network::new(&config);
+        let storage = Storage::new(&config);
+        let read_only = config.read_only;
         Node {
+            config,
             id: id.clone(),
             ip_addr: node_ip_addr,
             finger_table: FingerTable::new_first(id.clone(), successor.clone()),
             predecessor: Some(OtherNode::new(id, node_ip_addr)),
             successor_list: vec![successor],
-            storage: Storage::new(),
+            storage,
             joined: true,
+            predecessor_claim: None,
+            join_candidate: None,
+            leaving: false,
+            warmup_until: None,
+            rtts: HashMap::new(),
+            bootstrap: Vec::new(),
+            lookup_hops: HashMap::new(),
+            degraded_lookups: 0,
+            failed_requests: 0,
+            peer_keys_owned: HashMap::new(),
+            recently_dead: Vec::new(),
+            maintenance_paused: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(AtomicBool::new(read_only)),
+            stabilize_trigger: StabilizeTrigger::default(),
+            shutdown: ShutdownSignal::default(),
+            value_streams: HashMap::new(),
+            range_watches: Vec::new(),
+            namespace_deletions: HashMap::new(),
+            network,
+            reservations,
         }
     }
 
+    pub fn get_config(&self) -> &ChordConfig {
+        &self.config
+    }
+
+    pub fn get_network(&self) -> &Network {
+        &self.network
+    }
+
     pub fn get_id(&self) -> &BigInt {
         &self.id
     }
@@ -83,7 +406,11 @@ impl Node {
         &self.finger_table
     }
 
+    /// The node itself until it joined, so that requests arriving before cannot find an empty finger table
     pub fn get_successor(&self) -> OtherNode {
+        if self.finger_table.length() == 0 {
+            return self.to_other_node();
+        }
         self.finger_table.get_successor()
     }
 
@@ -91,6 +418,30 @@ impl Node {
         &self.predecessor
     }
 
+    /// Called by `check_predecessor` with whether `predecessor` answered. A node claiming its id
+    /// from a higher address meanwhile takes over if it did not, as the node moved there.
+    /// Returns whether it did.
+    pub fn resolve_predecessor_claim(&mut self, predecessor: &OtherNode, alive: bool) -> bool {
+        let claim = match self.predecessor_claim.take() {
+            Some(claim) => claim,
+            None => return false,
+        };
+        match self.predecessor {
+            Some(ref pre) if *pre == claim && pre.ip_addr == predecessor.ip_addr => {}
+            // The predecessor changed since the check
+            _ => return false,
+        }
+        if alive {
+            warn!(target: chord::LOG_STABILIZE, "[Node #{}] Nodes at {} and {} share the id {}, keeping {} as Pre",
+                  chord::short_id(&self.id), predecessor.ip_addr, claim.ip_addr, chord::short_id(&claim.id), predecessor.ip_addr);
+            false
+        } else {
+            info!(target: chord::LOG_STABILIZE, "[Node #{}] Pre {} moved from {} to {}", chord::short_id(&self.id), chord::short_id(&claim.id), predecessor.ip_addr, claim.ip_addr);
+            self.set_predecessor(Some(claim));
+            true
+        }
+    }
+
     pub fn set_predecessor(&mut self, predecessor: Option<OtherNode>) {
         self.predecessor = predecessor.clone();
 
@@ -98,408 +449,3057 @@ impl Node {
         if let Some(pre) = predecessor {
             self.check_redistribute_dht_keys(&pre.id)
         }
+        self.update_range_watches();
     }
 
     pub fn get_successor_list(&self) -> &Vec<OtherNode> {
         &self.successor_list
     }
 
+    /// A copy of the successor list in ring order, the immediate successor first, each node
+    /// once. The stored list repeats nodes in rings smaller than `chord::SUCCESSORLIST_SIZE`;
+    /// if it goes around the whole ring, I am the last node of the copy.
+    pub fn successor_list(&self) -> Vec<OtherNode> {
+        let mut successors: Vec<OtherNode> = Vec::with_capacity(self.successor_list.len());
+        for succ in &self.successor_list {
+            if !successors.contains(succ) {
+                successors.push(succ.clone());
+            }
+        }
+        let ring_size = BigInt::from(chord::CHORD_RING_SIZE);
+        successors.sort_by_key(|succ| match chord::chord_abs(&succ.id, &self.id) {
+            ref distance if *distance == BigInt::from(0) => ring_size.clone(),
+            distance => distance,
+        });
+        successors
+    }
+
     pub fn get_storage(&self) -> &Storage {
         &self.storage
     }
 
-    pub fn is_joined(&self) -> bool {
-        self.joined
+    /// Returns a copy of all key/value pairs stored on this node
+    pub fn dump_storage(&self) -> Vec<(BigInt, DHTEntry)> {
+        self.storage.get_data_as_vec()
     }
 
-    /// Converts internal representation of node to the simpler representation OtherNode
-    pub fn to_other_node(&self) -> OtherNode {
-        OtherNode::new(self.id.clone(), self.ip_addr)
+    /// Forgets deleted keys after `ChordConfig::tombstone_grace_period`
+    pub fn expire_tombstones(&mut self) {
+        let expired = self.storage.expire_tombstones(self.config.tombstone_grace_period);
+        if expired > 0 {
+            debug!(target: chord::LOG_DHT, "[Node #{}] Forgot {} deleted keys", chord::short_id(&self.id), expired);
+        }
     }
 
-    pub fn update_successor_and_successor_list(&mut self, successor: OtherNode) {
-        //if self.finger_table.length() == 0  || &self.get_successor().id != &successor.id {
-        self.finger_table.set_successor(successor.clone());
-        let req = Request::GetSuccessorList;
-        network::send_request(self.to_other_node(),*successor.get_ip_addr(),req);
+    /// Rejects chunked uploads not completed within `chord::DHT_CHUNK_UPLOAD_TIMEOUT`
+    pub fn expire_chunked_uploads(&mut self) {
+        for key in self.storage.expire_chunked_uploads(chord::DHT_CHUNK_UPLOAD_TIMEOUT) {
+            self.storage.write_log_entry(format!("Upload of key '{}' timed out and was rejected", key));
+            info!(target: chord::LOG_DHT, "Upload of key '{}' timed out and was rejected", key);
+        }
     }
 
-    pub fn graceful_shutdown(&self) -> Option<JoinHandle<()>> {
-        if self.joined && !self.storage.is_data_empty() {
-            info!("Initializing shutdown, moving keys...");
-            let req = Request::DHTTakeOverKeys { data: self.storage.get_data_as_vec().clone() };
-            info!("Shutting down.");
-            Some(network::send_request(self.to_other_node(), *self.get_successor().get_ip_addr(), req))
-        } else {
-            None
+    /// Hands up to `chord::LAZY_MIGRATION_BATCH_SIZE` of the keys I am not responsible for anymore
+    /// over to their owner, see `MigrationStrategy::Lazy`
+    pub fn migrate_misplaced_keys(&mut self) {
+        if self.config.migration_strategy != MigrationStrategy::Lazy {
+            return;
+        }
+        let predecessor = match self.key_range_start() {
+            Some(predecessor) => predecessor,
+            None => return,
+        };
+        let misplaced: Vec<BigInt> = self.storage.get_key_ids()
+            .filter(|key_id| !self.is_my_key(predecessor.get_id(), key_id))
+            .take(chord::LAZY_MIGRATION_BATCH_SIZE)
+            .cloned()
+            .collect();
+        if !misplaced.is_empty() {
+            debug!(target: chord::LOG_DHT, "[Node #{}] Migrating {} misplaced keys", chord::short_id(&self.id), misplaced.len());
+        }
+        for key_id in misplaced {
+            self.migrate_key(&key_id);
         }
     }
 
-    fn check_redistribute_dht_keys(&mut self, pre_id: &BigInt) {
-        for (key, value) in self.storage.clone().get_data_as_iter() {
-            if !chord::is_my_key(&self.id, pre_id, key) {
-                let req = Request::DHTStoreKey { data: (key.clone(), value.clone()) };
-                network::send_request(self.to_other_node(), self.ip_addr, req);
-                self.storage.delete_key(key);
-            }
+    /// Sends the key to its owner and forgets it
+    fn migrate_key(&mut self, key_id: &BigInt) {
+        if let Some(entry) = self.storage.remove_moved_key(key_id) {
+            let req = Request::DHTStoreKey { data: (key_id.clone(), entry), moved: true };
+            self.network.send_request(self.to_other_node(), self.ip_addr, req);
         }
     }
 
-    fn closest_preceding_node(&self, id: BigInt) -> OtherNode {
-        let mut min_abs: BigInt = BigInt::new(Sign::Plus, vec![u32::max_value(); 5]);
-        let mut return_node: OtherNode = self.to_other_node();
-        for i in 0..self.finger_table.length() {
-            let entry = self.finger_table.get(i);
-            let finger_abs = chord::chord_abs(entry.get_node().get_id(), &id);
-            if finger_abs < min_abs {
-                min_abs = finger_abs;
-                return_node = entry.get_node().clone()
-            }
-        }
-        for i in 0..self.successor_list.len() {
-            let entry = &self.successor_list[i];
-            if entry.id == self.id {
-                break;
-            } else {
-                let finger_abs = chord::chord_abs(entry.get_id(), &id);
-                if finger_abs < min_abs {
-                    min_abs = finger_abs;
-                    return_node = entry.clone()
-                }
+    /// Asks each of the configured probe nodes for the successor of my id,
+    /// see `handle_ring_probed_response`
+    pub fn probe_rings(&self) {
+        for probe in &self.config.ring_probes {
+            if *probe != self.ip_addr {
+                let req = Request::ProbeRing { id: self.id.clone(), lookup: LookupContext::new() };
+                self.network.send_request(self.to_other_node(), *probe, req);
             }
         }
-        return_node
     }
 
-    pub fn process_incoming_request(&mut self, request: Request) -> Option<Response> {
-        match request {
-            Request::FindSuccessor { id } => {
-                debug!("[Node #{}] Request::FindSuccessor(id: {})", self.clone().id, id.clone());
-                Some(self.handle_find_successor_request(id))
-            }
-            Request::GetPredecessor => {
-                debug!("[Node #{}] Request::GetPredecessor", self.clone().id);
-                Some(self.handle_get_predecessor_request())
-            }
-            Request::Notify { node } => {
-                debug!("[Node #{}] Request::Notify(node: {})", self.clone().id, node.id.clone());
-                Some(self.handle_notify_request(node))
-            }
-            Request::FindSuccessorFinger { index, finger_id } => {
-                debug!("[Node #{}] Request::FindSuccessorFinger(index: {} finger_id: {})", self.clone().id, index, finger_id.clone());
-                Some(self.handle_find_successor_finger_request(index, finger_id))
-            }
-            Request::GetSuccessorList => {
-                debug!("[Node #{}] Request::GetSuccessorList", self.clone().id);
-                Some(self.handle_get_successor_list_request())
-            }
-            Request::DHTStoreKey { data } => {
-                info!("[Node #{}] Request::StoreKey(data: {:?})", self.clone().id, data.clone());
-                Some(self.handle_dht_store_key_request(data))
-            }
-            Request::DHTFindKey { key_id } => {
-                info!("[Node #{}] Request::FindKey(key_id: {})", self.clone().id, key_id.clone());
-                Some(self.handle_dht_find_key_request(key_id))
-            }
-            Request::DHTDeleteKey { key_id } => {
-                info!("[Node #{}] Request::DeleteKey(key_id {})", self.clone().id, key_id.clone());
-                Some(self.handle_dht_delete_key_request(key_id))
-            }
-            Request::DHTTakeOverKeys { data } => {
-                info!("[Node #{}] Request::DHTTakeOverKey(data {:?})", self.clone().id, data.clone());
-                self.handle_dht_take_over_keys(data);
-                None
-            }
-        }
+    pub fn is_joined(&self) -> bool {
+        self.joined
     }
 
-    pub fn process_incoming_response(&mut self, response: Response) {
-        match response {
-            Response::FoundSuccessor { successor } => {
-                debug!("[Node #{}] Response::FoundSuccessor(successor: {})", self.clone().id, successor.id.clone());
-                self.handle_found_successor_response(successor)
-            }
-            Response::AskFurther { next_node } => {
-                debug!("[Node #{}] Response::AskFurther(next_node: {}", self.clone().id, next_node.id.clone());
-                self.handle_ask_further_response(next_node)
-            }
-            Response::GetPredecessorResponse { predecessor } => {
-                debug!("[Node #{}] Response::GetPredecessorResponse(predecessor: {:?})", self.clone().id, predecessor.clone());
-                self.handle_get_predecessor_response(predecessor)
-            }
-            Response::NotifyResponse => {
-                debug!("Response::NotifyResponse");
-                self.handle_notify_response()
-            }
-            Response::FoundSuccessorFinger { index, finger_id, successor } => {
-                debug!("[Node #{}] Response::FoundSuccessorFinger(index: {}, finger_id: {}, successor: {})",
-                       self.clone().id, index, finger_id.clone(), successor.id.clone());
-                self.handle_found_successor_finger_response(index, finger_id, successor)
-            }
-            Response::AskFurtherFinger { index, finger_id, next_node } => {
-                debug!("[Node #{}] Response::AskFurtherFinger(next_node: {}", self.clone().id, next_node.id.clone());
-                self.handle_ask_further_finger_response(index, finger_id, next_node)
-            }
-            Response::GetSuccessorListResponse { successor_list } => {
-                debug!("[Node #{}] Response::GetSuccessorListResponse(successor_list: {:?}",
-                       self.clone().id, successor_list.clone());
-                self.handle_get_successor_list_response(successor_list)
-            }
-            Response::DHTStoredKey{ key} => {
-                debug!("[Node #{}] Response::DHTStoredKey", self.clone().id);
-                self.handle_dht_stored_key_response(key)
-            }
-            Response::DHTFoundKey { data } => {
-                debug!("[Node #{}] Response::DHTFoundKey(data: {:?})", self.clone().id, data.clone());
-                self.handle_dht_found_key_response(data)
-            }
-            Response::DHTDeletedKey { key_existed } => {
-                debug!("[Node #{}] Response::DHTDeletedKey(key_existed: {})", self.clone().id, key_existed);
-                self.handle_dht_deleted_key_response(key_existed)
-            }
-            Response::DHTAskFurtherStore { next_node, data } => {
-                info!("[Node #{}] Response::DHTAskFurtherStore(next_node: {}, data: {:?})",
-                      self.clone().id, next_node.get_id().clone(), data);
-                self.handle_dht_ask_further_store_response(next_node, data)
-            }
-            Response::DHTAskFurtherFind { next_node, key_id } => {
-                info!("[Node #{}] Response::DHTAskFurtherFind(next_node: {}, key_id: {})",
-                      self.clone().id, next_node.get_id().clone(), key_id.clone());
-                self.handle_dht_ask_further_find_response(next_node, key_id)
-            }
-            Response::DHTAskFurtherDelete { next_node, key_id } => {
-                info!("[Node #{}] Response::DHTAskFurtherDelete(next_node: {}, key_id: {})",
-                      self.clone().id, next_node.get_id().clone(), key_id.clone());
-                self.handle_dht_ask_further_delete_response(next_node, key_id)
-            }
-        }
+    pub fn get_rtt(&self, id: &BigInt) -> Option<&Duration> {
+        self.rtts.get(id)
     }
 
-    // REQUESTS
+    pub fn record_rtt(&mut self, id: BigInt, rtt: Duration) {
+        self.rtts.insert(id, rtt);
+    }
 
-    fn handle_find_successor_request(&self, id: BigInt) -> Response {
-        if chord::is_in_interval(&self.id, self.get_successor().get_id(), &id) {
-            Response::FoundSuccessor { successor: self.get_successor().clone() }
-        } else if let Some(pre) = self.predecessor.clone() {
-            if chord::is_in_interval(pre.get_id(), &self.id, &id) {
-                Response::FoundSuccessor { successor: self.to_other_node() }
-            } else {
-                Response::AskFurther { next_node: self.closest_preceding_node(id) }
-            }
-        } else {
-            Response::AskFurther { next_node: self.closest_preceding_node(id) }
+    /// Remembers that the node with `id` did not answer me, see `chord::DEAD_GOSSIP_SIZE`
+    pub fn record_dead(&mut self, id: BigInt) {
+        self.recently_dead.retain(|(dead_id, _)| *dead_id != id);
+        self.recently_dead.push((id, Instant::now()));
+        if self.recently_dead.len() > chord::DEAD_GOSSIP_SIZE {
+            self.recently_dead.remove(0);
         }
     }
 
-    fn handle_get_predecessor_request(&self) -> Response {
-        Response::GetPredecessorResponse {
-            predecessor: self.predecessor.clone()
-        }
+    /// The ids of the nodes I found dead within `chord::DEAD_GOSSIP_TTL`
+    fn dead_gossip(&mut self) -> Vec<BigInt> {
+        self.recently_dead.retain(|(_, found)| found.elapsed() < chord::DEAD_GOSSIP_TTL);
+        self.recently_dead.iter().map(|(id, _)| id.clone()).collect()
     }
 
-    fn handle_notify_request(&mut self, node: OtherNode) -> Response {
-        match &self.predecessor {
-            None => {
-                debug!("[Node #{}] Notify: Had no Pre. Pre is now: {}", self.id, node.id);
-                self.set_predecessor(Some(node))
-            }
-            Some(pre) => {
-                debug!("[Node #{}] Notify: Current Pre: {}, possible new Pre: {}. Successor is: {}", self.id, pre.id, node.id, self.get_successor().id);
-                if pre.id != node.id && chord::is_in_interval(pre.get_id(), &self.id, node.get_id()) {
-                    self.set_predecessor(Some(node));
-                    debug!("[Node #{}] Took new Pre: {}", self.id, self.predecessor.clone().unwrap().id);
-                }
-            }
+    /// Drops the nodes my successor found dead from my successor list before my own pings
+    /// reach them. My successor answered, so it stays, and the list is never left empty.
+    fn prune_gossiped_dead(&mut self, recently_dead: &[BigInt]) {
+        if recently_dead.is_empty() {
+            return;
+        }
+        let successor = self.get_successor();
+        let before = self.successor_list.len();
+        self.successor_list.retain(|succ| *succ == successor || !recently_dead.contains(&succ.id));
+        if self.successor_list.len() < before {
+            debug!(target: chord::LOG_STABILIZE, "[Node #{}] Pruned {} successors my successor found dead", chord::short_id(&self.id), before - self.successor_list.len());
         }
+    }
 
-        Response::NotifyResponse
+    pub fn get_lookup_hops(&self) -> &HashMap<usize, usize> {
+        &self.lookup_hops
     }
 
-    fn handle_find_successor_finger_request(&self, index: usize, finger_id: BigInt) -> Response {
-        if chord::is_in_interval(&self.id, self.get_successor().get_id(), &finger_id) {
-            Response::FoundSuccessorFinger { index, finger_id, successor: self.get_successor().clone() }
-        } else {
-            Response::AskFurtherFinger { index, finger_id, next_node: self.get_successor() }
-        }
+    pub fn get_degraded_lookups(&self) -> usize {
+        self.degraded_lookups
     }
 
-    fn handle_get_successor_list_request(&self) -> Response {
-        Response::GetSuccessorListResponse { successor_list: self.successor_list.clone() }
+    pub fn get_failed_requests(&self) -> usize {
+        self.failed_requests
     }
 
-    fn handle_dht_store_key_request(&mut self,
-                                    data: (BigInt, DHTEntry)) -> Response {
-        if let Some(predecessor) = self.predecessor.clone() {
-            // I am responsible for the key
-            if chord::is_my_key(&self.id, predecessor.get_id(), &data.0) {
-                self.storage.store_key(data.clone());
-                Response::DHTStoredKey{
-                    key: data.1.get_key().to_owned()
-                }
-            } else {
-                Response::DHTAskFurtherStore {
-                    next_node: self.closest_preceding_node(data.0.clone()),
-                    data,
-                }
-            }
-        } else {
-            Response::DHTAskFurtherStore {
-                next_node: self.closest_preceding_node(data.0.clone()),
-                data,
-            }
+    /// Value streams and namespace deletions waiting for responses
+    pub fn outstanding_requests(&self) -> usize {
+        self.value_streams.len() + self.namespace_deletions.len()
+    }
+
+    /// Evicts the value streams and namespace deletions which got no response within
+    /// `chord::LOOKUP_TIMEOUT`, ending their streams and futures, and counts them as failed.
+    /// Called by the stabilize loop, so that lost responses cannot let them pile up.
+    pub fn expire_outstanding_requests(&mut self) {
+        let now = Instant::now();
+        let outstanding = self.outstanding_requests();
+        self.value_streams.retain(|_, stream| stream.deadline > now);
+        self.namespace_deletions.retain(|_, deletion| deletion.deadline > now);
+        let expired = outstanding - self.outstanding_requests();
+        if expired > 0 {
+            self.failed_requests += expired;
+            warn!(target: chord::LOG_DHT, "[Node #{}] {} outstanding request(s) got no response within {:?} and failed",
+                  chord::short_id(&self.id), expired, chord::LOOKUP_TIMEOUT);
         }
     }
 
-    fn handle_dht_find_key_request(&self, key_id: BigInt) -> Response {
-        if let Some(predecessor) = self.predecessor.clone() {
-            // I am responsible for the key
-            if chord::is_my_key(&self.id, predecessor.get_id(), &key_id) {
-                let value_option = self.storage.get_key(&key_id);
-                Response::DHTFoundKey { data: (key_id, value_option.cloned()) }
-            } else {
-                Response::DHTAskFurtherFind {
-                    next_node: self.closest_preceding_node(key_id.clone()),
-                    key_id,
-                }
-            }
-        } else {
-            Response::DHTAskFurtherFind {
-                next_node: self.closest_preceding_node(key_id.clone()),
-                key_id,
-            }
+    /// Counts the hops of a completed lookup and warns with `RoutingDegraded` if it took more
+    /// than `ChordConfig::hop_alert_factor` times log2 of the estimated ring size
+    fn record_lookup(&mut self, lookup: &LookupContext) {
+        *self.lookup_hops.entry(lookup.get_hops()).or_insert(0) += 1;
+        if self.config.hop_alert_factor <= 0.0 {
+            return;
+        }
+        let ring_size = cmp::max(self.estimate_ring_size(), 2);
+        let max_hops = self.config.hop_alert_factor * (ring_size as f64).log2();
+        if lookup.get_hops() as f64 > max_hops {
+            self.degraded_lookups += 1;
+            warn!(target: chord::LOG_FIX_FINGERS, "[Node #{}] RoutingDegraded: lookup took {} hops, expected at most {:.1} for about {} nodes. The finger table may be stale.",
+                  chord::short_id(&self.id), lookup.get_hops(), max_hops, ring_size);
         }
     }
 
-    fn handle_dht_delete_key_request(&mut self, key_id: BigInt) -> Response {
-        if let Some(predecessor) = self.predecessor.clone() {
-            // I am responsible for the key
-            if chord::is_my_key(&self.id, predecessor.get_id(), &key_id) {
-                let key_existed = self.storage.delete_key(&key_id).is_some();
-                Response::DHTDeletedKey { key_existed }
-            } else {
-                Response::DHTAskFurtherDelete {
-                    next_node: self.closest_preceding_node(key_id.clone()),
-                    key_id,
-                }
+    /// Counts the ring members exactly if the successor list wraps around to me. Otherwise the
+    /// ring size is extrapolated from the share of the ring the successor list covers.
+    pub fn estimate_ring_size(&self) -> usize {
+        let mut distinct: Vec<&BigInt> = Vec::new();
+        for succ in &self.successor_list {
+            if succ.id == self.id {
+                return distinct.len() + 1;
             }
-        } else {
-            Response::DHTAskFurtherDelete {
-                next_node: self.closest_preceding_node(key_id.clone()),
-                key_id,
+            if !distinct.contains(&&succ.id) {
+                distinct.push(&succ.id);
             }
         }
+        match self.successor_list.last() {
+            Some(last) => {
+                let covered = chord::chord_abs(&last.id, &self.id);
+                (BigInt::from(distinct.len()) * chord::CHORD_RING_SIZE / covered).to_usize().unwrap_or(usize::max_value())
+            }
+            None => 1,
+        }
     }
 
-    fn handle_dht_take_over_keys(&mut self, data: Vec<(BigInt, DHTEntry)>) {
-        for entry in data {
-            self.storage.store_key(entry);
-        }
+    /// Looks up `key` and yields the chunks of its value as they arrive, so large values can be
+    /// consumed before they are transferred completely. The stream ends after the last chunk,
+    /// right away if the key does not exist and on lookup timeouts. Chunks are yielded as stored,
+    /// i.e. still encrypted if a value encryption key is used.
+    pub fn find_key_stream(&mut self, key: &str) -> impl Stream<Item = Vec<u8>, Error = ()> {
+        let key_id = chord::create_key_id(key, self.config.key_space_bits);
+        let (chunks, stream) = mpsc::unbounded();
+        let stream_id = rand::random();
+        self.value_streams.insert(stream_id, ValueStream { chunks, next_index: 0, pending: HashMap::new(), deadline: Instant::now() + chord::LOOKUP_TIMEOUT });
+        let req = Request::DHTFindKeyStream { key_id, requester: self.to_other_node(), lookup: LookupContext::new().for_stream(stream_id) };
+        self.network.send_request(self.to_other_node(), self.ip_addr, req);
+        stream
     }
 
+    /// Stores `value` under its content key (see `storage::content_key`) unless the key exists
+    /// already, and returns the key id as the address to find it by. Storing a value twice
+    /// leaves one entry. Values are sealed with the value encryption key like those of the menu,
+    /// the address is derived from the plain value.
+    pub fn store_content(&self, value: &[u8]) -> BigInt {
+        let sealed = match &self.config.value_encryption_key {
+            Some(passphrase) => encryption::encrypt_value(passphrase, value),
+            None => value.to_vec(),
+        };
+        let data = storage::make_hashed_key_value_pair(storage::content_key(value), sealed, self.config.key_space_bits);
+        let key_id = data.0.clone();
+        self.network.send_request(self.to_other_node(), self.ip_addr, Request::DHTStoreIfAbsent { data });
+        key_id
+    }
 
-    // RESPONSES
+    /// Looks up the value `store_content` returned `address` for, the value gets logged
+    pub fn find_content(&self, address: &BigInt) {
+        let req = Request::DHTFindKey { key_id: address.clone(), lookup: LookupContext::new() };
+        self.network.send_request(self.to_other_node(), self.ip_addr, req);
+    }
 
-    fn handle_found_successor_response(&mut self, successor: OtherNode) {
-        debug!("Found my new successor: node #{}", successor.id.clone());
-        self.update_successor_and_successor_list(successor);
-        if !self.joined {
-            self.joined = true;
+    /// Deletes the keys of `namespace` (see `storage::NAMESPACE_SEPARATOR`) on every node of the
+    /// ring and yields how many were deleted. The request walks the ring along the successors and
+    /// does not finish if it gets lost on the way or takes longer than `chord::LOOKUP_TIMEOUT`.
+    pub fn delete_namespace(&mut self, namespace: &str) -> impl Future<Item = usize, Error = ()> {
+        let (result, receiver) = mpsc::unbounded();
+        self.namespace_deletions.insert(namespace.to_string(), NamespaceDeletion { result, deadline: Instant::now() + chord::LOOKUP_TIMEOUT });
+        let deleted = self.delete_namespace_keys(namespace);
+        let req = Request::DHTDeleteNamespace {
+            namespace: namespace.to_string(),
+            requester: self.to_other_node(),
+            deleted,
+            lookup: LookupContext::new(),
+        };
+        self.network.send_request(self.to_other_node(), self.get_successor().ip_addr, req);
+        receiver.into_future()
+            .map_err(|_| ())
+            .and_then(|(deleted, _)| deleted.ok_or(()))
+    }
+
+    fn delete_namespace_keys(&mut self, namespace: &str) -> usize {
+        if self.is_read_only() {
+            warn!(target: chord::LOG_DHT, "[Node #{}] Read-only, keeping the keys of namespace '{}'", chord::short_id(&self.id), namespace);
+            return 0;
+        }
+        let deleted = self.storage.delete_namespace(namespace);
+        if deleted > 0 {
+            self.storage.write_log_entry(format!("Deleted {} keys of namespace '{}'", deleted, namespace));
         }
+        deleted
     }
 
-    fn handle_ask_further_response(&mut self, next_node: OtherNode) {
-        debug!("Did not get successor yet, asking node #{} now...", next_node.id);
-        let req = Request::FindSuccessor { id: self.id.clone() };
-        network::send_request(self.to_other_node(),next_node.ip_addr, req);
+    /// Reports which parts of the ring ids from `start` inclusive to `end` exclusive this node
+    /// gains or loses responsibility for, starting with a `Gained` event for the part it owns now.
+    /// `start == end` watches the whole ring.
+    pub fn watch_range(&mut self, start: BigInt, end: BigInt) -> impl Stream<Item = OwnershipEvent, Error = ()> {
+        let (events, watch) = mpsc::unbounded();
+        let segments = chord::ring_segments(&start, &end);
+        self.range_watches.push(RangeWatch { segments, owned: Vec::new(), events });
+        self.update_range_watches();
+        watch
     }
 
-    fn handle_get_predecessor_response(&mut self, predecessor: Option<OtherNode>) {
-        if let Some(predecessor) = predecessor {
-            // maybe update my successor:
-            if predecessor.get_id() != &self.id &&
-                chord::is_in_interval(&self.id, self.get_successor().get_id(), predecessor.get_id()) {
-                debug!("[Node #{}] GetPreResp: Had succ #{}, got pre #{}, new succ: #{}", self.id.clone(), self.get_successor().id.clone(), predecessor.id.clone(), predecessor.id.clone());
-                self.update_successor_and_successor_list(predecessor);
+    /// The ring segments of the keys I am responsible for, `None` while this is unknown because
+    /// the predecessor has not been found yet
+    fn owned_segments(&self) -> Option<Vec<(BigInt, BigInt)>> {
+        if self.config.observer || self.leaving || !self.joined {
+            return Some(Vec::new());
+        }
+        self.key_range_start().map(|pre| chord::owned_segments(&pre.id, &self.id))
+    }
+
+    /// Sends the ownership changes since the last update to the range watches,
+    /// watches whose receiver was dropped are removed
+    fn update_range_watches(&mut self) {
+        let owned = match self.owned_segments() {
+            Some(owned) => owned,
+            None => return,
+        };
+        let range_watches = self.range_watches.drain(..).filter_map(|mut watch| {
+            let now_owned = chord::intersect_segments(&watch.segments, &owned);
+            let gained = chord::subtract_segments(&now_owned, &watch.owned)
+                .into_iter()
+                .map(|(start, end)| OwnershipEvent::Gained { start, end });
+            let lost = chord::subtract_segments(&watch.owned, &now_owned)
+                .into_iter()
+                .map(|(start, end)| OwnershipEvent::Lost { start, end });
+            for event in lost.chain(gained) {
+                if watch.events.unbounded_send(event).is_err() {
+                    return None;
+                }
             }
+            watch.owned = now_owned;
+            Some(watch)
+        }).collect();
+        self.range_watches = range_watches;
+    }
+
+    pub fn keys_owned(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Asks the successors how many keys they own, the answers are used by the next `check_load`
+    pub fn request_keys_owned(&self) {
+        for succ in self.successor_list.iter().filter(|succ| succ.id != self.id) {
+            self.network.send_request(self.to_other_node(), succ.ip_addr, Request::GetKeysOwned);
         }
-        let req = Request::Notify { node: self.to_other_node() };
-        network::send_request(self.to_other_node(), self.get_successor().ip_addr, req);
     }
 
-    fn handle_notify_response(&self) {}
+    /// Estimates the fair share of keys as the mean over this node and its successors.
+    /// Returns the fair share if this node owns more than `chord::LOAD_IMBALANCE_FACTOR` times of it.
+    pub fn check_load(&mut self) -> Option<f64> {
+        let successor_ids: Vec<&BigInt> = self.successor_list.iter().map(|succ| &succ.id).collect();
+        self.peer_keys_owned.retain(|id, _| successor_ids.contains(&id));
+        let total: usize = self.keys_owned() + self.peer_keys_owned.values().sum::<usize>();
+        let fair_share = total as f64 / (self.peer_keys_owned.len() + 1) as f64;
+        if self.peer_keys_owned.is_empty() || self.keys_owned() < chord::LOAD_IMBALANCE_MIN_KEYS ||
+            (self.keys_owned() as f64) <= fair_share * chord::LOAD_IMBALANCE_FACTOR {
+            return None;
+        }
+        warn!(target: chord::LOG_DHT, "[Node #{}] LoadImbalance: owning {} keys, fair share is about {:.1}", chord::short_id(&self.id), self.keys_owned(), fair_share);
+        self.storage.write_log_entry(format!("LoadImbalance: owning {} keys, fair share is about {:.1}", self.keys_owned(), fair_share));
+        Some(fair_share)
+    }
 
-    fn handle_found_successor_finger_response(&mut self, index: usize, finger_id: BigInt, successor: OtherNode) {
-        debug!("Found node for finger_id {}: node #{}", finger_id.clone(), successor.id.clone());
+    /// Forgets successor, predecessor and fingers and joins the ring again via one of the
+    /// `bootstrap` nodes. Id and stored keys are kept. Until joined again, the node acts
+    /// as its own successor.
+    pub fn rejoin(&mut self, bootstrap: &[SocketAddr]) {
+        info!(target: chord::LOG_STABILIZE, "[Node #{}] Rejoining via {:?}...", chord::short_id(&self.id), bootstrap);
+        self.joined = false;
+        self.join_candidate = None;
+        self.predecessor = None;
+        self.finger_table = FingerTable::new_first(self.id.clone(), self.to_other_node());
+        self.successor_list = Vec::with_capacity(chord::SUCCESSORLIST_SIZE);
+        self.rtts.clear();
+        self.bootstrap = bootstrap.to_vec();
+        self.update_range_watches();
+        self.send_join_requests();
+    }
 
-        self.finger_table.put(index, finger_id, successor);
-        if index == chord::FINGERTABLE_SIZE - 1 {
-            //self.finger_table.print(self.id.clone());
+    /// Asks every bootstrap node for my successor, the first answer lets me join
+    pub fn send_join_requests(&self) {
+        for addr in &self.bootstrap {
+            chord::join(&self.network, self.id.clone(), self.to_other_node(), *addr);
         }
     }
 
-    fn handle_ask_further_finger_response(&mut self, index: usize, finger_id: BigInt, next_node: OtherNode) {
-        debug!("Did not get entry for finger {} (#{}) yet, asking node #{} now...", finger_id.clone(), index, next_node.id);
-        let req = Request::FindSuccessorFinger { index, finger_id };
+    /// Lets stabilize, fix_fingers, check_predecessor and check_load skip their work until resumed
+    pub fn pause_maintenance(&self) {
+        info!(target: chord::LOG_STABILIZE, "[Node #{}] Maintenance paused", chord::short_id(&self.id));
+        self.maintenance_paused.store(true, Ordering::SeqCst);
+    }
 
-        network::send_request(self.to_other_node(),next_node.ip_addr, req);
+    pub fn resume_maintenance(&self) {
+        info!(target: chord::LOG_STABILIZE, "[Node #{}] Maintenance resumed", chord::short_id(&self.id));
+        self.maintenance_paused.store(false, Ordering::SeqCst);
     }
 
-    fn handle_get_successor_list_response(&mut self, successor_list: Vec<OtherNode>) {
-        let mut new_successor_list = vec![self.get_successor().clone()];
-        if successor_list.len() == chord::SUCCESSORLIST_SIZE {
-            new_successor_list.append(&mut successor_list.clone()[..(successor_list.len() - 1)].to_owned())
+    pub fn is_maintenance_paused(&self) -> bool {
+        self.maintenance_paused.load(Ordering::SeqCst)
+    }
+
+    /// See `ChordConfig::read_only`
+    pub fn set_read_only(&self, read_only: bool) {
+        info!(target: chord::LOG_DHT, "[Node #{}] {}", chord::short_id(&self.id), if read_only { "Read-only" } else { "Writable again" });
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    /// Runs the next stabilize cycle right away instead of after the current interval
+    pub fn trigger_stabilize(&self) {
+        info!(target: chord::LOG_STABILIZE, "[Node #{}] Stabilize triggered", chord::short_id(&self.id));
+        self.stabilize_trigger.fire();
+    }
+
+    pub fn get_stabilize_trigger(&self) -> &StabilizeTrigger {
+        &self.stabilize_trigger
+    }
+
+    /// Shares the shutdown signal of the `chord::NodeHandle`, set before the node's threads start
+    pub fn set_shutdown_signal(&mut self, shutdown: ShutdownSignal) {
+        self.shutdown = shutdown;
+    }
+
+    pub fn get_shutdown_signal(&self) -> &ShutdownSignal {
+        &self.shutdown
+    }
+
+    /// Marks the node as leaving, which is advertised to other nodes from now on
+    pub fn set_leaving(&mut self) {
+        self.leaving = true;
+        self.update_range_watches();
+    }
+
+    /// Converts internal representation of node to the simpler representation OtherNode
+    pub fn to_other_node(&self) -> OtherNode {
+        OtherNode { id: self.id.clone(), ip_addr: self.ip_addr, leaving: self.leaving, protocol_version: PROTOCOL_VERSION }
+    }
+
+    pub fn update_successor_and_successor_list(&mut self, successor: OtherNode) {
+        //if self.finger_table.length() == 0  || &self.get_successor().id != &successor.id {
+        self.finger_table.set_successor(successor.clone());
+        let req = Request::GetSuccessorList;
+        self.network.send_request(self.to_other_node(),*successor.get_ip_addr(),req);
+    }
+
+    /// The handoff of all keys to the first live successor that is not leaving itself, see
+    /// `Handoff::send`. Call `set_leaving` on the shared node first, so that neighbors which are
+    /// leaving at the same time skip this node as well.
+    pub fn prepare_handoff(&self) -> Option<Handoff> {
+        if self.joined && !self.storage.is_data_empty() {
+            info!(target: chord::LOG_DHT, "Initializing shutdown, moving keys...");
+            Some(Handoff {
+                me: self.to_other_node(),
+                successors: self.successor_list.clone(),
+                network: self.network.clone(),
+                requests: self.handoff_requests(self.storage.get_data_as_vec()),
+            })
         } else {
-            new_successor_list.append(&mut successor_list.clone())
+            None
+        }
+    }
+
+    /// A single DHTTakeOverKeys request, or with a `migration_rate_limit` one per
+    /// `ChordConfig::chunk_size` bytes of values, so that they can be paced
+    fn handoff_requests(&self, data: Vec<(BigInt, DHTEntry)>) -> Vec<Request> {
+        if self.config.migration_rate_limit == 0 {
+            return vec![Request::DHTTakeOverKeys { data }];
+        }
+        let mut requests = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for entry in data {
+            batch_size += entry.1.value_size();
+            batch.push(entry);
+            if batch_size >= self.config.chunk_size {
+                requests.push(Request::DHTTakeOverKeys { data: batch.split_off(0) });
+                batch_size = 0;
+            }
+        }
+        if !batch.is_empty() {
+            requests.push(Request::DHTTakeOverKeys { data: batch });
+        }
+        requests
+    }
+
+    fn check_redistribute_dht_keys(&mut self, pre_id: &BigInt) {
+        if self.config.migration_strategy == MigrationStrategy::Lazy {
+            // Moved in batches by `migrate_misplaced_keys`
+            return;
+        }
+        let mut requests = Vec::new();
+        for (key, value) in self.storage.clone().get_data_as_iter() {
+            if !self.is_my_key(pre_id, key) {
+                requests.push(Request::DHTStoreKey { data: (key.clone(), value), moved: true });
+                self.storage.remove_moved_key(key);
+            }
+        }
+        if !requests.is_empty() {
+            self.network.send_migration(self.to_other_node(), self.ip_addr, requests);
+        }
+    }
+
+    /// The node my keys start after, i.e. my predecessor. Alone in the ring I own every key even
+    /// while the predecessor is unknown (e.g. after the last other node died), as asking further
+    /// would only route the request back to me.
+    fn key_range_start(&self) -> Option<OtherNode> {
+        match &self.predecessor {
+            None if self.joined && self.is_single_node_ring() => Some(self.to_other_node()),
+            predecessor => predecessor.clone(),
+        }
+    }
+
+    /// Called when all successors before me in my successor list are dead. In a two-node ring my
+    /// predecessor was my successor as well, so it is dead too and I own the whole ring right away,
+    /// instead of forwarding its keys to it until `check_predecessor` gave up on it.
+    pub fn become_single_node_ring(&mut self, dead_successors: &[OtherNode]) {
+        let predecessor_dead = match self.predecessor {
+            Some(ref pre) => pre.id != self.id && dead_successors.contains(pre),
+            None => false,
         };
-        self.successor_list = new_successor_list;
+        if predecessor_dead {
+            info!(target: chord::LOG_STABILIZE, "[Node #{}] Predecessor #{} was my only successor and is dead, owning the whole ring",
+                  chord::short_id(&self.id), chord::short_id(&self.predecessor.as_ref().unwrap().id));
+            self.set_predecessor(None);
+        }
     }
 
-    fn handle_dht_stored_key_response(&mut self, key: String) {
-        self.storage.write_log_entry(format!("Key '{}' stored", key));
-        debug!("Key '{}' stored", key);
+    /// Whether I am my own successor, i.e. the only node of the ring I know of
+    fn is_single_node_ring(&self) -> bool {
+        self.get_successor().id == self.id
     }
 
-    fn handle_dht_found_key_response(&mut self, data: (BigInt, Option<DHTEntry>)) {
-        if let Some(dht_entry) = data.1.clone() {
-            self.storage.write_log_entry(format!("Value for key {} (id: {}) is {}", dht_entry.get_key(), data.0, dht_entry.get_value()));
-            debug!("Value for key '{}' (id: {}) is '{}'", dht_entry.get_key(), data.0, dht_entry.get_value());
-        } else {
-            self.storage.write_log_entry(format!("No value for key_id {} found in the network", data.0));
-            debug!("No value for key_id '{}' found in the network", data.0)
+    /// A reserved key belongs to the node it is reserved to. Otherwise an observer node routes but never owns keys.
+    fn is_my_key(&self, pre_id: &BigInt, key_id: &BigInt) -> bool {
+        match self.reserved_owner(key_id) {
+            Some(owner) => owner.id == self.id,
+            None => !self.config.observer && chord::is_my_key(&self.id, pre_id, &chord::key_placement_id(key_id)),
         }
     }
 
-    fn handle_dht_deleted_key_response(&mut self, key_existed: bool) {
-        if key_existed {
-            self.storage.write_log_entry("Key deleted".to_string());
-            info!("Key deleted");
-        } else {
-            self.storage.write_log_entry("Tried to delete key but the key was not present in the network".to_string());
-            debug!("Tried to delete key but the key was not present in the network");
+    fn reserved_owner(&self, key_id: &BigInt) -> Option<&OtherNode> {
+        let placement_id = chord::key_placement_id(key_id);
+        self.reservations.iter()
+            .find(|reservation| reservation.contains(&placement_id))
+            .map(|reservation| reservation.get_node())
+    }
+
+    /// Reserved keys are sent straight to their node, all others along the finger table
+    fn next_hop_for_key(&self, key_id: &BigInt) -> OtherNode {
+        match self.reserved_owner(key_id) {
+            Some(owner) if owner.id != self.id => owner.clone(),
+            _ => self.closest_preceding_node(chord::key_placement_id(key_id)),
         }
     }
 
-    fn handle_dht_ask_further_store_response(&self,
-                                             next_node: OtherNode,
-                                             data: (BigInt, DHTEntry)) {
-        debug!("Did not store data {:?} yet, asking node #{} now...", data, next_node.id);
-        let req = Request::DHTStoreKey { data };
-        network::send_request(self.to_other_node(),next_node.ip_addr,req);
+    pub fn get_reservations(&self) -> &Vec<KeyRangeReservation> {
+        &self.reservations
     }
 
-    fn handle_dht_ask_further_find_response(&self,
-                                            next_node: OtherNode,
-                                            key_id: BigInt) {
-        debug!("Did not find key '{}' yet, asking node #{} now...", key_id, next_node.id);
-        let req = Request::DHTFindKey { key_id };
-        network::send_request(self.to_other_node(), next_node.ip_addr, req);
+    /// Adds the reservations a successor knows of. A range reserved to two nodes goes to the
+    /// lower id, so that all nodes agree. Keys that are now reserved to another node are moved there.
+    fn merge_reservations(&mut self, reservations: Vec<KeyRangeReservation>) {
+        let mut changed = false;
+        for reservation in reservations {
+            // Only my own configuration reserves keys to me
+            if reservation.get_node().id == self.id {
+                continue;
+            }
+            match self.reservations.iter().position(|known| known.same_range(&reservation)) {
+                Some(i) if reservation.get_node().id < self.reservations[i].get_node().id => {
+                    warn!(target: chord::LOG_DHT, "Key range {} reserved to both node #{} and #{}, keeping #{}",
+                          reservation, chord::short_id(&self.reservations[i].get_node().id),
+                          chord::short_id(&reservation.get_node().id), chord::short_id(&reservation.get_node().id));
+                    self.reservations[i] = reservation;
+                    changed = true;
+                }
+                Some(_) => {}
+                None => {
+                    info!(target: chord::LOG_DHT, "[Node #{}] Key range {} is reserved to node #{}",
+                          chord::short_id(&self.id), reservation, chord::short_id(&reservation.get_node().id));
+                    self.reservations.push(reservation);
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            if let Some(predecessor) = self.key_range_start() {
+                self.check_redistribute_dht_keys(predecessor.get_id());
+            }
+        }
     }
 
-    fn handle_dht_ask_further_delete_response(&self,
-                                              next_node: OtherNode,
-                                              key_id: BigInt) {
-        debug!("Did not find key '{}' yet, asking node #{} now...", key_id, next_node.id);
-        let req = Request::DHTDeleteKey { key_id };
-        network::send_request(self.to_other_node(), next_node.ip_addr, req);
+    /// The nodes a lookup for `id` asks, in order, starting with me and ending with the node
+    /// responsible for `id`. Waits for the answer of each node, so call it on a clone of the
+    /// node instead of while holding the shared one.
+    pub fn trace_lookup(&self, id: &BigInt) -> Vec<OtherNode> {
+        let mut trace = vec![self.to_other_node()];
+        let mut response = self.handle_find_successor_request(id.clone(), LookupContext::new());
+        loop {
+            match response {
+                Response::FoundSuccessor { successor, .. } => {
+                    if trace.last() != Some(&successor) {
+                        trace.push(successor);
+                    }
+                    return trace;
+                }
+                Response::AskFurther { next_node, lookup } => {
+                    let looped = trace.contains(&next_node);
+                    trace.push(next_node.clone());
+                    if looped {
+                        warn!(target: chord::LOG_STABILIZE, "Lookup of id {} came back to node #{}, ending the trace", chord::short_id(id), chord::short_id(&next_node.id));
+                        return trace;
+                    }
+                    let req = Request::FindSuccessor { id: id.clone(), lookup: lookup.forwarded() };
+                    response = match self.network.send_client_request(next_node.ip_addr, req) {
+                        Ok(response) => response,
+                        Err(e) => {
+                            warn!(target: chord::LOG_STABILIZE, "Trace of id {} stopped at node #{}: {}", chord::short_id(id), chord::short_id(&next_node.id), e);
+                            return trace;
+                        }
+                    };
+                }
+                Response::LookupTimedOut { lookup, .. } => {
+                    warn!(target: chord::LOG_STABILIZE, "Lookup of id {} timed out after {} hops", chord::short_id(id), lookup.get_hops());
+                    return trace;
+                }
+                other => {
+                    warn!(target: chord::LOG_STABILIZE, "Trace of id {} got an unexpected response: {:?}", chord::short_id(id), other);
+                    return trace;
+                }
+            }
+        }
+    }
+
+    /// Stores a canary key with a unique value through the ring, finds it again and deletes it.
+    /// Returns whether the whole path worked, a deeper health check than `is_joined`. Waits for
+    /// the answers like `trace_lookup`, so call it on a clone of the node as well.
+    pub fn self_test(&self) -> bool {
+        let client = ChordClient::new(self.ip_addr, self.config.key_space_bits);
+        let key = format!("self-test-{}", chord::short_id(&self.id));
+        let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let value = format!("{}-{}{:09}", self.ip_addr, since.as_secs(), since.subsec_nanos());
+        let result = client.store(&key, &value)
+            .and_then(|_| client.get(&key))
+            .and_then(|found| match found {
+                Some(ref found) if *found == value => Ok(()),
+                Some(found) => Err(format!("found '{}' instead of '{}'", found, value)),
+                None => Err("the stored key was not found".to_string()),
+            })
+            .and_then(|_| client.delete(&key))
+            .and_then(|existed| if existed { Ok(()) } else { Err("the key was gone before the delete".to_string()) });
+        match result {
+            Ok(()) => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Self test passed", chord::short_id(&self.id));
+                true
+            }
+            Err(e) => {
+                warn!(target: chord::LOG_DHT, "[Node #{}] Self test failed: {}", chord::short_id(&self.id), e);
+                false
+            }
+        }
+    }
+
+    pub fn export_routing_state(&self) -> RoutingState {
+        RoutingState {
+            successor_list: self.successor_list.clone(),
+            predecessor: self.predecessor.clone(),
+            fingers: self.finger_table.get_nodes(),
+        }
+    }
+
+    /// Takes over the entries of `state` which answer a ping and counts as joined if one of the
+    /// successors did, stabilize and fix_fingers correct what changed in the meantime. Returns
+    /// whether the node joined. Pings every entry, so call it before the node is shared.
+    pub fn import_routing_state(&mut self, state: RoutingState) -> bool {
+        let me = self.to_other_node();
+        let mut alive: HashMap<BigInt, bool> = HashMap::new();
+        for node in state.successor_list.iter().chain(state.fingers.iter()).chain(state.predecessor.iter()) {
+            if !alive.contains_key(&node.id) {
+                // The own listener does not run yet, and alone in the ring there is nothing to restore
+                let answered = node.id != self.id && self.network.check_alive(node.ip_addr, me.clone());
+                alive.insert(node.id.clone(), answered);
+            }
+        }
+        let successor_list: Vec<OtherNode> = state.successor_list.into_iter().filter(|succ| alive[&succ.id]).collect();
+        let successor = match successor_list.first() {
+            Some(successor) => successor.clone(),
+            None => {
+                info!(target: chord::LOG_STABILIZE, "[Node #{}] No successor of the routing state answered, joining from scratch", chord::short_id(&self.id));
+                return false;
+            }
+        };
+        self.finger_table = FingerTable::new(self.id.clone());
+        self.finger_table.set_successor(successor);
+        // Fingers are kept up to the first dead one, the others are guessed from the live nodes
+        for (index, finger) in state.fingers.iter().enumerate().skip(1) {
+            if !alive[&finger.id] {
+                break;
+            }
+            self.finger_table.put(index, finger_start(&self.id, index + 1), finger.clone());
+        }
+        let live_nodes: Vec<OtherNode> = successor_list.iter().chain(state.fingers.iter())
+            .filter(|node| alive[&node.id])
+            .cloned()
+            .collect();
+        self.finger_table.seed(&live_nodes);
+        info!(target: chord::LOG_STABILIZE, "[Node #{}] Restored the routing state, {} of {} known nodes answered, joined.",
+              chord::short_id(&self.id), alive.values().filter(|answered| **answered).count(), alive.len());
+        self.successor_list = successor_list;
+        self.set_predecessor(state.predecessor.filter(|pre| alive[&pre.id]));
+        self.mark_joined();
+        true
+    }
+
+    /// Up to `depth` predecessors walking backward from me, nearest first. Every predecessor is
+    /// asked for its own, so a gap in the ring shows as a chain ending early or skipping nodes.
+    /// Waits for each answer, so call it on a clone like `trace_lookup`.
+    pub fn predecessor_chain(&self, depth: usize) -> Vec<OtherNode> {
+        let mut chain: Vec<OtherNode> = Vec::new();
+        let mut next = self.predecessor.clone();
+        while let Some(predecessor) = next {
+            if chain.len() >= depth || predecessor.id == self.id || chain.contains(&predecessor) {
+                break;
+            }
+            chain.push(predecessor.clone());
+            let req = Request::GetPredecessorChain { depth: depth - chain.len() + 1 };
+            next = match self.network.send_client_request(predecessor.ip_addr, req) {
+                Ok(Response::PredecessorChainLink { predecessor, .. }) => predecessor,
+                Ok(other) => {
+                    warn!(target: chord::LOG_STABILIZE, "Predecessor chain got an unexpected response from node #{}: {:?}", chord::short_id(&predecessor.id), other);
+                    None
+                }
+                Err(e) => {
+                    warn!(target: chord::LOG_STABILIZE, "Predecessor chain stopped at node #{}: {}", chord::short_id(&predecessor.id), e);
+                    None
+                }
+            };
+        }
+        chain
+    }
+
+    /// Number of keys owned per node id, collected by crawling the ring along the successor lists,
+    /// see `chord::crawl_ring`. Waits for the answers, so call it on a clone like `trace_lookup`.
+    /// Nodes joining or leaving meanwhile may be missed or keys counted twice.
+    pub fn key_distribution(&self) -> HashMap<BigInt, usize> {
+        let network = self.network.clone();
+        let visits = chord::crawl_ring(self.to_other_node(), self.config.crawl_parallelism, move |node: &OtherNode| {
+            let count = match network.send_client_request(node.ip_addr, Request::GetKeysOwned)? {
+                Response::KeysOwned { count, .. } => count,
+                other => return Err(format!("Unexpected response: {:?}", other)),
+            };
+            match network.send_client_request(node.ip_addr, Request::GetSuccessorList)? {
+                Response::GetSuccessorListResponse { successor_list, .. } => Ok((count, successor_list)),
+                other => Err(format!("Unexpected response: {:?}", other)),
+            }
+        });
+        let mut distribution = HashMap::new();
+        for (node, result) in visits {
+            match result {
+                Ok(count) => {
+                    distribution.insert(node.id, count);
+                }
+                Err(e) => warn!(target: chord::LOG_DHT, "Key distribution skipped node #{}: {}", chord::short_id(&node.id), e),
+            }
+        }
+        distribution
+    }
+
+    /// The nodes answering while the ring is crawled along the successor lists, see
+    /// `chord::crawl_ring`. Waits for the answers, so call it on a clone like `trace_lookup`.
+    pub fn list_members(&self) -> MemberList {
+        let network = self.network.clone();
+        let visits = chord::crawl_ring(self.to_other_node(), self.config.crawl_parallelism, move |node: &OtherNode| {
+            match network.send_client_request(node.ip_addr, Request::GetSuccessorList)? {
+                Response::GetSuccessorListResponse { successor_list, .. } => Ok((successor_list.clone(), successor_list)),
+                other => Err(format!("Unexpected response: {:?}", other)),
+            }
+        });
+        let mut members = Vec::new();
+        let mut named = HashSet::new();
+        let mut complete = true;
+        for (node, result) in visits {
+            match result {
+                Ok(successor_list) => {
+                    named.extend(successor_list.into_iter().map(|succ| succ.id));
+                    members.push(node);
+                }
+                Err(e) => {
+                    debug!(target: chord::LOG_STABILIZE, "Member list skipped node #{}: {}", chord::short_id(&node.id), e);
+                    complete = false;
+                }
+            }
+        }
+        // Named by a member, but not visited before the crawl stopped
+        let member_ids: HashSet<&BigInt> = members.iter().map(|member| &member.id).collect();
+        if !named.iter().all(|id| member_ids.contains(id)) {
+            complete = false;
+        }
+        members.sort_by(|a, b| a.id.cmp(&b.id));
+        MemberList { members, complete }
+    }
+
+    fn closest_preceding_node(&self, id: BigInt) -> OtherNode {
+        let mut min_abs: BigInt = BigInt::new(Sign::Plus, vec![u32::max_value(); 5]);
+        let mut return_node: OtherNode = self.to_other_node();
+        for i in 0..self.finger_table.length() {
+            let entry = self.finger_table.get(i);
+            let finger_abs = chord::chord_abs(entry.get_node().get_id(), &id);
+            if finger_abs < min_abs {
+                min_abs = finger_abs;
+                return_node = entry.get_node().clone()
+            }
+        }
+        for i in 0..self.successor_list.len() {
+            let entry = &self.successor_list[i];
+            if entry.id == self.id {
+                break;
+            } else {
+                let finger_abs = chord::chord_abs(entry.get_id(), &id);
+                if finger_abs < min_abs {
+                    min_abs = finger_abs;
+                    return_node = entry.clone()
+                }
+            }
+        }
+        return_node
+    }
+
+    pub fn process_incoming_request(&mut self, request: Request) -> Option<Response> {
+        match request {
+            Request::FindSuccessor { id, lookup } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::FindSuccessor(id: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&id), lookup.get_hops());
+                Some(self.handle_find_successor_request(id, lookup))
+            }
+            Request::GetPredecessor => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::GetPredecessor", chord::short_id(&self.id));
+                Some(self.handle_get_predecessor_request())
+            }
+            Request::Notify { node } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::Notify(node: {})", chord::short_id(&self.id), chord::short_id(&node.id));
+                Some(self.handle_notify_request(node))
+            }
+            Request::Stabilize { from } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::Stabilize(from: {})", chord::short_id(&self.id), chord::short_id(&from.id));
+                Some(self.handle_stabilize_request(from))
+            }
+            Request::SetMaintenancePaused { paused } => {
+                info!(target: chord::LOG_STABILIZE, "[Node #{}] Request::SetMaintenancePaused(paused: {})", chord::short_id(&self.id), paused);
+                Some(self.handle_set_maintenance_paused_request(paused))
+            }
+            Request::SetReadOnly { read_only } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::SetReadOnly(read_only: {})", chord::short_id(&self.id), read_only);
+                self.set_read_only(read_only);
+                Some(Response::ReadOnlySet { read_only })
+            }
+            Request::DHTDeleteNamespace { namespace, requester, deleted, lookup } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTDeleteNamespace(namespace: {}, requester: {}, deleted: {}, hops: {})",
+                      chord::short_id(&self.id), namespace, chord::short_id(&requester.id), deleted, lookup.get_hops());
+                self.handle_dht_delete_namespace_request(namespace, requester, deleted, lookup);
+                None
+            }
+            Request::DHTCompareAndSwap { key_id, expected, new } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Request::DHTCompareAndSwap(key_id: {}, expected: {:?}, new: {:?})",
+                     chord::short_id(&self.id), chord::short_id(&key_id), expected.clone(), new.clone());
+                Some(self.handle_dht_compare_and_swap_request(key_id, expected, new))
+            }
+            Request::DHTTransaction { ops } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTTransaction(ops: {})", chord::short_id(&self.id), ops.len());
+                Some(self.handle_dht_transaction_request(ops))
+            }
+            Request::DHTAppend { key_id, key, data } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTAppend(key_id: {}, bytes: {})", chord::short_id(&self.id), chord::short_id(&key_id), data.len());
+                Some(self.handle_dht_append_request(key_id, key, data))
+            }
+            Request::GetPredecessorChain { depth } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::GetPredecessorChain(depth: {})", chord::short_id(&self.id), depth);
+                Some(Response::PredecessorChainLink { node: self.to_other_node(), predecessor: self.predecessor.clone(), depth: depth.saturating_sub(1) })
+            }
+            Request::GetKeysOwned => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Request::GetKeysOwned", chord::short_id(&self.id));
+                Some(Response::KeysOwned { id: self.id.clone(), count: self.keys_owned() })
+            }
+            Request::FindSuccessorFinger { index, finger_id } => {
+                debug!(target: chord::LOG_FIX_FINGERS, "[Node #{}] Request::FindSuccessorFinger(index: {} finger_id: {})", chord::short_id(&self.id), index, chord::short_id(&finger_id));
+                Some(self.handle_find_successor_finger_request(index, finger_id))
+            }
+            Request::GetSuccessorList => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::GetSuccessorList", chord::short_id(&self.id));
+                Some(self.handle_get_successor_list_request())
+            }
+            Request::GetFingerTable => {
+                debug!(target: chord::LOG_FIX_FINGERS, "[Node #{}] Request::GetFingerTable", chord::short_id(&self.id));
+                Some(Response::FingerTableResponse { fingers: self.finger_table.get_nodes() })
+            }
+            Request::DHTStoreKey { data, moved } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Request::StoreKey(data: {:?}, moved: {})", chord::short_id(&self.id), data.clone(), moved);
+                Some(self.handle_dht_store_key_request(data, moved))
+            }
+            Request::DHTFindKey { key_id, lookup } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::FindKey(key_id: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&key_id), lookup.get_hops());
+                Some(self.handle_dht_find_key_request(key_id, lookup))
+            }
+            Request::DHTFindKeyStream { key_id, requester, lookup } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTFindKeyStream(key_id: {}, requester: {}, hops: {})",
+                      chord::short_id(&self.id), chord::short_id(&key_id), chord::short_id(&requester.id), lookup.get_hops());
+                self.handle_dht_find_key_stream_request(key_id, requester, lookup)
+            }
+            Request::DHTDeleteKey { key_id } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DeleteKey(key_id {})", chord::short_id(&self.id), chord::short_id(&key_id));
+                Some(self.handle_dht_delete_key_request(key_id))
+            }
+            Request::DHTGetAndDelete { key_id } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTGetAndDelete(key_id {})", chord::short_id(&self.id), chord::short_id(&key_id));
+                Some(self.handle_dht_get_and_delete_request(key_id))
+            }
+            Request::DHTTakeOverKeys { data } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Request::DHTTakeOverKey(data {:?})", chord::short_id(&self.id), data.clone());
+                self.handle_dht_take_over_keys(data);
+                None
+            }
+            Request::DHTStoreChunk { chunk_index, total_chunks, data } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DHTStoreChunk(chunk: {}/{}, key_id: {})",
+                      chord::short_id(&self.id), chunk_index + 1, total_chunks, chord::short_id(&data.0));
+                self.handle_dht_store_chunk_request(chunk_index, total_chunks, data)
+            }
+            Request::DumpStorage { keys_only, cursor } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Request::DumpStorage(keys_only: {}, cursor: {:?})", chord::short_id(&self.id), keys_only, cursor);
+                Some(self.handle_dump_storage_request(keys_only, cursor))
+            }
+            Request::DHTStoreIfAbsent { data } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Request::DHTStoreIfAbsent(data: {:?})", chord::short_id(&self.id), data.clone());
+                Some(self.handle_dht_store_if_absent_request(data))
+            }
+            Request::ProbeRing { id, lookup } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Request::ProbeRing(id: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&id), lookup.get_hops());
+                Some(self.handle_probe_ring_request(id, lookup))
+            }
+        }
+    }
+
+    pub fn process_incoming_response(&mut self, response: Response) {
+        match response {
+            Response::FoundSuccessor { successor, lookup } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Response::FoundSuccessor(successor: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&successor.id), lookup.get_hops());
+                self.handle_found_successor_response(successor, lookup)
+            }
+            Response::AskFurther { next_node, lookup } => {
+                debug!("[Node #{}] Response::AskFurther(next_node: {}", chord::short_id(&self.id), chord::short_id(&next_node.id));
+                self.handle_ask_further_response(next_node, lookup)
+            }
+            Response::GetPredecessorResponse { predecessor, recently_dead } => {
+                log!(target: chord::LOG_STABILIZE, self.network.message_log_level(Level::Debug), "[Node #{}] Response::GetPredecessorResponse(predecessor: {:?}, recently_dead: {})", chord::short_id(&self.id), predecessor.clone(), recently_dead.len());
+                self.prune_gossiped_dead(&recently_dead);
+                self.handle_get_predecessor_response(predecessor)
+            }
+            Response::NotifyResponse => {
+                debug!(target: chord::LOG_STABILIZE, "Response::NotifyResponse");
+                self.handle_notify_response()
+            }
+            Response::DHTCasResult { swapped, current } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Debug), "[Node #{}] Response::DHTCasResult(swapped: {}, current: {:?})", chord::short_id(&self.id), swapped, current.clone());
+                self.handle_dht_cas_result_response(swapped, current)
+            }
+            Response::DHTAskFurtherCompareAndSwap { next_node, key_id, expected, new } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherCompareAndSwap(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_compare_and_swap_response(next_node, key_id, expected, new)
+            }
+            Response::DHTTransactionApplied { ops } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTTransactionApplied(ops: {})", chord::short_id(&self.id), ops);
+                self.handle_dht_transaction_applied_response(ops)
+            }
+            Response::DHTTransactionRejected { failed_op, reason } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTTransactionRejected(failed_op: {}, reason: {})", chord::short_id(&self.id), failed_op, reason);
+                self.handle_dht_transaction_rejected_response(failed_op, reason)
+            }
+            Response::DHTAskFurtherTransaction { next_node, ops } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherTransaction(next_node: {}, ops: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), ops.len());
+                self.handle_dht_ask_further_transaction_response(next_node, ops)
+            }
+            Response::DHTAppended { key, length } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAppended(key: {}, length: {})", chord::short_id(&self.id), key, length);
+                self.handle_dht_appended_response(key, length)
+            }
+            Response::DHTAskFurtherAppend { next_node, key_id, key, data } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherAppend(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_append_response(next_node, key_id, key, data)
+            }
+            Response::MaintenancePaused { paused } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Response::MaintenancePaused(paused: {})", chord::short_id(&self.id), paused);
+                self.handle_maintenance_paused_response(paused)
+            }
+            Response::ReadOnlySet { read_only } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::ReadOnlySet(read_only: {})", chord::short_id(&self.id), read_only);
+                self.handle_read_only_set_response(read_only)
+            }
+            Response::DHTReadOnly { key_id } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTReadOnly(key_id: {})", chord::short_id(&self.id), chord::short_id(&key_id));
+                self.handle_dht_read_only_response(key_id)
+            }
+            Response::KeysOwned { id, count } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::KeysOwned(id: {}, count: {})", chord::short_id(&self.id), chord::short_id(&id), count);
+                self.peer_keys_owned.insert(id, count);
+            }
+            Response::LookupBusy { node, request } => {
+                debug!(target: chord::LOG_NETWORK, "[Node #{}] Response::LookupBusy(node: {})", chord::short_id(&self.id), chord::short_id(&node.id));
+                self.handle_lookup_busy_response(node, *request)
+            }
+            Response::PredecessorChainLink { node, predecessor, depth } => {
+                // Only expected as the answer to a client request of `predecessor_chain`
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Response::PredecessorChainLink(node: {}, predecessor: {:?}, depth: {})",
+                       chord::short_id(&self.id), chord::short_id(&node.id), predecessor.map(|pre| chord::short_id(&pre.id)), depth);
+            }
+            Response::StabilizeResponse { predecessor, accepted, recently_dead } => {
+                log!(target: chord::LOG_STABILIZE, self.network.message_log_level(Level::Debug), "[Node #{}] Response::StabilizeResponse(predecessor: {:?}, accepted: {}, recently_dead: {})", chord::short_id(&self.id), predecessor.clone(), accepted, recently_dead.len());
+                self.prune_gossiped_dead(&recently_dead);
+                self.handle_stabilize_response(predecessor, accepted)
+            }
+            Response::FoundSuccessorFinger { index, finger_id, successor } => {
+                debug!(target: chord::LOG_FIX_FINGERS, "[Node #{}] Response::FoundSuccessorFinger(index: {}, finger_id: {}, successor: {})",
+                       chord::short_id(&self.id), index, chord::short_id(&finger_id), chord::short_id(&successor.id));
+                self.handle_found_successor_finger_response(index, finger_id, successor)
+            }
+            Response::AskFurtherFinger { index, finger_id, next_node } => {
+                debug!(target: chord::LOG_FIX_FINGERS, "[Node #{}] Response::AskFurtherFinger(next_node: {}", chord::short_id(&self.id), chord::short_id(&next_node.id));
+                self.handle_ask_further_finger_response(index, finger_id, next_node)
+            }
+            Response::GetSuccessorListResponse { successor_list, reservations } => {
+                log!(target: chord::LOG_STABILIZE, self.network.message_log_level(Level::Debug), "[Node #{}] Response::GetSuccessorListResponse(successor_list: {:?}, reservations: {:?}",
+                     chord::short_id(&self.id), successor_list.clone(), reservations.clone());
+                self.handle_get_successor_list_response(successor_list);
+                self.merge_reservations(reservations)
+            }
+            Response::FingerTableResponse { fingers } => {
+                log!(target: chord::LOG_FIX_FINGERS, self.network.message_log_level(Level::Debug), "[Node #{}] Response::FingerTableResponse(fingers: {:?})",
+                     chord::short_id(&self.id), fingers.clone());
+                self.handle_finger_table_response(fingers)
+            }
+            Response::DHTStoredKey{ key} => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTStoredKey", chord::short_id(&self.id));
+                self.handle_dht_stored_key_response(key)
+            }
+            Response::LookupTimedOut { id, lookup } => {
+                debug!("[Node #{}] Response::LookupTimedOut(id: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&id), lookup.get_hops());
+                self.handle_lookup_timed_out_response(id, lookup)
+            }
+            Response::DHTRejectedKey { key, size, max_size } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTRejectedKey(key: {}, size: {}, max_size: {})", chord::short_id(&self.id), key, size, max_size);
+                self.handle_dht_rejected_key_response(key, size, max_size)
+            }
+            Response::DHTOverloaded { key, stored_bytes, high_water_mark } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTOverloaded(key: {}, stored_bytes: {}, high_water_mark: {})", chord::short_id(&self.id), key, stored_bytes, high_water_mark);
+                self.handle_dht_overloaded_response(key, stored_bytes)
+            }
+            Response::DHTFoundKey { data, lookup } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Debug), "[Node #{}] Response::DHTFoundKey(data: {:?}, hops: {})", chord::short_id(&self.id), data.clone(), lookup.get_hops());
+                self.handle_dht_found_key_response(data, lookup)
+            }
+            Response::DHTFoundChunk { chunk_index, total_chunks, data, lookup } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTFoundChunk(chunk: {}/{}, key_id: {}, hops: {})",
+                       chord::short_id(&self.id), chunk_index + 1, total_chunks, chord::short_id(&data.0), lookup.get_hops());
+                self.handle_dht_found_chunk_response(chunk_index, total_chunks, data, lookup)
+            }
+            Response::DHTDeletedKey { key_existed } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTDeletedKey(key_existed: {})", chord::short_id(&self.id), key_existed);
+                self.handle_dht_deleted_key_response(key_existed)
+            }
+            Response::DHTPopped { data } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Debug), "[Node #{}] Response::DHTPopped(data: {:?})", chord::short_id(&self.id), data.clone());
+                self.handle_dht_popped_response(data)
+            }
+            Response::DHTAskFurtherStore { next_node, data, moved } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Response::DHTAskFurtherStore(next_node: {}, data: {:?}, moved: {})",
+                     chord::short_id(&self.id), chord::short_id(next_node.get_id()), data, moved);
+                self.handle_dht_ask_further_store_response(next_node, data, moved)
+            }
+            Response::DHTAskFurtherFind { next_node, key_id, lookup } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherFind(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_find_response(next_node, key_id, lookup)
+            }
+            Response::DHTAskFurtherFindStream { next_node, key_id, lookup } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherFindStream(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_find_stream_response(next_node, key_id, lookup)
+            }
+            Response::DHTAskFurtherDelete { next_node, key_id } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherDelete(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_delete_response(next_node, key_id)
+            }
+            Response::DHTAskFurtherGetAndDelete { next_node, key_id } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherGetAndDelete(next_node: {}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chord::short_id(&key_id));
+                self.handle_dht_ask_further_get_and_delete_response(next_node, key_id)
+            }
+            Response::DumpedStoragePage { node, data, keys_only, next_cursor } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Debug), "[Node #{}] Response::DumpedStoragePage(node: {}, data: {:?}, next_cursor: {:?})", chord::short_id(&self.id), chord::short_id(&node.id), data.clone(), next_cursor);
+                self.handle_dumped_storage_page_response(node, data, keys_only, next_cursor)
+            }
+            Response::DHTStoredIfAbsent { stored } => {
+                debug!(target: chord::LOG_DHT, "[Node #{}] Response::DHTStoredIfAbsent(stored: {})", chord::short_id(&self.id), stored);
+                self.handle_dht_stored_if_absent_response(stored)
+            }
+            Response::DHTAskFurtherStoreIfAbsent { next_node, data } => {
+                log!(target: chord::LOG_DHT, self.network.message_log_level(Level::Info), "[Node #{}] Response::DHTAskFurtherStoreIfAbsent(next_node: {}, data: {:?})",
+                     chord::short_id(&self.id), chord::short_id(next_node.get_id()), data);
+                self.handle_dht_ask_further_store_if_absent_response(next_node, data)
+            }
+            Response::DHTAskFurtherStoreChunk { next_node, chunk_index, total_chunks, data } => {
+                info!(target: chord::LOG_DHT, "[Node #{}] Response::DHTAskFurtherStoreChunk(next_node: {}, chunk: {}/{}, key_id: {})",
+                      chord::short_id(&self.id), chord::short_id(next_node.get_id()), chunk_index + 1, total_chunks, chord::short_id(&data.0));
+                self.handle_dht_ask_further_store_chunk_response(next_node, chunk_index, total_chunks, data)
+            }
+            Response::RingProbed { successor, lookup } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Response::RingProbed(successor: {}, hops: {})", chord::short_id(&self.id), chord::short_id(&successor.id), lookup.get_hops());
+                self.handle_ring_probed_response(successor)
+            }
+            Response::AskFurtherProbe { next_node, lookup } => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Response::AskFurtherProbe(next_node: {})", chord::short_id(&self.id), chord::short_id(&next_node.id));
+                self.handle_ask_further_probe_response(next_node, lookup)
+            }
+        }
+    }
+
+    // REQUESTS
+
+    fn handle_find_successor_request(&self, id: BigInt, lookup: LookupContext) -> Response {
+        if lookup.is_expired() {
+            Response::LookupTimedOut { id, lookup }
+        } else if chord::is_my_key(self.get_successor().get_id(), &self.id, &id) {
+            Response::FoundSuccessor { successor: self.get_successor().clone(), lookup }
+        } else if let Some(pre) = self.predecessor.clone() {
+            if chord::is_my_key(&self.id, pre.get_id(), &id) {
+                Response::FoundSuccessor { successor: self.to_other_node(), lookup }
+            } else {
+                Response::AskFurther { next_node: self.closest_preceding_node(id), lookup }
+            }
+        } else {
+            Response::AskFurther { next_node: self.closest_preceding_node(id), lookup }
+        }
+    }
+
+    fn handle_probe_ring_request(&self, id: BigInt, lookup: LookupContext) -> Response {
+        match self.handle_find_successor_request(id, lookup) {
+            Response::FoundSuccessor { successor, lookup } => Response::RingProbed { successor, lookup },
+            Response::AskFurther { next_node, lookup } => Response::AskFurtherProbe { next_node, lookup },
+            response => response,
+        }
+    }
+
+    fn handle_get_predecessor_request(&mut self) -> Response {
+        Response::GetPredecessorResponse {
+            predecessor: self.predecessor.clone(),
+            recently_dead: self.dead_gossip(),
+        }
+    }
+
+    fn handle_notify_request(&mut self, node: OtherNode) -> Response {
+        self.accept_notify(node);
+        Response::NotifyResponse
+    }
+
+    /// Takes `node` as predecessor if it lies between the current predecessor and me. Of several
+    /// nodes notifying me the closest one ends up as predecessor, in whichever order they notify.
+    fn accept_notify(&mut self, node: OtherNode) -> bool {
+        if self.config.observer {
+            // Without a predecessor an observer forwards all keys
+            return false;
+        }
+        match &self.predecessor {
+            None => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Notify: Had no Pre. Pre is now: {}", chord::short_id(&self.id), chord::short_id(&node.id));
+                self.set_predecessor(Some(node));
+                true
+            }
+            Some(pre) if *pre == node && pre.ip_addr != node.ip_addr => {
+                // The node restarted under another address with a fixed id (see
+                // `ChordConfig::node_id`), or two live nodes share the id. The lower address wins
+                // either way, so that the predecessor does not flap between them with every notify.
+                if (node.ip_addr.ip(), node.ip_addr.port()) < (pre.ip_addr.ip(), pre.ip_addr.port()) {
+                    info!(target: chord::LOG_STABILIZE, "[Node #{}] Pre {} is claimed from {} and {}, taking {}",
+                          chord::short_id(&self.id), chord::short_id(&node.id), pre.ip_addr, node.ip_addr, node.ip_addr);
+                    self.set_predecessor(Some(node));
+                    true
+                } else {
+                    // The higher address only takes over once check_predecessor found the
+                    // current one dead, see `resolve_predecessor_claim`
+                    self.predecessor_claim = Some(node);
+                    false
+                }
+            }
+            Some(pre) => {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] Notify: Current Pre: {}, possible new Pre: {}. Successor is: {}", chord::short_id(&self.id), chord::short_id(&pre.id), chord::short_id(&node.id), chord::short_id(&self.get_successor().id));
+                if *pre != node && chord::is_in_interval(pre.get_id(), &self.id, node.get_id()) {
+                    self.set_predecessor(Some(node));
+                    debug!(target: chord::LOG_STABILIZE, "[Node #{}] Took new Pre: {}", chord::short_id(&self.id), chord::short_id(&self.predecessor.clone().unwrap().id));
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Answers with the predecessor the sender would have got from GetPredecessor
+    /// before its Notify is handled
+    fn handle_stabilize_request(&mut self, from: OtherNode) -> Response {
+        let predecessor = self.predecessor.clone();
+        let accepted = self.accept_notify(from);
+        Response::StabilizeResponse { predecessor, accepted, recently_dead: self.dead_gossip() }
+    }
+
+    fn handle_find_successor_finger_request(&self, index: usize, finger_id: BigInt) -> Response {
+        if chord::is_my_key(self.get_successor().get_id(), &self.id, &finger_id) {
+            Response::FoundSuccessorFinger { index, finger_id, successor: self.get_successor().clone() }
+        } else {
+            Response::AskFurtherFinger { index, finger_id, next_node: self.get_successor() }
+        }
+    }
+
+    fn handle_get_successor_list_request(&self) -> Response {
+        Response::GetSuccessorListResponse {
+            successor_list: self.successor_list.clone(),
+            reservations: self.reservations.clone(),
+        }
+    }
+
+    fn handle_dht_store_key_request(&mut self,
+                                    data: (BigInt, DHTEntry),
+                                    moved: bool) -> Response {
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &data.0) {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherStore { next_node: successor, data, moved };
+                }
+                // Moved keys are taken over in any case, the node moving them forgot them already
+                if !moved {
+                    if let Some(rejected) = self.check_writable(&data.0) {
+                        return rejected;
+                    }
+                    if let Some(rejected) = self.check_store_allowed(&data.1, data.1.value_size()) {
+                        return rejected;
+                    }
+                }
+                self.storage.store_key(data.clone());
+                Response::DHTStoredKey{
+                    key: data.1.get_key().to_owned()
+                }
+            } else {
+                Response::DHTAskFurtherStore {
+                    next_node: self.next_hop_for_key(&data.0),
+                    data,
+                    moved,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherStore {
+                next_node: self.next_hop_for_key(&data.0),
+                data,
+                moved,
+            }
+        }
+    }
+
+    fn handle_dht_find_key_request(&mut self, key_id: BigInt, lookup: LookupContext) -> Response {
+        if lookup.is_expired() {
+            return Response::LookupTimedOut { id: key_id, lookup };
+        }
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &key_id) {
+                let value_option = self.storage.get_key(&key_id);
+                if self.config.migration_strategy == MigrationStrategy::Lazy
+                    && !lookup.is_for_previous_owner() && !self.is_single_node_ring() {
+                    // My successor may not have handed the key over yet, or hold a newer copy
+                    return Response::DHTAskFurtherFind {
+                        next_node: self.get_successor(),
+                        key_id,
+                        lookup: lookup.for_previous_owner(value_option),
+                    };
+                }
+                Response::DHTFoundKey { data: (key_id, value_option), lookup }
+            } else if lookup.is_for_previous_owner() {
+                // Asked by the owner for a key I did not hand over yet, which moves it right away.
+                // The owner keeps the newer copy, so a stale one of either of us is repaired.
+                let mut lookup = lookup;
+                let value_option = storage::newer_entry(self.storage.get_key(&key_id), lookup.take_owner_entry());
+                self.migrate_key(&key_id);
+                Response::DHTFoundKey { data: (key_id, value_option), lookup }
+            } else {
+                Response::DHTAskFurtherFind {
+                    next_node: self.next_hop_for_key(&key_id),
+                    key_id,
+                    lookup,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherFind {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+                lookup,
+            }
+        }
+    }
+
+    /// Sends the value chunk by chunk to the requester, answers like `DHTFindKey` otherwise
+    fn handle_dht_find_key_stream_request(&self, key_id: BigInt, requester: OtherNode, lookup: LookupContext) -> Option<Response> {
+        if lookup.is_expired() {
+            return Some(Response::LookupTimedOut { id: key_id, lookup });
+        }
+        match self.key_range_start() {
+            // I am responsible for the key
+            Some(predecessor) if self.is_my_key(predecessor.get_id(), &key_id) => {
+                let dht_entry = match self.storage.get_key(&key_id) {
+                    Some(dht_entry) => dht_entry,
+                    None => return Some(Response::DHTFoundKey { data: (key_id, None), lookup }),
+                };
+                let chunks = storage::split_value_into_chunks(dht_entry.get_value(), self.config.chunk_size);
+                let total_chunks = chunks.len();
+                for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+                    let response = Response::DHTFoundChunk {
+                        chunk_index,
+                        total_chunks,
+                        data: (key_id.clone(), DHTEntry::from_bytes(dht_entry.get_key().to_string(), chunk)),
+                        lookup: lookup.clone(),
+                    };
+                    self.network.send_response(self.to_other_node(), requester.ip_addr, response);
+                }
+                None
+            }
+            _ => Some(Response::DHTAskFurtherFindStream {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+                lookup,
+            }),
+        }
+    }
+
+    fn handle_dht_delete_key_request(&mut self, key_id: BigInt) -> Response {
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &key_id) {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherDelete { next_node: successor, key_id };
+                }
+                if let Some(rejected) = self.check_writable(&key_id) {
+                    return rejected;
+                }
+                let key_existed = self.storage.delete_key(&key_id).is_some();
+                Response::DHTDeletedKey { key_existed }
+            } else {
+                Response::DHTAskFurtherDelete {
+                    next_node: self.next_hop_for_key(&key_id),
+                    key_id,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherDelete {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+            }
+        }
+    }
+
+    /// Runs with the node locked like every request, so no other request sees the key in between
+    fn handle_dht_get_and_delete_request(&mut self, key_id: BigInt) -> Response {
+        match self.key_range_start() {
+            Some(ref predecessor) if self.is_my_key(predecessor.get_id(), &key_id) => {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherGetAndDelete { next_node: successor, key_id };
+                }
+                match self.check_writable(&key_id) {
+                    Some(rejected) => rejected,
+                    None => Response::DHTPopped { data: self.storage.delete_key(&key_id) },
+                }
+            }
+            _ => Response::DHTAskFurtherGetAndDelete {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+            },
+        }
+    }
+
+    fn handle_dht_take_over_keys(&mut self, data: Vec<(BigInt, DHTEntry)>) {
+        if self.leaving {
+            // Keys from a neighbor that did not see me leaving yet, pass them on. The successors
+            // are pinged on a thread of its own, as the listener holds the node lock.
+            let handoff = Handoff {
+                me: self.to_other_node(),
+                successors: self.successor_list.clone(),
+                network: self.network.clone(),
+                requests: vec![Request::DHTTakeOverKeys { data }],
+            };
+            let started = logging::spawn("Handoff", move || {
+                if let Some(handle) = handoff.send() {
+                    let _ = handle.join();
+                }
+            });
+            if let Err(e) = started {
+                error!(target: chord::LOG_DHT, "Unable to pass taken over keys on: {}. Keys are lost!", e);
+            }
+            return;
+        }
+        for entry in data {
+            self.storage.store_key(entry);
+        }
+    }
+
+    fn handle_dht_store_chunk_request(&mut self,
+                                      chunk_index: usize,
+                                      total_chunks: usize,
+                                      data: (BigInt, DHTEntry)) -> Option<Response> {
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &data.0) {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Some(Response::DHTAskFurtherStoreChunk { next_node: successor, chunk_index, total_chunks, data });
+                }
+                self.expire_chunked_uploads();
+                if let Some(rejected) = self.check_writable(&data.0) {
+                    self.storage.abort_chunked_upload(&data.0);
+                    return Some(rejected);
+                }
+                let upload_size = self.storage.chunked_upload_size(&data.0) + data.1.value_size();
+                if let Some(rejected) = self.check_store_allowed(&data.1, upload_size) {
+                    self.storage.abort_chunked_upload(&data.0);
+                    return Some(rejected);
+                }
+                // Only the last missing chunk gets answered
+                self.storage.store_chunk(chunk_index, total_chunks, data)
+                    .map(|key| Response::DHTStoredKey { key })
+            } else {
+                Some(Response::DHTAskFurtherStoreChunk {
+                    next_node: self.next_hop_for_key(&data.0),
+                    chunk_index,
+                    total_chunks,
+                    data,
+                })
+            }
+        } else {
+            Some(Response::DHTAskFurtherStoreChunk {
+                next_node: self.next_hop_for_key(&data.0),
+                chunk_index,
+                total_chunks,
+                data,
+            })
+        }
+    }
+
+
+    fn handle_dht_store_if_absent_request(&mut self,
+                                          data: (BigInt, DHTEntry)) -> Response {
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &data.0) {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherStoreIfAbsent { next_node: successor, data };
+                }
+                if let Some(rejected) = self.check_writable(&data.0) {
+                    return rejected;
+                }
+                if let Some(rejected) = self.check_store_allowed(&data.1, data.1.value_size()) {
+                    return rejected;
+                }
+                // The node is locked while handling a request, so check and store are atomic
+                Response::DHTStoredIfAbsent { stored: self.storage.store_key_if_absent(data) }
+            } else {
+                Response::DHTAskFurtherStoreIfAbsent {
+                    next_node: self.next_hop_for_key(&data.0),
+                    data,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherStoreIfAbsent {
+                next_node: self.next_hop_for_key(&data.0),
+                data,
+            }
+        }
+    }
+
+    /// Goes to the owner of the first key, which applies the ops if it owns all other keys as well.
+    /// The node is locked while handling a request, so no other request sees a part of the ops.
+    fn handle_dht_transaction_request(&mut self, ops: Vec<DHTOp>) -> Response {
+        let first_key_id = match ops.first() {
+            Some(op) => op.get_key_id().clone(),
+            None => return Response::DHTTransactionApplied { ops: 0 },
+        };
+        let predecessor = match self.key_range_start() {
+            Some(ref predecessor) if self.is_my_key(predecessor.get_id(), &first_key_id) => predecessor.clone(),
+            _ => return Response::DHTAskFurtherTransaction { next_node: self.next_hop_for_key(&first_key_id), ops },
+        };
+        if let Some(successor) = self.warmup_write_target() {
+            return Response::DHTAskFurtherTransaction { next_node: successor, ops };
+        }
+        if self.is_read_only() {
+            return Response::DHTTransactionRejected { failed_op: 0, reason: "The owner of the keys is read-only".to_string() };
+        }
+        for (index, op) in ops.iter().enumerate() {
+            if !self.is_my_key(predecessor.get_id(), op.get_key_id()) {
+                return Response::DHTTransactionRejected {
+                    failed_op: index,
+                    reason: format!("Key of op {} is owned by another node than the first key", index),
+                };
+            }
+            let new_entry = match op {
+                DHTOp::Store { data } => Some(&data.1),
+                DHTOp::CompareAndSwap { new, .. } => Some(new),
+                DHTOp::Delete { .. } => None,
+            };
+            if let Some(entry) = new_entry {
+                if self.check_store_allowed(entry, entry.value_size()).is_some() {
+                    return Response::DHTTransactionRejected {
+                        failed_op: index,
+                        reason: format!("Value of key '{}' is too large or the node is overloaded", entry.get_key()),
+                    };
+                }
+            }
+        }
+        let count = ops.len();
+        match self.storage.apply_transaction(ops) {
+            Ok(()) => Response::DHTTransactionApplied { ops: count },
+            Err(index) => Response::DHTTransactionRejected {
+                failed_op: index,
+                reason: format!("Value of the key of op {} does not match the expected one", index),
+            },
+        }
+    }
+
+    fn handle_dht_append_request(&mut self, key_id: BigInt, key: String, data: Vec<u8>) -> Response {
+        match self.key_range_start() {
+            Some(ref predecessor) if self.is_my_key(predecessor.get_id(), &key_id) => {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherAppend { next_node: successor, key_id, key, data };
+                }
+                if let Some(rejected) = self.check_writable(&key_id) {
+                    return rejected;
+                }
+                let entry = DHTEntry::from_bytes(key, data);
+                let current_size = self.storage.get_key(&key_id).map_or(0, |current| current.value_size());
+                if let Some(rejected) = self.check_store_allowed(&entry, current_size + entry.value_size()) {
+                    return rejected;
+                }
+                let key = entry.get_key().clone();
+                // The node is locked while handling a request, so concurrent appends never lose bytes
+                let length = self.storage.append_to_key(key_id, entry);
+                Response::DHTAppended { key, length }
+            }
+            _ => Response::DHTAskFurtherAppend {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+                key,
+                data,
+            },
+        }
+    }
+
+    fn handle_dht_compare_and_swap_request(&mut self,
+                                           key_id: BigInt,
+                                           expected: Option<DHTEntry>,
+                                           new: DHTEntry) -> Response {
+        if let Some(predecessor) = self.key_range_start() {
+            // I am responsible for the key
+            if self.is_my_key(predecessor.get_id(), &key_id) {
+                if let Some(successor) = self.warmup_write_target() {
+                    return Response::DHTAskFurtherCompareAndSwap { next_node: successor, key_id, expected, new };
+                }
+                if let Some(rejected) = self.check_writable(&key_id) {
+                    return rejected;
+                }
+                if let Some(rejected) = self.check_store_allowed(&new, new.value_size()) {
+                    return rejected;
+                }
+                // The node is locked while handling a request, so compare and swap are atomic
+                let (swapped, current) = self.storage.compare_and_swap(key_id, expected, new);
+                Response::DHTCasResult { swapped, current }
+            } else {
+                Response::DHTAskFurtherCompareAndSwap {
+                    next_node: self.next_hop_for_key(&key_id),
+                    key_id,
+                    expected,
+                    new,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherCompareAndSwap {
+                next_node: self.next_hop_for_key(&key_id),
+                key_id,
+                expected,
+                new,
+            }
+        }
+    }
+
+    fn handle_dht_delete_namespace_request(&mut self,
+                                           namespace: String,
+                                           requester: OtherNode,
+                                           deleted: usize,
+                                           lookup: LookupContext) {
+        if requester.id == self.id {
+            // Went around the whole ring
+            self.storage.write_log_entry(format!("Deleted {} keys of namespace '{}' in the ring", deleted, namespace));
+            info!(target: chord::LOG_DHT, "Deleted {} keys of namespace '{}' in the ring ({} hops, {:?})",
+                  deleted, namespace, lookup.get_hops(), lookup.elapsed());
+            if let Some(deletion) = self.namespace_deletions.remove(&namespace) {
+                let _ = deletion.result.unbounded_send(deleted);
+            }
+            return;
+        }
+        if lookup.is_expired() {
+            warn!(target: chord::LOG_DHT, "Deleting namespace '{}' timed out after {} hops, {} keys were deleted",
+                  namespace, lookup.get_hops(), deleted);
+            return;
+        }
+        let deleted = deleted + self.delete_namespace_keys(&namespace);
+        let req = Request::DHTDeleteNamespace { namespace, requester, deleted, lookup: lookup.forwarded() };
+        self.network.send_request(self.to_other_node(), self.get_successor().ip_addr, req);
+    }
+
+    /// Rejects values larger than `max_value_size`, and sheds all writes while the storage
+    /// holds more than `storage_high_water_mark`. Reads, deletes and key moves go on.
+    fn check_store_allowed(&self, entry: &DHTEntry, size: usize) -> Option<Response> {
+        let max_size = self.config.max_value_size;
+        let high_water_mark = self.config.storage_high_water_mark;
+        let stored_bytes = self.storage.bytes_stored();
+        if max_size > 0 && size > max_size {
+            info!(target: chord::LOG_DHT, "[Node #{}] Rejecting key '{}' with {} bytes, at most {} bytes are allowed",
+                  chord::short_id(&self.id), entry.get_key(), size, max_size);
+            Some(Response::DHTRejectedKey { key: entry.get_key().clone(), size, max_size })
+        } else if high_water_mark > 0 && stored_bytes >= high_water_mark {
+            warn!(target: chord::LOG_DHT, "[Node #{}] Overloaded, shedding key '{}': {} bytes stored, high-water mark is {} bytes",
+                  chord::short_id(&self.id), entry.get_key(), stored_bytes, high_water_mark);
+            Some(Response::DHTOverloaded { key: entry.get_key().clone(), stored_bytes, high_water_mark })
+        } else {
+            None
+        }
+    }
+
+    fn check_writable(&self, key_id: &BigInt) -> Option<Response> {
+        if self.is_read_only() {
+            info!(target: chord::LOG_DHT, "[Node #{}] Read-only, rejecting a write to key {}", chord::short_id(&self.id), chord::short_id(key_id));
+            Some(Response::DHTReadOnly { key_id: key_id.clone() })
+        } else {
+            None
+        }
+    }
+
+    fn handle_set_maintenance_paused_request(&self, paused: bool) -> Response {
+        if paused {
+            self.pause_maintenance();
+        } else {
+            self.resume_maintenance();
+        }
+        Response::MaintenancePaused { paused }
+    }
+
+    fn handle_dump_storage_request(&self, keys_only: bool, cursor: Option<BigInt>) -> Response {
+        let mut data: Vec<(BigInt, Option<DHTEntry>)> = self.dump_storage().into_iter()
+            .filter(|(key_id, _)| match cursor {
+                Some(ref cursor) => key_id >= cursor,
+                None => true,
+            })
+            .map(|(key_id, entry)| if keys_only { (key_id, None) } else { (key_id, Some(entry)) })
+            .collect();
+        data.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let page_size = self.config.dump_page_size;
+        let next_cursor = if data.len() > page_size {
+            Some(data[page_size].0.clone())
+        } else {
+            None
+        };
+        data.truncate(page_size);
+        Response::DumpedStoragePage { node: self.to_other_node(), data, keys_only, next_cursor }
+    }
+
+    // RESPONSES
+
+    fn handle_found_successor_response(&mut self, successor: OtherNode, lookup: LookupContext) {
+        debug!(target: chord::LOG_STABILIZE, "Found my new successor: node #{} ({} hops, {:?})", chord::short_id(&successor.id), lookup.get_hops(), lookup.elapsed());
+        self.record_lookup(&lookup);
+        if self.joined {
+            self.update_successor_and_successor_list(successor);
+        } else {
+            self.propose_join_successor(successor);
+        }
+    }
+
+    /// Joins `successor` once it answered `ChordConfig::join_pings` pings. They are sent by
+    /// `chord::confirm_join_candidate` without holding the node lock, which the listener holds
+    /// while calling me.
+    fn propose_join_successor(&mut self, successor: OtherNode) {
+        if self.config.join_pings == 0 || successor == self.to_other_node() {
+            self.join_successor(successor);
+        } else {
+            self.join_candidate = Some(successor);
+            self.trigger_stabilize();
+        }
+    }
+
+    /// The successor found while joining, for `chord::confirm_join_candidate` to ping
+    pub fn take_join_candidate(&mut self) -> Option<OtherNode> {
+        if self.joined {
+            self.join_candidate = None;
+        }
+        self.join_candidate.take()
+    }
+
+    /// Joins the candidate of `take_join_candidate` if it answered the pings, so that the node
+    /// does not count as joined behind a successor that died after answering the lookup
+    pub fn confirm_join_candidate(&mut self, candidate: OtherNode, alive: bool) {
+        if self.joined {
+            return;
+        }
+        if alive {
+            self.join_successor(candidate);
+        } else {
+            // The join loop in spawn_node sends FindSuccessor again
+            warn!(target: chord::LOG_STABILIZE, "Found successor #{} does not answer pings, not joining it", chord::short_id(&candidate.id));
+        }
+    }
+
+    fn join_successor(&mut self, successor: OtherNode) {
+        self.update_successor_and_successor_list(successor.clone());
+        // Lookups would take as many hops as in a ring without fingers until fix_fingers filled them
+        self.network.send_request(self.to_other_node(), successor.ip_addr, Request::GetFingerTable);
+        if self.config.verify_join {
+            // Joined once the successor's predecessor confirms the placement
+            let req = Request::GetPredecessor;
+            self.network.send_request(self.to_other_node(), successor.ip_addr, req);
+        } else {
+            self.mark_joined();
+        }
+    }
+
+    /// Counts as joined and starts the write warmup, see `ChordConfig::write_warmup`
+    fn mark_joined(&mut self) {
+        self.joined = true;
+        if self.config.write_warmup > Duration::from_millis(0) {
+            self.warmup_until = Some(Instant::now() + self.config.write_warmup);
+        }
+    }
+
+    /// My successor while my write warmup lasts. Until my successor took me as predecessor it
+    /// still stores the keys I own and hands them to me afterwards, so no write gets lost.
+    fn warmup_write_target(&mut self) -> Option<OtherNode> {
+        match self.warmup_until {
+            Some(until) if Instant::now() < until && self.get_successor().id != self.id => Some(self.get_successor()),
+            Some(_) => {
+                self.end_write_warmup();
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn end_write_warmup(&mut self) {
+        if self.warmup_until.take().is_some() {
+            info!(target: chord::LOG_DHT, "[Node #{}] Write warmup over, storing my keys myself", chord::short_id(&self.id));
+        }
+    }
+
+    fn handle_ask_further_response(&mut self, next_node: OtherNode, lookup: LookupContext) {
+        if lookup.is_expired() {
+            self.handle_lookup_timed_out_response(self.id.clone(), lookup);
+            return;
+        }
+        debug!(target: chord::LOG_STABILIZE, "Did not get successor yet, asking node #{} now...", chord::short_id(&next_node.id));
+        let req = Request::FindSuccessor { id: self.id.clone(), lookup: lookup.forwarded() };
+        self.network.send_request(self.to_other_node(),next_node.ip_addr, req);
+    }
+
+    /// Sends the lookup to the busy node again a little later, as long as it has not expired.
+    /// Finger lookups are not, the next run of fix_fingers looks the finger up anyway.
+    fn handle_lookup_busy_response(&mut self, node: OtherNode, request: Request) {
+        match request.get_lookup() {
+            Some(lookup) if !lookup.is_expired() => {
+                self.network.send_request_later(self.to_other_node(), node.ip_addr, request, chord::LOOKUP_BUSY_RETRY_INTERVAL);
+            }
+            _ => debug!(target: chord::LOG_NETWORK, "[Node #{}] Dropping lookup answered busy by node #{}", chord::short_id(&self.id), chord::short_id(&node.id)),
+        }
+    }
+
+    /// In my own ring the successor of my id is me, or my successor while the ring does not know
+    /// of me yet. Any other node belongs to a ring formed apart from mine, e.g. during a network
+    /// partition. Taking it as successor links the two rings, stabilize then merges them and
+    /// moves the keys to their new owners.
+    fn handle_ring_probed_response(&mut self, successor: OtherNode) {
+        if successor == self.to_other_node() || successor == self.get_successor() {
+            return;
+        }
+        warn!(target: chord::LOG_STABILIZE, "[Node #{}] Node #{} belongs to another ring, merging the rings", chord::short_id(&self.id), chord::short_id(&successor.id));
+        self.update_successor_and_successor_list(successor);
+        self.trigger_stabilize();
+    }
+
+    fn handle_ask_further_probe_response(&mut self, next_node: OtherNode, lookup: LookupContext) {
+        if lookup.is_expired() {
+            debug!(target: chord::LOG_STABILIZE, "Ring probe timed out after {} hops", lookup.get_hops());
+            return;
+        }
+        let req = Request::ProbeRing { id: self.id.clone(), lookup: lookup.forwarded() };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_get_predecessor_response(&mut self, predecessor: Option<OtherNode>) {
+        if !self.check_successor_predecessor(predecessor) {
+            return;
+        }
+        // An observer never notifies, so no node takes it as predecessor and hands keys to it
+        if !self.config.observer {
+            let req = Request::Notify { node: self.to_other_node() };
+            self.network.send_request(self.to_other_node(), self.get_successor().ip_addr, req);
+        }
+    }
+
+    /// The Notify was handled with the request already. If the successor changed,
+    /// the next Stabilize goes to the new successor and notifies it.
+    fn handle_stabilize_response(&mut self, predecessor: Option<OtherNode>, accepted: bool) {
+        if accepted {
+            self.end_write_warmup();
+        }
+        self.check_successor_predecessor(predecessor);
+    }
+
+    /// Verifies a pending join and takes the successor's predecessor as new successor
+    /// if it lies between us. Returns false if the join has to be retried.
+    fn check_successor_predecessor(&mut self, predecessor: Option<OtherNode>) -> bool {
+        if !self.joined {
+            if self.is_placement_consistent(&predecessor) {
+                info!(target: chord::LOG_STABILIZE, "[Node #{}] Placement before node #{} verified, joined.", chord::short_id(&self.id), chord::short_id(&self.get_successor().id));
+                self.mark_joined();
+            } else {
+                // The successor took a node joining between us as predecessor while my lookup was
+                // on its way. Routing may not know of that node yet, so a new lookup would find
+                // the same successor again. The node is closer to me, verify the placement before it
+                // once it answered the pings. If it does not, the join loop in spawn_node sends
+                // FindSuccessor again.
+                if let Some(closer) = predecessor {
+                    info!(target: chord::LOG_STABILIZE, "[Node #{}] Node #{} joined before node #{} meanwhile, verifying placement before it...",
+                          chord::short_id(&self.id), chord::short_id(&closer.id), chord::short_id(&self.get_successor().id));
+                    self.propose_join_successor(closer);
+                }
+                return false;
+            }
+        }
+        if let Some(predecessor) = predecessor {
+            if predecessor.id == self.id {
+                // My successor took me as predecessor and forwards my keys to me now
+                self.end_write_warmup();
+            }
+            // maybe update my successor:
+            if predecessor.get_id() != &self.id &&
+                chord::is_in_interval(&self.id, self.get_successor().get_id(), predecessor.get_id()) {
+                debug!(target: chord::LOG_STABILIZE, "[Node #{}] GetPreResp: Had succ #{}, got pre #{}, new succ: #{}", chord::short_id(&self.id), chord::short_id(&self.get_successor().id), chord::short_id(&predecessor.id), chord::short_id(&predecessor.id));
+                self.update_successor_and_successor_list(predecessor);
+            }
+        }
+        true
+    }
+
+    /// The successor's predecessor must not lie between me and my successor
+    fn is_placement_consistent(&self, successor_predecessor: &Option<OtherNode>) -> bool {
+        match successor_predecessor {
+            Some(pre) => {
+                pre.id == self.id || *pre == self.get_successor() ||
+                    !chord::is_in_interval(&self.id, self.get_successor().get_id(), pre.get_id())
+            }
+            None => true,
+        }
+    }
+
+    fn handle_notify_response(&self) {}
+
+    fn handle_found_successor_finger_response(&mut self, index: usize, finger_id: BigInt, successor: OtherNode) {
+        debug!(target: chord::LOG_FIX_FINGERS, "Found node for finger_id {}: node #{}", chord::short_id(&finger_id), chord::short_id(&successor.id));
+
+        self.finger_table.put(index, finger_id, successor);
+        if index == chord::FINGERTABLE_SIZE - 1 {
+            //self.finger_table.print(self.id.clone());
+        }
+    }
+
+    fn handle_ask_further_finger_response(&mut self, index: usize, finger_id: BigInt, next_node: OtherNode) {
+        debug!(target: chord::LOG_FIX_FINGERS, "Did not get entry for finger {} (#{}) yet, asking node #{} now...", chord::short_id(&finger_id), index, chord::short_id(&next_node.id));
+        let req = Request::FindSuccessorFinger { index, finger_id };
+
+        self.network.send_request(self.to_other_node(),next_node.ip_addr, req);
+    }
+
+    /// Seeds the fingers fix_fingers has not found yet with the successor's fingers
+    fn handle_finger_table_response(&mut self, fingers: Vec<OtherNode>) {
+        let mut candidates = fingers;
+        candidates.push(self.get_successor());
+        candidates.push(self.to_other_node());
+        let known = self.finger_table.length();
+        self.finger_table.seed(&candidates);
+        debug!(target: chord::LOG_FIX_FINGERS, "[Node #{}] Seeded {} fingers from the successor's finger table",
+               chord::short_id(&self.id), self.finger_table.length() - known);
+    }
+
+    fn handle_get_successor_list_response(&mut self, successor_list: Vec<OtherNode>) {
+        if self.is_single_node_ring() {
+            // My own list still holds the nodes that were before me, e.g. the other node of a two-node ring
+            self.successor_list = vec![self.to_other_node()];
+            return;
+        }
+        let mut new_successor_list = vec![self.get_successor().clone()];
+        if successor_list.len() == chord::SUCCESSORLIST_SIZE {
+            new_successor_list.append(&mut successor_list.clone()[..(successor_list.len() - 1)].to_owned())
+        } else {
+            new_successor_list.append(&mut successor_list.clone())
+        };
+        self.successor_list = new_successor_list;
+    }
+
+    fn handle_dht_stored_key_response(&mut self, key: String) {
+        self.storage.write_log_entry(format!("Key '{}' stored", key));
+        debug!(target: chord::LOG_DHT, "Key '{}' stored", key);
+    }
+
+    fn handle_maintenance_paused_response(&mut self, paused: bool) {
+        let state = if paused { "paused" } else { "resumed" };
+        self.storage.write_log_entry(format!("Maintenance of peer {}", state));
+        info!(target: chord::LOG_STABILIZE, "Maintenance of peer {}", state);
+    }
+
+    fn handle_read_only_set_response(&mut self, read_only: bool) {
+        let state = if read_only { "read-only" } else { "writable" };
+        self.storage.write_log_entry(format!("Peer is {} now", state));
+        info!(target: chord::LOG_DHT, "Peer is {} now", state);
+    }
+
+    fn handle_dht_read_only_response(&mut self, key_id: BigInt) {
+        self.storage.write_log_entry(format!("Write to key {} rejected, its owner is read-only", chord::short_id(&key_id)));
+        info!(target: chord::LOG_DHT, "Write to key {} rejected, its owner is read-only", chord::short_id(&key_id));
+    }
+
+    fn handle_lookup_timed_out_response(&mut self, id: BigInt, lookup: LookupContext) {
+        if let Some(stream_id) = lookup.get_stream() {
+            self.value_streams.remove(&stream_id);
+        }
+        self.storage.write_log_entry(format!("Lookup of id {} timed out after {} hops", chord::short_id(&id), lookup.get_hops()));
+        warn!(target: chord::LOG_DHT, "Lookup of id {} timed out after {} hops ({:?})", chord::short_id(&id), lookup.get_hops(), lookup.elapsed());
+    }
+
+    fn handle_dht_rejected_key_response(&mut self, key: String, size: usize, max_size: usize) {
+        self.storage.write_log_entry(format!("Key '{}' rejected, value of {} bytes exceeds {} bytes", key, size, max_size));
+        info!(target: chord::LOG_DHT, "Key '{}' rejected, value of {} bytes exceeds {} bytes", key, size, max_size);
+    }
+
+    fn handle_dht_overloaded_response(&mut self, key: String, stored_bytes: usize) {
+        self.storage.write_log_entry(format!("Key '{}' not stored, the owner holds {} bytes already, try again later", key, stored_bytes));
+        info!(target: chord::LOG_DHT, "Key '{}' not stored, the owner holds {} bytes already, try again later", key, stored_bytes);
+    }
+
+    fn handle_dht_found_key_response(&mut self, data: (BigInt, Option<DHTEntry>), lookup: LookupContext) {
+        self.record_lookup(&lookup);
+        let elapsed = lookup.elapsed();
+        let cost = format!("{} hops, {}ms", lookup.get_hops(), elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()));
+        if let Some(dht_entry) = data.1.clone() {
+            let value = self.open_value(dht_entry.get_value());
+            self.storage.write_log_entry(format!("Value for key {} (id: {}) is {} ({})", dht_entry.get_key(), data.0, value, cost));
+            debug!(target: chord::LOG_DHT, "Value for key '{}' (id: {}) is '{}' ({})", dht_entry.get_key(), chord::short_id(&data.0), value, cost);
+        } else {
+            // Ends a stream waiting for the key
+            if let Some(stream_id) = lookup.get_stream() {
+                self.value_streams.remove(&stream_id);
+            }
+            self.storage.write_log_entry(format!("No value for key_id {} found in the network ({})", data.0, cost));
+            debug!(target: chord::LOG_DHT, "No value for key_id '{}' found in the network ({})", chord::short_id(&data.0), cost)
+        }
+    }
+
+    fn handle_dht_found_chunk_response(&mut self,
+                                       chunk_index: usize,
+                                       total_chunks: usize,
+                                       data: (BigInt, DHTEntry),
+                                       lookup: LookupContext) {
+        let dht_entry = data.1;
+        let stream_id = lookup.get_stream();
+        let complete = match stream_id.and_then(|stream_id| self.value_streams.get_mut(&stream_id)) {
+            Some(stream) => {
+                stream.pending.insert(chunk_index, dht_entry.get_value().to_vec());
+                stream.deadline = Instant::now() + chord::LOOKUP_TIMEOUT;
+                while let Some(chunk) = stream.pending.remove(&stream.next_index) {
+                    // The consumer may have dropped the stream already, the chunks are discarded then
+                    let _ = stream.chunks.unbounded_send(chunk);
+                    stream.next_index += 1;
+                }
+                stream.next_index >= total_chunks
+            }
+            None => {
+                debug!(target: chord::LOG_DHT, "Got chunk {}/{} of key '{}' nobody is streaming", chunk_index + 1, total_chunks, dht_entry.get_key());
+                false
+            }
+        };
+        if complete {
+            if let Some(stream_id) = stream_id {
+                self.value_streams.remove(&stream_id);
+            }
+            self.record_lookup(&lookup);
+            self.storage.write_log_entry(format!("Streamed value for key {} in {} chunks ({} hops)", dht_entry.get_key(), total_chunks, lookup.get_hops()));
+            debug!(target: chord::LOG_DHT, "Streamed value for key '{}' in {} chunks ({} hops)", dht_entry.get_key(), total_chunks, lookup.get_hops());
+        }
+    }
+
+    /// Decrypts a found value if a value encryption key is configured, as text for the logs
+    fn open_value(&self, value: &[u8]) -> String {
+        let opened = match &self.config.value_encryption_key {
+            Some(passphrase) => encryption::decrypt_value(passphrase, value).unwrap_or_else(|| {
+                warn!(target: chord::LOG_DHT, "Value could not be decrypted with the configured key");
+                value.to_vec()
+            }),
+            None => value.to_vec(),
+        };
+        String::from_utf8_lossy(&opened).into_owned()
+    }
+
+    fn handle_dht_deleted_key_response(&mut self, key_existed: bool) {
+        if key_existed {
+            self.storage.write_log_entry("Key deleted".to_string());
+            info!(target: chord::LOG_DHT, "Key deleted");
+        } else {
+            self.storage.write_log_entry("Tried to delete key but the key was not present in the network".to_string());
+            debug!(target: chord::LOG_DHT, "Tried to delete key but the key was not present in the network");
+        }
+    }
+
+    fn handle_dht_popped_response(&mut self, data: Option<DHTEntry>) {
+        match data {
+            Some(entry) => {
+                let value = self.open_value(entry.get_value());
+                self.storage.write_log_entry(format!("Popped key {}: {}", entry.get_key(), value));
+                info!(target: chord::LOG_DHT, "Popped key '{}': '{}'", entry.get_key(), value);
+            }
+            None => {
+                self.storage.write_log_entry("Tried to pop key but the key was not present in the network".to_string());
+                debug!(target: chord::LOG_DHT, "Tried to pop key but the key was not present in the network");
+            }
+        }
+    }
+
+    fn handle_dht_cas_result_response(&mut self, swapped: bool, current: Option<DHTEntry>) {
+        let current_value = current.map(|entry| self.open_value(entry.get_value())).unwrap_or_else(|| "absent".to_string());
+        if swapped {
+            self.storage.write_log_entry(format!("Value swapped, now '{}'", current_value));
+            debug!(target: chord::LOG_DHT, "Value swapped, now '{}'", current_value);
+        } else {
+            self.storage.write_log_entry(format!("Value not swapped, current value is '{}'", current_value));
+            debug!(target: chord::LOG_DHT, "Value not swapped, current value is '{}'", current_value);
+        }
+    }
+
+    fn handle_dht_transaction_applied_response(&mut self, ops: usize) {
+        self.storage.write_log_entry(format!("Transaction of {} ops applied", ops));
+        info!(target: chord::LOG_DHT, "Transaction of {} ops applied", ops);
+    }
+
+    fn handle_dht_transaction_rejected_response(&mut self, failed_op: usize, reason: String) {
+        self.storage.write_log_entry(format!("Transaction rejected, nothing applied: {}", reason));
+        warn!(target: chord::LOG_DHT, "Transaction rejected at op {}, nothing applied: {}", failed_op, reason);
+    }
+
+    fn handle_dht_appended_response(&mut self, key: String, length: usize) {
+        self.storage.write_log_entry(format!("Appended to key '{}', now {} bytes", key, length));
+        debug!(target: chord::LOG_DHT, "Appended to key '{}', now {} bytes", key, length);
+    }
+
+    fn handle_dht_stored_if_absent_response(&mut self, stored: bool) {
+        if stored {
+            self.storage.write_log_entry("Key was absent and got stored".to_string());
+            debug!(target: chord::LOG_DHT, "Key was absent and got stored");
+        } else {
+            self.storage.write_log_entry("Key already present, nothing stored".to_string());
+            debug!(target: chord::LOG_DHT, "Key already present, nothing stored");
+        }
+    }
+
+    /// Logs a page of a storage dump and asks for the next one, if there is one
+    fn handle_dumped_storage_page_response(&mut self, node: OtherNode, data: Vec<(BigInt, Option<DHTEntry>)>,
+                                           keys_only: bool, next_cursor: Option<BigInt>) {
+        self.storage.write_log_entry(format!("Storage dump page of node #{} contains {} keys", chord::short_id(&node.id), data.len()));
+        if let Some(cursor) = next_cursor {
+            let req = Request::DumpStorage { keys_only, cursor: Some(cursor) };
+            self.network.send_request(self.to_other_node(), node.ip_addr, req);
+        }
+        let mut dump = String::new();
+        for (key_id, entry) in data {
+            match entry {
+                Some(entry) => dump.push_str(&format!("\n{} '{}': '{}'", key_id, entry.get_key(), entry.value_string())),
+                None => dump.push_str(&format!("\n{}", key_id)),
+            }
+        }
+        info!(target: chord::LOG_DHT, "Storage dump:{}", dump);
+    }
+
+    fn handle_dht_ask_further_store_response(&self,
+                                             next_node: OtherNode,
+                                             data: (BigInt, DHTEntry),
+                                             moved: bool) {
+        debug!("Did not store data {:?} yet, asking node #{} now...", data, chord::short_id(&next_node.id));
+        let req = Request::DHTStoreKey { data, moved };
+        self.network.send_request(self.to_other_node(),next_node.ip_addr,req);
+    }
+
+    fn handle_dht_ask_further_compare_and_swap_response(&self,
+                                                        next_node: OtherNode,
+                                                        key_id: BigInt,
+                                                        expected: Option<DHTEntry>,
+                                                        new: DHTEntry) {
+        debug!(target: chord::LOG_DHT, "Did not compare and swap key '{}' yet, asking node #{} now...", chord::short_id(&key_id), chord::short_id(&next_node.id));
+        let req = Request::DHTCompareAndSwap { key_id, expected, new };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_transaction_response(&self, next_node: OtherNode, ops: Vec<DHTOp>) {
+        debug!(target: chord::LOG_DHT, "Did not apply transaction yet, asking node #{} now...", chord::short_id(&next_node.id));
+        let req = Request::DHTTransaction { ops };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_append_response(&self, next_node: OtherNode, key_id: BigInt, key: String, data: Vec<u8>) {
+        debug!(target: chord::LOG_DHT, "Did not append to key '{}' yet, asking node #{} now...", key, chord::short_id(&next_node.id));
+        let req = Request::DHTAppend { key_id, key, data };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_store_if_absent_response(&self,
+                                                       next_node: OtherNode,
+                                                       data: (BigInt, DHTEntry)) {
+        debug!("Did not store data {:?} if absent yet, asking node #{} now...", data, chord::short_id(&next_node.id));
+        let req = Request::DHTStoreIfAbsent { data };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_find_response(&mut self,
+                                            next_node: OtherNode,
+                                            key_id: BigInt,
+                                            lookup: LookupContext) {
+        if lookup.is_expired() {
+            self.handle_lookup_timed_out_response(key_id, lookup);
+            return;
+        }
+        debug!(target: chord::LOG_DHT, "Did not find key '{}' yet, asking node #{} now...", chord::short_id(&key_id), chord::short_id(&next_node.id));
+        let req = Request::DHTFindKey { key_id, lookup: lookup.forwarded() };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_find_stream_response(&mut self,
+                                                   next_node: OtherNode,
+                                                   key_id: BigInt,
+                                                   lookup: LookupContext) {
+        if lookup.is_expired() {
+            self.handle_lookup_timed_out_response(key_id, lookup);
+            return;
+        }
+        debug!(target: chord::LOG_DHT, "Did not find key '{}' to stream yet, asking node #{} now...", chord::short_id(&key_id), chord::short_id(&next_node.id));
+        let req = Request::DHTFindKeyStream { key_id, requester: self.to_other_node(), lookup: lookup.forwarded() };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_delete_response(&self,
+                                              next_node: OtherNode,
+                                              key_id: BigInt) {
+        debug!(target: chord::LOG_DHT, "Did not find key '{}' yet, asking node #{} now...", chord::short_id(&key_id), chord::short_id(&next_node.id));
+        let req = Request::DHTDeleteKey { key_id };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_get_and_delete_response(&self,
+                                                      next_node: OtherNode,
+                                                      key_id: BigInt) {
+        debug!(target: chord::LOG_DHT, "Did not find key '{}' yet, asking node #{} now...", chord::short_id(&key_id), chord::short_id(&next_node.id));
+        let req = Request::DHTGetAndDelete { key_id };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+
+    fn handle_dht_ask_further_store_chunk_response(&self,
+                                                   next_node: OtherNode,
+                                                   chunk_index: usize,
+                                                   total_chunks: usize,
+                                                   data: (BigInt, DHTEntry)) {
+        debug!(target: chord::LOG_DHT, "Did not store chunk {}/{} of key '{}' yet, asking node #{} now...",
+               chunk_index + 1, total_chunks, chord::short_id(&data.0), chord::short_id(&next_node.id));
+        let req = Request::DHTStoreChunk { chunk_index, total_chunks, data };
+        self.network.send_request(self.to_other_node(), next_node.ip_addr, req);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::super::config::NodeIdentity;
+
+    // Nothing listens there, messages the node sends to others get lost
+    fn unreachable_addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn first_node() -> Node {
+        first_node_with(ChordConfig::default())
+    }
+
+    fn first_node_with(config: ChordConfig) -> Node {
+        Node::new_first(unreachable_addr(1), config)
+    }
+
+    // Values of at most 8 bytes are allowed
+    fn limited_first_node() -> Node {
+        first_node_with(ChordConfig { max_value_size: 8, ..ChordConfig::default() })
+    }
+
+    fn stored_value(node: &Node, key_id: &BigInt) -> Option<Vec<u8>> {
+        node.storage.get_key(key_id).map(|entry| entry.get_value().to_vec())
+    }
+
+    fn found_chunk(stream_id: u64, chunk_index: usize, key: &str, chunk: &[u8]) -> Response {
+        let key_id = chord::create_key_id(key, ChordConfig::default().key_space_bits);
+        Response::DHTFoundChunk {
+            chunk_index,
+            total_chunks: 2,
+            data: (key_id, DHTEntry::from_bytes(key.to_string(), chunk.to_vec())),
+            lookup: LookupContext::new().for_stream(stream_id),
+        }
+    }
+
+    fn key_value(key: &str, value: &str) -> (BigInt, DHTEntry) {
+        storage::make_hashed_key_value_pair(key.to_string(), value, ChordConfig::default().key_space_bits)
+    }
+
+    // Owns the half of the ring before it, a node in the middle of the ring is its predecessor
+    fn half_ring_owner() -> Node {
+        let mut node = first_node();
+        let predecessor = peer(&node, 4, 2);
+        node.set_predecessor(Some(predecessor));
+        node
+    }
+
+    // A key in the half of the ring `half_ring_owner` does not own
+    fn foreign_key_id(node: &Node) -> BigInt {
+        peer(node, 2, 0).get_id().clone()
+    }
+
+    #[test]
+    fn a_value_over_the_size_limit_is_rejected_and_not_stored() {
+        let mut node = limited_first_node();
+        let (large_id, large) = key_value("large", "more than eight bytes");
+        match node.process_incoming_request(Request::DHTStoreKey { data: (large_id.clone(), large), moved: false }) {
+            Some(Response::DHTRejectedKey { ref key, size: 21, max_size: 8 }) if key == "large" => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &large_id), None);
+        assert_eq!(node.storage.bytes_stored(), 0);
+
+        let (small_id, small) = key_value("small", "eight by");
+        match node.process_incoming_request(Request::DHTStoreKey { data: (small_id.clone(), small), moved: false }) {
+            Some(Response::DHTStoredKey { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &small_id), Some(b"eight by".to_vec()));
+    }
+
+    #[test]
+    fn stores_past_the_high_water_mark_are_shed_while_reads_and_deletes_are_served() {
+        let mut node = first_node_with(ChordConfig { storage_high_water_mark: 32, ..ChordConfig::default() });
+        let mut stored = Vec::new();
+        for i in 0.. {
+            let (key_id, entry) = key_value(&format!("key{}", i), "a value of some bytes");
+            match node.process_incoming_request(Request::DHTStoreKey { data: (key_id.clone(), entry), moved: false }) {
+                Some(Response::DHTStoredKey { .. }) => stored.push(key_id),
+                Some(Response::DHTOverloaded { stored_bytes, high_water_mark: 32, .. }) => {
+                    assert_eq!(stored_bytes, node.storage.bytes_stored());
+                    assert!(stored_bytes >= 32);
+                    assert_eq!(stored_value(&node, &key_id), None);
+                    break;
+                }
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        assert!(!stored.is_empty());
+
+        match node.process_incoming_request(Request::DHTFindKey { key_id: stored[0].clone(), lookup: LookupContext::new() }) {
+            Some(Response::DHTFoundKey { data: (_, Some(entry)), .. }) => assert_eq!(entry.get_value(), b"a value of some bytes"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        // Deleting makes room again
+        for key_id in stored {
+            match node.process_incoming_request(Request::DHTDeleteKey { key_id }) {
+                Some(Response::DHTDeletedKey { key_existed: true }) => {}
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        match node.process_incoming_request(Request::DHTStoreKey { data: key_value("key", "value"), moved: false }) {
+            Some(Response::DHTStoredKey { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_read_only_owner_rejects_stores_and_deletes_but_answers_finds() {
+        let mut node = first_node();
+        let (key_id, entry) = key_value("key", "value");
+        node.storage.store_key((key_id.clone(), entry));
+        match node.process_incoming_request(Request::SetReadOnly { read_only: true }) {
+            Some(Response::ReadOnlySet { read_only: true }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        match node.process_incoming_request(Request::DHTStoreKey { data: key_value("key", "other"), moved: false }) {
+            Some(Response::DHTReadOnly { key_id: ref rejected }) => assert_eq!(*rejected, key_id),
+            other => panic!("unexpected response {:?}", other),
+        }
+        match node.process_incoming_request(Request::DHTDeleteKey { key_id: key_id.clone() }) {
+            Some(Response::DHTReadOnly { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        match node.process_incoming_request(Request::DHTFindKey { key_id: key_id.clone(), lookup: LookupContext::new() }) {
+            Some(Response::DHTFoundKey { data: (_, Some(entry)), .. }) => assert_eq!(entry.get_value(), b"value"),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        node.process_incoming_request(Request::SetReadOnly { read_only: false });
+        match node.process_incoming_request(Request::DHTStoreKey { data: key_value("key", "other"), moved: false }) {
+            Some(Response::DHTStoredKey { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"other".to_vec()));
+    }
+
+    #[test]
+    fn an_observer_passes_stores_on_without_keeping_them() {
+        let config = ChordConfig { observer: true, join_pings: 0, ..ChordConfig::default() };
+        let mut node = Node::new(unreachable_addr(1), config);
+        let successor = peer(&node, 4, 2);
+        node.process_incoming_response(Response::FoundSuccessor { successor: successor.clone(), lookup: LookupContext::new() });
+        assert!(node.is_joined());
+        // No node becomes the predecessor of an observer, so it owns no part of the ring
+        node.process_incoming_request(Request::Notify { node: peer(&node, 6, 3) });
+        assert_eq!(*node.get_predecessor(), None);
+
+        for i in 0..10 {
+            let (key_id, entry) = key_value(&format!("key{}", i), "value");
+            match node.process_incoming_request(Request::DHTStoreKey { data: (key_id.clone(), entry), moved: false }) {
+                Some(Response::DHTAskFurtherStore { next_node, data: (asked_id, _), moved: false }) => {
+                    assert_eq!(next_node, successor);
+                    assert_eq!(asked_id, key_id);
+                }
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        assert!(node.storage.is_data_empty());
+    }
+
+    #[test]
+    fn store_if_absent_stores_only_the_first_value() {
+        let mut node = first_node();
+        let first = key_value("key", "first");
+        let key_id = first.0.clone();
+        match node.process_incoming_request(Request::DHTStoreIfAbsent { data: first }) {
+            Some(Response::DHTStoredIfAbsent { stored: true }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        match node.process_incoming_request(Request::DHTStoreIfAbsent { data: key_value("key", "second") }) {
+            Some(Response::DHTStoredIfAbsent { stored: false }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"first".to_vec()));
+    }
+
+    #[test]
+    fn store_if_absent_is_rejected_by_a_read_only_owner_and_for_large_values() {
+        let mut node = limited_first_node();
+        let large = key_value("large", "more than eight bytes");
+        let large_id = large.0.clone();
+        match node.process_incoming_request(Request::DHTStoreIfAbsent { data: large }) {
+            Some(Response::DHTRejectedKey { max_size: 8, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &large_id), None);
+
+        node.set_read_only(true);
+        let small = key_value("small", "value");
+        let small_id = small.0.clone();
+        match node.process_incoming_request(Request::DHTStoreIfAbsent { data: small }) {
+            Some(Response::DHTReadOnly { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &small_id), None);
+    }
+
+    fn compare_and_swap(node: &mut Node, key_id: &BigInt, expected: Option<&str>, new: &str) -> Option<Response> {
+        let entry = |value: &str| DHTEntry::new("key".to_string(), value.to_string());
+        node.process_incoming_request(Request::DHTCompareAndSwap {
+            key_id: key_id.clone(),
+            expected: expected.map(entry),
+            new: entry(new),
+        })
+    }
+
+    #[test]
+    fn compare_and_swap_swaps_if_the_value_matches() {
+        let mut node = first_node();
+        let (key_id, _) = key_value("key", "");
+        // Absent is expected as `None`
+        match compare_and_swap(&mut node, &key_id, None, "first") {
+            Some(Response::DHTCasResult { swapped: true, current: Some(current) }) => assert_eq!(current.get_value(), b"first"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        match compare_and_swap(&mut node, &key_id, Some("first"), "second") {
+            Some(Response::DHTCasResult { swapped: true, current: Some(current) }) => assert_eq!(current.get_value(), b"second"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"second".to_vec()));
+    }
+
+    #[test]
+    fn compare_and_swap_with_another_value_returns_the_current_one() {
+        let mut node = first_node();
+        let (key_id, _) = key_value("key", "");
+        compare_and_swap(&mut node, &key_id, None, "current");
+        match compare_and_swap(&mut node, &key_id, Some("outdated"), "new") {
+            Some(Response::DHTCasResult { swapped: false, current: Some(current) }) => assert_eq!(current.get_value(), b"current"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        // The key is not absent anymore
+        match compare_and_swap(&mut node, &key_id, None, "new") {
+            Some(Response::DHTCasResult { swapped: false, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"current".to_vec()));
+
+        node.set_read_only(true);
+        match compare_and_swap(&mut node, &key_id, Some("current"), "new") {
+            Some(Response::DHTReadOnly { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"current".to_vec()));
+    }
+
+    #[test]
+    fn pop_returns_the_entry_once_and_deletes_it() {
+        let mut node = first_node();
+        let (key_id, entry) = key_value("key", "value");
+        node.storage.store_key((key_id.clone(), entry));
+        match node.process_incoming_request(Request::DHTGetAndDelete { key_id: key_id.clone() }) {
+            Some(Response::DHTPopped { data: Some(entry) }) => assert_eq!(entry.get_value(), b"value"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), None);
+        match node.process_incoming_request(Request::DHTGetAndDelete { key_id }) {
+            Some(Response::DHTPopped { data: None }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pop_is_rejected_by_a_read_only_owner_and_passed_on_by_others() {
+        let mut node = first_node();
+        let (key_id, entry) = key_value("key", "value");
+        node.storage.store_key((key_id.clone(), entry));
+        node.set_read_only(true);
+        match node.process_incoming_request(Request::DHTGetAndDelete { key_id: key_id.clone() }) {
+            Some(Response::DHTReadOnly { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"value".to_vec()));
+
+        let mut node = half_ring_owner();
+        let key_id = foreign_key_id(&node);
+        match node.process_incoming_request(Request::DHTGetAndDelete { key_id: key_id.clone() }) {
+            Some(Response::DHTAskFurtherGetAndDelete { key_id: asked_id, .. }) => assert_eq!(asked_id, key_id),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    fn append(node: &mut Node, key_id: &BigInt, data: &str) -> Option<Response> {
+        node.process_incoming_request(Request::DHTAppend { key_id: key_id.clone(), key: "key".to_string(), data: data.as_bytes().to_vec() })
+    }
+
+    #[test]
+    fn append_creates_the_key_and_appends_to_it() {
+        let mut node = first_node();
+        let (key_id, _) = key_value("key", "");
+        match append(&mut node, &key_id, "abc") {
+            Some(Response::DHTAppended { length: 3, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        match append(&mut node, &key_id, "de") {
+            Some(Response::DHTAppended { length: 5, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"abcde".to_vec()));
+    }
+
+    #[test]
+    fn append_beyond_the_value_size_limit_is_rejected() {
+        let mut node = limited_first_node();
+        let (key_id, _) = key_value("key", "");
+        append(&mut node, &key_id, "abcdef");
+        // The limit applies to the value after the append
+        match append(&mut node, &key_id, "ghi") {
+            Some(Response::DHTRejectedKey { size: 9, max_size: 8, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"abcdef".to_vec()));
+
+        node.set_read_only(true);
+        match append(&mut node, &key_id, "g") {
+            Some(Response::DHTReadOnly { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &key_id), Some(b"abcdef".to_vec()));
+    }
+
+    fn store_op(key_id: &BigInt, value: &str) -> DHTOp {
+        DHTOp::Store { data: (key_id.clone(), DHTEntry::new("key".to_string(), value.to_string())) }
+    }
+
+    fn compare_and_swap_op(key_id: &BigInt, expected: Option<&str>, new: &str) -> DHTOp {
+        let entry = |value: &str| DHTEntry::new("key".to_string(), value.to_string());
+        DHTOp::CompareAndSwap { key_id: key_id.clone(), expected: expected.map(entry), new: entry(new) }
+    }
+
+    #[test]
+    fn transaction_applies_all_ops_in_order() {
+        let mut node = first_node();
+        let (first, _) = key_value("first", "");
+        let (second, _) = key_value("second", "");
+        let (deleted, entry) = key_value("deleted", "value");
+        node.storage.store_key((deleted.clone(), entry));
+        let ops = vec![
+            store_op(&first, "a"),
+            // Sees the value of the op before it
+            compare_and_swap_op(&first, Some("a"), "b"),
+            compare_and_swap_op(&second, None, "c"),
+            DHTOp::Delete { key_id: deleted.clone() },
+        ];
+        match node.process_incoming_request(Request::DHTTransaction { ops }) {
+            Some(Response::DHTTransactionApplied { ops: 4 }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &first), Some(b"b".to_vec()));
+        assert_eq!(stored_value(&node, &second), Some(b"c".to_vec()));
+        assert_eq!(stored_value(&node, &deleted), None);
+    }
+
+    #[test]
+    fn transaction_with_a_mismatch_applies_nothing() {
+        let mut node = first_node();
+        let (first, _) = key_value("first", "");
+        let (second, entry) = key_value("second", "current");
+        node.storage.store_key((second.clone(), entry));
+        let ops = vec![store_op(&first, "a"), compare_and_swap_op(&second, Some("outdated"), "b")];
+        match node.process_incoming_request(Request::DHTTransaction { ops }) {
+            Some(Response::DHTTransactionRejected { failed_op: 1, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &first), None);
+        assert_eq!(stored_value(&node, &second), Some(b"current".to_vec()));
+    }
+
+    #[test]
+    fn transaction_with_a_key_of_another_node_applies_nothing() {
+        let mut node = half_ring_owner();
+        let own = node.get_id().clone();
+        let foreign = foreign_key_id(&node);
+        let ops = vec![store_op(&own, "a"), store_op(&foreign, "b")];
+        match node.process_incoming_request(Request::DHTTransaction { ops }) {
+            Some(Response::DHTTransactionRejected { failed_op: 1, .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(stored_value(&node, &own), None);
+        // Goes to the owner of the first key
+        let ops = vec![store_op(&foreign, "b"), store_op(&own, "a")];
+        match node.process_incoming_request(Request::DHTTransaction { ops }) {
+            Some(Response::DHTAskFurtherTransaction { .. }) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    // A node at the given fraction of the ring after `node`
+    fn peer(node: &Node, eighths: usize, port: u16) -> OtherNode {
+        let offset = BigInt::from(chord::CHORD_RING_SIZE / 8 * eighths);
+        OtherNode::new(chord::x_modulo_ring_size(node.get_id() + offset), unreachable_addr(port))
+    }
+
+    // The full successor list a node sends in a small ring: the ring, repeated
+    fn repeated_ring(ring: &[OtherNode]) -> Vec<OtherNode> {
+        ring.iter().cycle().take(chord::SUCCESSORLIST_SIZE).cloned().collect()
+    }
+
+    // My successor answers Stabilize with its predecessor, then GetSuccessorList with its list
+    fn stabilize_round(node: &mut Node, successor_predecessor: &OtherNode, successor_list: Vec<OtherNode>) {
+        node.process_incoming_response(Response::StabilizeResponse {
+            predecessor: Some(successor_predecessor.clone()),
+            accepted: true,
+            recently_dead: Vec::new(),
+        });
+        node.process_incoming_response(Response::GetSuccessorListResponse { successor_list, reservations: Vec::new() });
+    }
+
+    fn assert_successor_list_in_ring_order(node: &Node) {
+        let successors = node.successor_list();
+        assert_eq!(successors[0], node.get_successor());
+        let distances: Vec<BigInt> = successors.iter()
+            .map(|succ| match chord::chord_abs(succ.get_id(), node.get_id()) {
+                ref distance if *distance == BigInt::from(0) => BigInt::from(chord::CHORD_RING_SIZE),
+                distance => distance,
+            })
+            .collect();
+        for pair in distances.windows(2) {
+            assert!(pair[0] < pair[1], "successor list {:?} out of ring order", successors);
+        }
+        let unique: HashSet<&OtherNode> = successors.iter().collect();
+        assert_eq!(unique.len(), successors.len());
+    }
+
+    #[test]
+    fn joining_node_verifies_its_placement_before_a_node_that_joined_meanwhile() {
+        let config = ChordConfig { verify_join: true, join_pings: 0, ..ChordConfig::default() };
+        let mut node = Node::new(unreachable_addr(1), config);
+        let successor = peer(&node, 4, 2);
+        let closer = peer(&node, 2, 3);
+        let predecessor = peer(&node, 6, 4);
+
+        node.process_incoming_response(Response::FoundSuccessor { successor: successor.clone(), lookup: LookupContext::new() });
+        assert!(!node.is_joined());
+        assert_eq!(node.get_successor(), successor);
+
+        // My successor took another joiner between us as predecessor
+        node.process_incoming_response(Response::GetPredecessorResponse { predecessor: Some(closer.clone()), recently_dead: Vec::new() });
+        assert!(!node.is_joined());
+        assert_eq!(node.get_successor(), closer);
+
+        // Its predecessor is before me, so the placement holds
+        node.process_incoming_response(Response::GetPredecessorResponse { predecessor: Some(predecessor), recently_dead: Vec::new() });
+        assert!(node.is_joined());
+        assert_eq!(node.get_successor(), closer);
+    }
+
+    #[test]
+    fn a_node_that_joined_meanwhile_is_pinged_before_verifying_the_placement_before_it() {
+        let config = ChordConfig { verify_join: true, ..ChordConfig::default() };
+        let mut node = Node::new(unreachable_addr(1), config);
+        let successor = peer(&node, 4, 2);
+        let closer = peer(&node, 2, 3);
+        node.join_successor(successor.clone());
+
+        node.process_incoming_response(Response::GetPredecessorResponse { predecessor: Some(closer.clone()), recently_dead: Vec::new() });
+        // Not pinged while the listener holds the node
+        assert_eq!(node.get_successor(), successor);
+        assert_eq!(node.take_join_candidate(), Some(closer.clone()));
+
+        node.confirm_join_candidate(closer.clone(), true);
+        assert!(!node.is_joined());
+        assert_eq!(node.get_successor(), closer);
+    }
+
+    fn predecessor_addr(node: &Node) -> SocketAddr {
+        *node.get_predecessor().as_ref().unwrap().get_ip_addr()
+    }
+
+    #[test]
+    fn notifies_end_with_the_closest_predecessor_in_either_order() {
+        for &(first, second) in &[(6, 7), (7, 6)] {
+            let mut node = half_ring_owner();
+            let first = peer(&node, first, 3);
+            let second = peer(&node, second, 4);
+            node.process_incoming_request(Request::Notify { node: first });
+            node.process_incoming_request(Request::Notify { node: second });
+            assert_eq!(*node.get_predecessor(), Some(peer(&node, 7, 0)));
+        }
+    }
+
+    #[test]
+    fn claims_of_the_predecessor_id_end_with_the_lower_address_in_either_order() {
+        let node = half_ring_owner();
+        let lower = node.get_predecessor().clone().unwrap();
+        let higher = OtherNode::new(lower.get_id().clone(), unreachable_addr(3));
+
+        let mut node = half_ring_owner();
+        node.process_incoming_request(Request::Notify { node: higher.clone() });
+        // Both answer the check of the predecessor
+        assert!(!node.resolve_predecessor_claim(&lower, true));
+        assert_eq!(predecessor_addr(&node), lower.ip_addr);
+
+        let mut node = half_ring_owner();
+        node.set_predecessor(Some(higher.clone()));
+        node.process_incoming_request(Request::Notify { node: lower.clone() });
+        assert_eq!(predecessor_addr(&node), lower.ip_addr);
+        assert!(!node.resolve_predecessor_claim(&lower, true));
+        assert_eq!(predecessor_addr(&node), lower.ip_addr);
+    }
+
+    #[test]
+    fn a_claim_of_the_predecessor_id_from_a_higher_address_wins_once_the_predecessor_is_dead() {
+        let mut node = half_ring_owner();
+        let lower = node.get_predecessor().clone().unwrap();
+        let higher = OtherNode::new(lower.get_id().clone(), unreachable_addr(3));
+        node.process_incoming_request(Request::Notify { node: higher.clone() });
+        assert_eq!(predecessor_addr(&node), lower.ip_addr);
+
+        assert!(node.resolve_predecessor_claim(&lower, false));
+        assert_eq!(predecessor_addr(&node), higher.ip_addr);
+    }
+
+    #[test]
+    fn successor_list_stays_in_ring_order_while_nodes_join() {
+        let mut node = first_node();
+        let me = node.to_other_node();
+        let b = peer(&node, 4, 2);
+        let c = peer(&node, 2, 3);
+        let d = peer(&node, 6, 4);
+
+        // b joins before me and becomes my successor
+        stabilize_round(&mut node, &b, repeated_ring(&[me.clone(), b.clone()]));
+        assert_eq!(node.get_successor(), b);
+        assert_successor_list_in_ring_order(&node);
+
+        // c joins between me and b
+        stabilize_round(&mut node, &c, repeated_ring(&[b.clone(), me.clone(), c.clone()]));
+        assert_eq!(node.get_successor(), c);
+        assert_successor_list_in_ring_order(&node);
+
+        // d joins between b and me, c learns of it
+        stabilize_round(&mut node, &me, repeated_ring(&[b.clone(), d.clone(), me.clone(), c.clone()]));
+        assert_successor_list_in_ring_order(&node);
+
+        // Another round with the same answers changes nothing
+        stabilize_round(&mut node, &me, repeated_ring(&[b.clone(), d.clone(), me.clone(), c.clone()]));
+        assert_successor_list_in_ring_order(&node);
+        assert_eq!(node.successor_list(), vec![c, b, d, me]);
+    }
+
+    // The node I know as `other`, with its id and address
+    fn node_as(other: &OtherNode) -> Node {
+        Node::new_first(*other.get_ip_addr(), ChordConfig { node_id: Some(other.get_id().clone()), ..ChordConfig::default() })
+    }
+
+    #[test]
+    fn a_node_my_successor_found_dead_is_pruned_within_one_stabilize_round() {
+        let mut node = first_node();
+        let me = node.to_other_node();
+        let successor = peer(&node, 2, 2);
+        let dead = peer(&node, 4, 3);
+        let next = peer(&node, 6, 4);
+        stabilize_round(&mut node, &successor, repeated_ring(&[dead.clone(), next.clone(), me.clone(), successor.clone()]));
+        assert_eq!(node.successor_list(), vec![successor.clone(), dead.clone(), next.clone(), me.clone()]);
+
+        // My successor's ping of the dead node failed, mine did not run yet
+        let mut detector = node_as(&successor);
+        detector.record_dead(dead.get_id().clone());
+        let response = detector.process_incoming_request(Request::Stabilize { from: me.clone() }).unwrap();
+        node.process_incoming_response(response);
+        assert_eq!(node.successor_list(), vec![successor, next, me]);
+        assert!(node.dead_gossip().is_empty());
+    }
+
+    // My successor and its predecessor after one stabilize round, with the combined Stabilize or
+    // with GetPredecessor and the Notify sent to my successor afterwards
+    fn after_stabilize_round(combined: bool, successor_predecessor: usize) -> (OtherNode, Option<OtherNode>) {
+        let mut node = first_node();
+        let mut successor = node_as(&peer(&node, 4, 2));
+        successor.set_predecessor(Some(peer(&node, successor_predecessor, 3)));
+        node.update_successor_and_successor_list(successor.to_other_node());
+        if combined {
+            let response = successor.process_incoming_request(Request::Stabilize { from: node.to_other_node() }).unwrap();
+            node.process_incoming_response(response);
+        } else {
+            let response = successor.process_incoming_request(Request::GetPredecessor).unwrap();
+            node.process_incoming_response(response);
+            if node.get_successor() == successor.to_other_node() {
+                successor.process_incoming_request(Request::Notify { node: node.to_other_node() });
+            }
+        }
+        (node.get_successor(), successor.get_predecessor().clone())
+    }
+
+    #[test]
+    fn the_combined_stabilize_ends_in_the_same_routing_state_as_get_predecessor_and_notify() {
+        // The predecessor of my successor is between us, or before me
+        let node = first_node();
+        let (successor, between) = (peer(&node, 4, 2), peer(&node, 2, 3));
+        assert_eq!(after_stabilize_round(true, 2), (between.clone(), Some(between.clone())));
+        assert_eq!(after_stabilize_round(false, 2), (between.clone(), Some(between)));
+        assert_eq!(after_stabilize_round(true, 6), (successor.clone(), Some(node.to_other_node())));
+        assert_eq!(after_stabilize_round(false, 6), (successor, Some(node.to_other_node())));
+    }
+
+    #[test]
+    fn nodes_with_the_same_custom_identity_get_the_same_id_whatever_their_address() {
+        // Intended: a stable name keeps the id of a node whose address changes
+        let named = ChordConfig { identity: NodeIdentity::Custom("node-a".to_string()), ..ChordConfig::default() };
+        let node = Node::new_first(unreachable_addr(1), named.clone());
+        let moved = Node::new(unreachable_addr(2), named);
+        assert_eq!(node.get_id(), moved.get_id());
+        assert_eq!(*node.get_id(), chord::create_node_id("node-a"));
+
+        // By default the address is hashed
+        assert_ne!(first_node().get_id(), Node::new(unreachable_addr(2), ChordConfig::default()).get_id());
+    }
+
+    // The events of `watch` until `node` is dropped
+    fn ownership_events(node: Node, watch: impl Stream<Item = OwnershipEvent, Error = ()>) -> Vec<OwnershipEvent> {
+        drop(node);
+        watch.collect().wait().unwrap()
+    }
+
+    fn event_segments(events: &[OwnershipEvent], gained: bool) -> Vec<(BigInt, BigInt)> {
+        events.iter().filter_map(|event| match (event, gained) {
+            (OwnershipEvent::Gained { start, end }, true) | (OwnershipEvent::Lost { start, end }, false) => Some((start.clone(), end.clone())),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn a_joining_node_gains_the_part_of_a_watched_range_its_successor_loses() {
+        let mut former_owner = first_node();
+        let (start, end) = (peer(&former_owner, 2, 0).get_id().clone(), peer(&former_owner, 6, 0).get_id().clone());
+        let watched = chord::ring_segments(&start, &end);
+        let former_watch = former_owner.watch_range(start.clone(), end.clone());
+
+        let config = ChordConfig { node_id: Some(peer(&former_owner, 4, 0).get_id().clone()), join_pings: 0, ..ChordConfig::default() };
+        let mut newcomer = Node::new(unreachable_addr(2), config);
+        let newcomer_watch = newcomer.watch_range(start, end);
+        newcomer.process_incoming_response(Response::FoundSuccessor { successor: former_owner.to_other_node(), lookup: LookupContext::new() });
+        assert!(newcomer.is_joined());
+        former_owner.process_incoming_request(Request::Notify { node: newcomer.to_other_node() });
+        newcomer.process_incoming_request(Request::Notify { node: former_owner.to_other_node() });
+
+        let taken_over = chord::intersect_segments(&watched, &chord::owned_segments(former_owner.get_id(), newcomer.get_id()));
+        assert!(!taken_over.is_empty());
+        let former_events = ownership_events(former_owner, former_watch);
+        assert_eq!(event_segments(&former_events, true), watched);
+        assert_eq!(event_segments(&former_events, false), taken_over);
+        let newcomer_events = ownership_events(newcomer, newcomer_watch);
+        assert_eq!(event_segments(&newcomer_events, true), taken_over);
+        assert!(event_segments(&newcomer_events, false).is_empty());
+    }
+
+    #[test]
+    fn a_node_owning_far_more_keys_than_its_successors_reports_a_load_imbalance() {
+        let mut node = first_node();
+        let me = node.to_other_node();
+        let successor = peer(&node, 2, 2);
+        let next = peer(&node, 4, 3);
+        stabilize_round(&mut node, &successor, repeated_ring(&[next.clone(), me.clone(), successor.clone()]));
+        for i in 0..30 {
+            node.storage.store_key(key_value(&format!("key{}", i), "value"));
+        }
+
+        node.process_incoming_response(Response::KeysOwned { id: successor.get_id().clone(), count: 25 });
+        node.process_incoming_response(Response::KeysOwned { id: next.get_id().clone(), count: 35 });
+        assert_eq!(node.check_load(), None);
+
+        node.process_incoming_response(Response::KeysOwned { id: successor.get_id().clone(), count: 1 });
+        node.process_incoming_response(Response::KeysOwned { id: next.get_id().clone(), count: 2 });
+        // Counts of nodes which are no successors (anymore) are left out
+        node.process_incoming_response(Response::KeysOwned { id: peer(&node, 6, 4).get_id().clone(), count: 30 });
+        assert_eq!(node.check_load(), Some(11.0));
+        assert!(node.storage.get_last_three_log_entries().iter().any(|entry| entry.contains("LoadImbalance")));
+    }
+
+    #[test]
+    fn other_nodes_with_the_same_id_are_equal_whatever_their_address() {
+        let id = BigInt::from(42);
+        let node = OtherNode::new(id.clone(), unreachable_addr(1));
+        let moved = OtherNode::new(id, unreachable_addr(2));
+        assert_eq!(node, moved);
+        assert_ne!(node, OtherNode::new(BigInt::from(43), unreachable_addr(1)));
+
+        let mut nodes = HashSet::new();
+        nodes.insert(node);
+        assert!(!nodes.insert(moved.clone()));
+        assert!(nodes.contains(&moved));
+        // The copy already in the set is kept with its old address
+        assert_eq!(nodes.get(&moved).unwrap().get_ip_addr(), &unreachable_addr(1));
+    }
+
+    #[test]
+    fn concurrent_streams_of_a_key_get_their_own_chunks() {
+        let mut node = first_node();
+        let first = node.find_key_stream("key");
+        let second = node.find_key_stream("key");
+        let stream_ids: Vec<u64> = node.value_streams.keys().cloned().collect();
+        assert_eq!(stream_ids.len(), 2);
+        // The second stream gets its chunks first and out of order
+        node.process_incoming_response(found_chunk(stream_ids[1], 1, "key", b"d"));
+        node.process_incoming_response(found_chunk(stream_ids[0], 0, "key", b"a"));
+        node.process_incoming_response(found_chunk(stream_ids[1], 0, "key", b"c"));
+        node.process_incoming_response(found_chunk(stream_ids[0], 1, "key", b"b"));
+        assert!(node.value_streams.is_empty());
+        let mut chunks = vec![first.collect().wait().unwrap(), second.collect().wait().unwrap()];
+        chunks.sort();
+        assert_eq!(chunks, vec![vec![b"a".to_vec(), b"b".to_vec()], vec![b"c".to_vec(), b"d".to_vec()]]);
+    }
+
+    // Pretends the responses of all outstanding requests were lost for longer than they may take
+    fn lose_outstanding_responses(node: &mut Node) {
+        let now = Instant::now();
+        for stream in node.value_streams.values_mut() {
+            stream.deadline = now;
+        }
+        for deletion in node.namespace_deletions.values_mut() {
+            deletion.deadline = now;
+        }
+    }
+
+    #[test]
+    fn outstanding_requests_without_responses_are_evicted_and_counted_as_failed() {
+        let mut node = first_node();
+        let mut streams = Vec::new();
+        for round in 1..=5 {
+            streams.extend((0..100).map(|_| node.find_key_stream("key")));
+            let deletion = node.delete_namespace("namespace");
+            assert_eq!(node.outstanding_requests(), 101);
+            node.expire_outstanding_requests();
+            assert_eq!(node.outstanding_requests(), 101, "requests were evicted before their timeout");
+
+            lose_outstanding_responses(&mut node);
+            node.expire_outstanding_requests();
+            assert_eq!(node.outstanding_requests(), 0);
+            assert_eq!(node.get_failed_requests(), round * 101);
+            assert!(deletion.wait().is_err());
+        }
+        // The evicted streams end without a value
+        for stream in streams {
+            assert!(stream.collect().wait().unwrap().is_empty());
+        }
+    }
+
+    fn received_message(listener: &TcpListener) -> Message {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut message = String::new();
+        stream.read_to_string(&mut message).unwrap();
+        serde_json::from_str(message.trim()).unwrap()
+    }
+
+    #[test]
+    fn a_node_restarted_under_another_address_with_its_id_reclaims_its_keys() {
+        // Keys the successor moves are sent to its own address and routed on from there
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut successor = Node::new_first(listener.local_addr().unwrap(), ChordConfig::default());
+        let restarted_id = peer(&successor, 4, 0).get_id().clone();
+        let owned_id = peer(&successor, 6, 0).get_id().clone();
+        let reclaimed_id = peer(&successor, 3, 0).get_id().clone();
+        // Alone since the old address died, the successor owns the keys of the restarted node meanwhile
+        successor.storage.store_key((owned_id.clone(), DHTEntry::new("owned".to_string(), "mine".to_string())));
+        successor.storage.store_key((reclaimed_id.clone(), DHTEntry::new("reclaimed".to_string(), "its".to_string())));
+
+        let config = ChordConfig { node_id: Some(restarted_id.clone()), ..ChordConfig::default() };
+        let mut restarted = Node::new(unreachable_addr(3), config);
+        assert_eq!(*restarted.get_id(), restarted_id);
+        restarted.process_incoming_response(Response::FoundSuccessor { successor: successor.to_other_node(), lookup: LookupContext::new() });
+        restarted.set_predecessor(Some(successor.to_other_node()));
+        successor.process_incoming_request(Request::Notify { node: restarted.to_other_node() });
+        assert_eq!(predecessor_addr(&successor), unreachable_addr(3));
+        assert_eq!(stored_value(&successor, &reclaimed_id), None);
+        assert_eq!(stored_value(&successor, &owned_id), Some(b"mine".to_vec()));
+
+        match received_message(&listener) {
+            Message::RequestMessage { request: request @ Request::DHTStoreKey { moved: true, .. }, .. } => {
+                match restarted.process_incoming_request(request) {
+                    Some(Response::DHTStoredKey { ref key }) if key == "reclaimed" => {}
+                    other => panic!("unexpected response {:?}", other),
+                }
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(stored_value(&restarted, &reclaimed_id), Some(b"its".to_vec()));
+    }
+
+    // Finds the key at the owner, which asks its successor, and passes the moved key back to the owner
+    fn find_with_read_repair(owner: &mut Node, successor: &mut Node, listener: &TcpListener, key_id: &BigInt) -> Option<Vec<u8>> {
+        let lookup = match owner.process_incoming_request(Request::DHTFindKey { key_id: key_id.clone(), lookup: LookupContext::new() }) {
+            Some(Response::DHTAskFurtherFind { ref next_node, lookup, .. }) if next_node.get_id() == successor.get_id() => lookup,
+            other => panic!("unexpected response {:?}", other),
+        };
+        let found = match successor.process_incoming_request(Request::DHTFindKey { key_id: key_id.clone(), lookup }) {
+            Some(Response::DHTFoundKey { data: (_, entry), .. }) => entry.map(|entry| entry.get_value().to_vec()),
+            other => panic!("unexpected response {:?}", other),
+        };
+        match received_message(listener) {
+            Message::RequestMessage { request: request @ Request::DHTStoreKey { moved: true, .. }, .. } => {
+                match owner.process_incoming_request(request) {
+                    Some(Response::DHTStoredKey { .. }) => {}
+                    other => panic!("unexpected response {:?}", other),
+                }
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        found
+    }
+
+    #[test]
+    fn a_stale_copy_found_by_a_read_is_repaired_with_the_newer_one() {
+        // Keys the successor moves are sent to its own address and routed on from there
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ChordConfig { migration_strategy: MigrationStrategy::Lazy, ..ChordConfig::default() };
+        let mut owner = first_node_with(config.clone());
+        let successor_id = peer(&owner, 4, 0).get_id().clone();
+        let mut successor = Node::new_first(listener.local_addr().unwrap(), ChordConfig { node_id: Some(successor_id), ..config });
+        let stale_at_owner = peer(&owner, 6, 0).get_id().clone();
+        let stale_at_successor = peer(&owner, 7, 0).get_id().clone();
+        // Both got a copy of each key, one of them was written again before the key moved
+        for &(key_id, rewritten_at_successor) in &[(&stale_at_owner, true), (&stale_at_successor, false)] {
+            successor.storage.store_key((key_id.clone(), DHTEntry::new("key".to_string(), "old".to_string())));
+            let copy = successor.storage.get_key(key_id).unwrap();
+            owner.storage.store_key((key_id.clone(), copy));
+            let newer = if rewritten_at_successor { &mut successor } else { &mut owner };
+            newer.storage.store_key((key_id.clone(), DHTEntry::new("key".to_string(), "new".to_string())));
+        }
+        owner.update_successor_and_successor_list(successor.to_other_node());
+        match received_message(&listener) {
+            Message::RequestMessage { request: Request::GetSuccessorList, .. } => {}
+            other => panic!("unexpected message {:?}", other),
+        }
+        owner.set_predecessor(Some(successor.to_other_node()));
+        successor.set_predecessor(Some(owner.to_other_node()));
+
+        for key_id in &[stale_at_owner, stale_at_successor] {
+            assert_eq!(find_with_read_repair(&mut owner, &mut successor, &listener, key_id), Some(b"new".to_vec()));
+            assert_eq!(stored_value(&owner, key_id), Some(b"new".to_vec()));
+            assert_eq!(stored_value(&successor, key_id), None);
+        }
+    }
+
+    #[test]
+    fn a_multi_megabyte_value_is_stored_and_streamed_back_in_chunks() {
+        // Messages the node sends to itself arrive here and are passed back by hand
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ChordConfig { chunk_size: 256 * 1024, ..ChordConfig::default() };
+        let mut node = Node::new_first(listener.local_addr().unwrap(), config);
+        let value: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let chunks = storage::split_value_into_chunks(&value, node.config.chunk_size);
+        let total_chunks = chunks.len();
+        assert_eq!(total_chunks, 12);
+
+        // Chunks may arrive in any order, only the last missing one is answered
+        for (chunk_index, chunk) in chunks.into_iter().enumerate().rev() {
+            let data = storage::make_hashed_key_value_pair("big".to_string(), chunk, node.config.key_space_bits);
+            match node.process_incoming_request(Request::DHTStoreChunk { chunk_index, total_chunks, data }) {
+                Some(Response::DHTStoredKey { ref key }) if chunk_index == 0 => assert_eq!(key, "big"),
+                None if chunk_index > 0 => {}
+                other => panic!("unexpected response {:?} to chunk {}", other, chunk_index),
+            }
+        }
+        let key_id = chord::create_key_id("big", node.config.key_space_bits);
+        assert_eq!(stored_value(&node, &key_id).as_ref(), Some(&value));
+
+        let stream = node.find_key_stream("big");
+        match received_message(&listener) {
+            Message::RequestMessage { request, .. } => assert!(node.process_incoming_request(request).is_none()),
+            other => panic!("unexpected message {:?}", other),
+        }
+        for _ in 0..total_chunks {
+            match received_message(&listener) {
+                Message::ResponseMessage { response, .. } => node.process_incoming_response(response),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+        let streamed: Vec<u8> = stream.collect().wait().unwrap().concat();
+        assert_eq!(streamed.len(), value.len());
+        assert!(streamed == value, "the streamed value differs from the stored one");
     }
 }
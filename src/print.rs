@@ -1,33 +1,67 @@
 use colored::*;
 use prettytable::format;
 
+use super::chord;
+use super::logging;
 use super::node::*;
 
-pub fn print_current_node_state(node: &Node) {
+/// The tables of `print_node_state`, made while holding the node lock
+pub fn format_current_node_state(node: &Node) -> String {
     let mut pre_id_string = "None".to_string();
     let mut pre_ip_string = "None".to_string();
 
     if let Some(pre) = node.get_predecessor().clone() {
-        pre_id_string = pre.get_id().to_string();
+        pre_id_string = chord::short_id(pre.get_id());
         pre_ip_string = pre.get_ip_addr().to_string();
     }
-    let succ_id_string = node.get_successor().get_id().to_string();
+    let succ_id_string = chord::short_id(node.get_successor().get_id());
     let succ_ip_string = node.get_successor().get_ip_addr().to_string();
 
+    let mut hops: Vec<(&usize, &usize)> = node.get_lookup_hops().iter().collect();
+    hops.sort();
+    let hops_string = hops.iter().map(|(hops, count)| format!("{}: {}", hops, count)).collect::<Vec<String>>().join(", ");
+
+    let storage = node.get_storage();
+    let bytes_string = if storage.bytes_uncompressed() != storage.bytes_stored() {
+        format!("{} ({} uncompressed)", storage.bytes_stored(), storage.bytes_uncompressed())
+    } else {
+        storage.bytes_stored().to_string()
+    };
+
+    let reservations_string = node.get_reservations().iter()
+        .map(|reservation| format!("{} #{}", reservation, chord::short_id(reservation.get_node().get_id())))
+        .collect::<Vec<String>>().join(", ");
+
     //Node Info
     let mut node_info_table = table!(["Descr.".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow()],["", "", ""],
                     ["Predecessor", &pre_id_string, &pre_ip_string],
-                    ["Self".green(), node.get_id().clone().to_string().green(), node.get_ip_addr().to_string().green()],
-                    ["Successor",  &succ_id_string, &succ_ip_string]);
+                    ["Self".green(), chord::short_id(node.get_id()).green(), node.get_ip_addr().to_string().green()],
+                    ["Successor",  &succ_id_string, &succ_ip_string],
+                    ["", "", ""],
+                    ["Keys owned", &node.keys_owned().to_string(), ""],
+                    ["Bytes stored", &bytes_string, ""],
+                    ["Ring size (est.)", &node.estimate_ring_size().to_string(), ""],
+                    ["Lookup hops", &hops_string, ""],
+                    ["Degraded lookups", &node.get_degraded_lookups().to_string(), ""],
+                    ["Outstanding requests", &node.outstanding_requests().to_string(), ""],
+                    ["Requests timed out", &node.get_failed_requests().to_string(), ""],
+                    ["Reserved keys", &reservations_string, ""],
+                    ["Threads (process)", &logging::live_threads().to_string(), ""],
+                    ["Open circuit breakers", &node.get_network().open_breakers().to_string(), ""],
+                    ["Lookups answered busy", &node.get_network().shed_lookups().to_string(), ""],
+                    ["Read-only", &node.is_read_only().to_string(), ""]);
     // node_info_table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     node_info_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
 
 
     // Successor List
-    let mut successor_list_table = table!(["#".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow()],["", "", ""]);
-    for i in 0..node.get_successor_list().len() {
-        let succ = &node.get_successor_list()[i];
-        successor_list_table.add_row(row![r -> &i.to_string(), &succ.get_id().clone().to_string(), &succ.get_ip_addr().clone().to_string()]);
+    let mut successor_list_table = table!(["#".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow(), "RTT".italic().yellow()],["", "", "", ""]);
+    for (i, succ) in node.successor_list().iter().enumerate() {
+        let rtt_string = match node.get_rtt(succ.get_id()) {
+            Some(rtt) => format!("{:.1}ms", rtt.as_secs() as f64 * 1000.0 + f64::from(rtt.subsec_micros()) / 1000.0),
+            None => "-".to_string(),
+        };
+        successor_list_table.add_row(row![r -> &i.to_string(), &chord::short_id(succ.get_id()), &succ.get_ip_addr().clone().to_string(), &rtt_string]);
     }
     // successor_list_table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     successor_list_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
@@ -37,7 +71,7 @@ pub fn print_current_node_state(node: &Node) {
     let mut finger_table_table = table!(["#".italic().yellow(), "Finger".italic().yellow(), "Node".italic().yellow()],["", "", ""]);
     for i in 0..node.get_finger_table().length() {
         let entry = node.get_finger_table().get(i);
-        finger_table_table.add_row(row![r -> &i.to_string(), entry.get_id().to_string(), entry.get_node().get_id().to_string()]);
+        finger_table_table.add_row(row![r -> &i.to_string(), chord::short_id(entry.get_id()), chord::short_id(entry.get_node().get_id())]);
     }
     // finger_table_table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     finger_table_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
@@ -48,7 +82,7 @@ pub fn print_current_node_state(node: &Node) {
         ["", "", ""]
         );
     for (key_id, dht_entry) in node.get_storage().get_data_as_iter() {
-        storage_table.add_row(row![&key_id.clone().to_string(), dht_entry.get_key(), dht_entry.get_value()]);
+        storage_table.add_row(row![&chord::short_id(key_id), dht_entry.get_key(), dht_entry.value_string()]);
     }
     storage_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
 
@@ -69,5 +103,10 @@ pub fn print_current_node_state(node: &Node) {
     }
     storage_logs_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
     //print!("{}[2J", 27 as char);
-    info!("\n\n{}\n{}{}", "INFO! Open menu by typing 'm+ENTER'".to_string().black().on_white(), state_table, storage_logs_table);
+    format!("\n\n{}\n{}{}", "INFO! Open menu by typing 'm+ENTER'".to_string().black().on_white(), state_table, storage_logs_table)
+}
+
+/// Logs the node state of `format_current_node_state`, after the lock is released
+pub fn print_node_state(state: &str) {
+    info!("{}", state);
 }
\ No newline at end of file
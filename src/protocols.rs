@@ -1,29 +1,437 @@
+use std::cmp;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
 use num_bigint::BigInt;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
 
+use super::chord;
 use super::node::OtherNode;
+use super::storage;
 use super::storage::DHTEntry;
 
+/// Version of the protocol spoken by this node, advertised in every `OtherNode`.
+/// Nodes predating versioning are seen as version `0`.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Lowest version which understands `Request::Stabilize`
+pub const STABILIZE_MIN_VERSION: u16 = 1;
+
+/// Lowest version which reads ids as bytes, see `id_encoding`
+pub const ID_BYTES_MIN_VERSION: u16 = 2;
+
+/// Both sides only use features of the lower of their versions
+pub fn negotiate_version(peer_version: u16) -> u16 {
+    cmp::min(PROTOCOL_VERSION, peer_version)
+}
+
+/// Ids on the wire as big-endian bytes, left-padded to `ID_BYTES`, instead of the sign and
+/// digits of `num-bigint`, so that the format does not depend on its internals.
+/// Used with `#[serde(with = "id_encoding")]` on fields of the types implementing `WireIds`.
+/// Ids in the old `num-bigint` form are still read, and sent to peers older than
+/// `ID_BYTES_MIN_VERSION` (see `with_peer_version`).
+pub mod id_encoding {
+    use std::cell::Cell;
+
+    use num_bigint::{BigInt, Sign};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::DeserializeOwned;
+    use serde::ser;
+
+    use super::chord;
+
+    /// Bytes of an id on the chord circle
+    pub const ID_BYTES: usize = (chord::CHORD_CIRCLE_BITS + 7) / 8;
+
+    thread_local! {
+        // Set while encoding a message for a peer which cannot read ids as bytes
+        static LEGACY_IDS: Cell<bool> = Cell::new(false);
+    }
+
+    /// Runs `encode` with ids in the form a peer speaking `peer_version` can read, i.e. in the
+    /// form of `num-bigint` for peers older than `ID_BYTES_MIN_VERSION`
+    pub fn with_peer_version<T, F: FnOnce() -> T>(peer_version: u16, encode: F) -> T {
+        let legacy = super::negotiate_version(peer_version) < super::ID_BYTES_MIN_VERSION;
+        let previous = LEGACY_IDS.with(|ids| ids.replace(legacy));
+        let result = encode();
+        LEGACY_IDS.with(|ids| ids.set(previous));
+        result
+    }
+
+    /// Big-endian bytes of `id`, at least `ID_BYTES` long. Ids which do not fit, e.g. key ids of
+    /// a larger key space, keep all of their bytes.
+    pub fn id_to_bytes(id: &BigInt) -> Vec<u8> {
+        let (_, magnitude) = id.to_bytes_be();
+        let mut bytes = vec![0; ID_BYTES.saturating_sub(magnitude.len())];
+        bytes.extend(magnitude);
+        bytes
+    }
+
+    pub fn id_from_bytes(bytes: &[u8]) -> BigInt {
+        BigInt::from_bytes_be(Sign::Plus, bytes)
+    }
+
+    /// Types made of ids which `serialize` and `deserialize` can encode
+    pub trait WireIds: Sized {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+    }
+
+    pub fn serialize<T: WireIds, S: Serializer>(ids: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        ids.serialize_ids(serializer)
+    }
+
+    pub fn deserialize<'de, T: WireIds, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        T::deserialize_ids(deserializer)
+    }
+
+    struct IdRef<'a>(&'a BigInt);
+
+    impl<'a> Serialize for IdRef<'a> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.0.sign() == Sign::Minus {
+                return Err(ser::Error::custom(format!("negative id {}", self.0)));
+            }
+            if LEGACY_IDS.with(|ids| ids.get()) {
+                return self.0.serialize(serializer);
+            }
+            serializer.serialize_bytes(&id_to_bytes(self.0))
+        }
+    }
+
+    struct Id(BigInt);
+
+    /// Tried in order, bytes never look like the `[sign, [digits]]` of `num-bigint`
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AnyId {
+        Bytes(Vec<u8>),
+        Legacy(BigInt),
+    }
+
+    impl<'de> Deserialize<'de> for Id {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+            Ok(Id(match AnyId::deserialize(deserializer)? {
+                AnyId::Bytes(bytes) => id_from_bytes(&bytes),
+                AnyId::Legacy(id) => id,
+            }))
+        }
+    }
+
+    impl WireIds for BigInt {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            IdRef(self).serialize(serializer)
+        }
+
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Id::deserialize(deserializer)?.0)
+        }
+    }
+
+    impl WireIds for Option<BigInt> {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.as_ref().map(IdRef).serialize(serializer)
+        }
+
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Option::<Id>::deserialize(deserializer)?.map(|id| id.0))
+        }
+    }
+
+    impl WireIds for Vec<BigInt> {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter().map(IdRef))
+        }
+
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Vec::<Id>::deserialize(deserializer)?.into_iter().map(|id| id.0).collect())
+        }
+    }
+
+    impl<T: Serialize + DeserializeOwned> WireIds for (BigInt, T) {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            (IdRef(&self.0), &self.1).serialize(serializer)
+        }
+
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let (id, value) = <(Id, T)>::deserialize(deserializer)?;
+            Ok((id.0, value))
+        }
+    }
+
+    impl<T: Serialize + DeserializeOwned> WireIds for Vec<(BigInt, T)> {
+        fn serialize_ids<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter().map(|(id, value)| (IdRef(id), value)))
+        }
+
+        fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Vec::<(Id, T)>::deserialize(deserializer)?.into_iter().map(|(id, value)| (id.0, value)).collect())
+        }
+    }
+}
+
+/// Trace and span of the message, so that the hops of one lookup across nodes can be put
+/// together into one trace from the logs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TraceContext {
+    trace_id: u64,
+    span_id: u64,
+}
+
+impl TraceContext {
+    /// The root span of a new trace
+    pub fn new() -> TraceContext {
+        TraceContext { trace_id: rand::random(), span_id: rand::random() }
+    }
+
+    /// A span of the same trace, started on receiving a message of this span
+    pub fn child(&self) -> TraceContext {
+        TraceContext { trace_id: self.trace_id, span_id: rand::random() }
+    }
+
+    pub fn get_trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    pub fn get_span_id(&self) -> u64 {
+        self.span_id
+    }
+}
+
+/// Bookkeeping of a lookup, carried through every forward to the answering node and back
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LookupContext {
+    hops: usize,
+    started_at: SystemTime,
+    // Fixed when the lookup starts, so that no hop restarts the clock
+    #[serde(default)]
+    deadline: Option<SystemTime>,
+    // Set when the owner of a key asks the node that held it before, see `MigrationStrategy::Lazy`
+    #[serde(default)]
+    previous_owner: bool,
+    // The copy of the key the owner holds, sent along to the node that held it before
+    #[serde(default)]
+    owner_entry: Option<DHTEntry>,
+    // Set by the requester of a `DHTFindKeyStream`, to tell its streams of the same key apart
+    #[serde(default)]
+    stream: Option<u64>,
+}
+
+impl LookupContext {
+    pub fn new() -> LookupContext {
+        let started_at = SystemTime::now();
+        LookupContext { hops: 0, started_at, deadline: Some(started_at + chord::LOOKUP_TIMEOUT), previous_owner: false, owner_entry: None, stream: None }
+    }
+
+    /// Returns the context for asking the next node
+    pub fn forwarded(self) -> LookupContext {
+        LookupContext { hops: self.hops + 1, ..self }
+    }
+
+    /// Returns the context for asking the node I took the key over from, which answers
+    /// with the newer of its copy of the key and mine instead of asking further
+    pub fn for_previous_owner(self, owner_entry: Option<DHTEntry>) -> LookupContext {
+        LookupContext { previous_owner: true, owner_entry, ..self }
+    }
+
+    pub fn is_for_previous_owner(&self) -> bool {
+        self.previous_owner
+    }
+
+    /// The copy of the owner, taken so that it is not sent back with the answer
+    pub fn take_owner_entry(&mut self) -> Option<DHTEntry> {
+        self.owner_entry.take()
+    }
+
+    /// Returns the context for streaming a value into the stream `stream` of the requester
+    pub fn for_stream(self, stream: u64) -> LookupContext {
+        LookupContext { stream: Some(stream), ..self }
+    }
+
+    pub fn get_stream(&self) -> Option<u64> {
+        self.stream
+    }
+
+    pub fn get_hops(&self) -> usize {
+        self.hops
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed().unwrap_or_else(|_| Duration::from_secs(0))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.deadline {
+            Some(deadline) => SystemTime::now() > deadline,
+            None => false,
+        }
+    }
+}
+
+/// Keys with a ring id (see `chord::key_placement_id`) in [start, end) belong to `node`,
+/// whichever node would own them by its id. `start == end` is the whole ring.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRangeReservation {
+    #[serde(with = "id_encoding")]
+    start: BigInt,
+    #[serde(with = "id_encoding")]
+    end: BigInt,
+    node: OtherNode,
+}
+
+impl KeyRangeReservation {
+    pub fn new(start: BigInt, end: BigInt, node: OtherNode) -> KeyRangeReservation {
+        KeyRangeReservation { start, end, node }
+    }
+
+    pub fn get_node(&self) -> &OtherNode {
+        &self.node
+    }
+
+    pub fn contains(&self, placement_id: &BigInt) -> bool {
+        chord::ring_segments(&self.start, &self.end).iter()
+            .any(|(start, end)| start <= placement_id && placement_id < end)
+    }
+
+    pub fn same_range(&self, other: &KeyRangeReservation) -> bool {
+        self.start == other.start && self.end == other.end
+    }
+}
+
+impl fmt::Display for KeyRangeReservation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", chord::short_id(&self.start), chord::short_id(&self.end))
+    }
+}
+
+/// One write of a `DHTTransaction`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum DHTOp {
+    Store {
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry)
+    },
+    Delete {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt
+    },
+    /// Fails the whole transaction unless the key has the value of `expected`, `None` means absent
+    CompareAndSwap {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        expected: Option<DHTEntry>,
+        new: DHTEntry,
+    },
+}
+
+impl DHTOp {
+    pub fn get_key_id(&self) -> &BigInt {
+        match self {
+            DHTOp::Store { data } => &data.0,
+            DHTOp::Delete { key_id } | DHTOp::CompareAndSwap { key_id, .. } => key_id,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Message {
     Kill,
     Ping {
         sender: OtherNode
     },
+    Pong {
+        sender: OtherNode
+    },
     RequestMessage {
         sender: OtherNode,
         request: Request,
+        /// Span of the sender the request was sent in
+        #[serde(default)]
+        trace: Option<TraceContext>,
     },
     ResponseMessage {
         sender: OtherNode,
         response: Response,
+        #[serde(default)]
+        trace: Option<TraceContext>,
+    },
+    /// Sent by a `ChordClient`, which is no member of the ring and gets the response
+    /// written back on the same connection
+    ClientRequest {
+        request: Request,
+        #[serde(default)]
+        trace: Option<TraceContext>,
     },
 }
 
+impl Message {
+    /// Tells from the tags of `msg`, which failed to parse, whether it is a message or holds a
+    /// request or response of a kind this node does not know, e.g. sent by a newer version
+    pub fn is_of_unknown_kind(msg: &[u8]) -> bool {
+        let (tag, content) = match serde_json::from_slice(msg).ok().as_ref().and_then(enum_tag) {
+            Some((tag, content)) => (tag.to_string(), content.cloned()),
+            None => return false,
+        };
+        if !variant_names::<Message>().contains(&tag.as_str()) {
+            return true;
+        }
+        let (field, variants) = match tag.as_str() {
+            "RequestMessage" | "ClientRequest" => ("request", variant_names::<Request>()),
+            "ResponseMessage" => ("response", variant_names::<Response>()),
+            _ => return false,
+        };
+        match content.as_ref().and_then(|content| content.get(field)).and_then(enum_tag) {
+            Some((tag, _)) => !variants.contains(&tag),
+            None => false,
+        }
+    }
+}
+
+/// The variant and content of an enum in the externally tagged form of serde
+fn enum_tag(value: &serde_json::Value) -> Option<(&str, Option<&serde_json::Value>)> {
+    match value {
+        serde_json::Value::String(tag) => Some((tag, None)),
+        serde_json::Value::Object(map) if map.len() == 1 => map.iter().next().map(|(tag, content)| (tag.as_str(), Some(content))),
+        _ => None,
+    }
+}
+
+/// Names of the variants of the enum `T`, which serde hands to the deserializer
+fn variant_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut variants: &'static [&'static str] = &[];
+    let _ = T::deserialize(VariantNames(&mut variants));
+    variants
+}
+
+/// Deserializer which only records the variants it is asked for
+struct VariantNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de, 'a> Deserializer<'de> for VariantNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not an enum"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, variants: &'static [&'static str], _visitor: V)
+                                         -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("only the variants are recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Request {
     FindSuccessor {
-        id: BigInt
+        #[serde(with = "id_encoding")]
+        id: BigInt,
+        lookup: LookupContext,
     },
     GetPredecessor,
     Notify {
@@ -31,68 +439,403 @@ pub enum Request {
     },
     FindSuccessorFinger {
         index: usize,
+        #[serde(with = "id_encoding")]
         finger_id: BigInt,
     },
     GetSuccessorList,
+    /// Asked by a joining node to guess its own fingers from them
+    GetFingerTable,
     DHTStoreKey {
-        data: (BigInt, DHTEntry)
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry),
+        /// Moved to its new owner by the node that held it, which a read-only owner takes as well
+        #[serde(default)]
+        moved: bool,
     },
     DHTFindKey {
-        key_id: BigInt
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        lookup: LookupContext,
+    },
+    /// Like `DHTFindKey`, but the owner sends the value to `requester` as `DHTFoundChunk`s,
+    /// which carry the stream of the `lookup` back
+    DHTFindKeyStream {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        requester: OtherNode,
+        lookup: LookupContext,
     },
     DHTDeleteKey {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt
+    },
+    /// Removes the key and returns its entry in one step, so that of several requests for the
+    /// same key only one gets the entry
+    DHTGetAndDelete {
+        #[serde(with = "id_encoding")]
         key_id: BigInt
     },
     DHTTakeOverKeys {
+        #[serde(with = "id_encoding")]
         data: Vec<(BigInt, DHTEntry)>
+    },
+    DHTStoreChunk {
+        chunk_index: usize,
+        total_chunks: usize,
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry),
+    },
+    DumpStorage {
+        keys_only: bool,
+        /// Smallest key id of the requested page, `None` for the first page
+        #[serde(default)]
+        #[serde(with = "id_encoding")]
+        cursor: Option<BigInt>,
+    },
+    DHTStoreIfAbsent {
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry)
+    },
+    /// FindSuccessor for the sender's own id, to learn whether a node belongs to the same ring
+    ProbeRing {
+        #[serde(with = "id_encoding")]
+        id: BigInt,
+        lookup: LookupContext,
+    },
+    /// GetPredecessor and Notify in one round trip
+    Stabilize {
+        from: OtherNode
+    },
+    GetKeysOwned,
+    /// Asks for the predecessor of the receiving node, whom the asker asks in turn until it
+    /// knows `depth` predecessors, see `Node::predecessor_chain`
+    GetPredecessorChain {
+        depth: usize
+    },
+    SetMaintenancePaused {
+        paused: bool
+    },
+    /// Lets the node reject or accept writes again, see `ChordConfig::read_only`
+    SetReadOnly {
+        read_only: bool
+    },
+    /// Passed from successor to successor around the ring, every node deletes its keys of
+    /// `namespace` and adds their number to `deleted` until the requester is reached again
+    DHTDeleteNamespace {
+        namespace: String,
+        requester: OtherNode,
+        deleted: usize,
+        lookup: LookupContext,
+    },
+    /// Stores `new` only if the current entry has the value of `expected`, `None` means absent
+    DHTCompareAndSwap {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        expected: Option<DHTEntry>,
+        new: DHTEntry,
+    },
+    /// Applies all ops in order or none of them. Only the owner of all their keys applies them,
+    /// other keys make the transaction fail instead of spanning several nodes.
+    DHTTransaction {
+        ops: Vec<DHTOp>
+    },
+    /// Appends `data` to the value of the key, which is created if absent
+    DHTAppend {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        key: String,
+        #[serde(with = "storage::value_encoding")]
+        data: Vec<u8>,
+    },
+}
+
+impl Request {
+    /// Requests routed towards the node responsible for an id, which count towards
+    /// `ChordConfig::max_inflight_lookups`
+    pub fn is_lookup(&self) -> bool {
+        match self {
+            Request::FindSuccessorFinger { .. } => true,
+            _ => self.get_lookup().is_some(),
+        }
+    }
+
+    pub fn get_lookup(&self) -> Option<&LookupContext> {
+        match self {
+            Request::FindSuccessor { lookup, .. } | Request::DHTFindKey { lookup, .. }
+            | Request::DHTFindKeyStream { lookup, .. } | Request::ProbeRing { lookup, .. } => Some(lookup),
+            _ => None,
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Response {
     FoundSuccessor {
-        successor: OtherNode
+        successor: OtherNode,
+        lookup: LookupContext,
     },
     AskFurther {
-        next_node: OtherNode
+        next_node: OtherNode,
+        lookup: LookupContext,
     },
     GetPredecessorResponse {
-        predecessor: Option<OtherNode>
+        predecessor: Option<OtherNode>,
+        /// Ids of nodes the sender recently found dead, see `chord::DEAD_GOSSIP_SIZE`
+        #[serde(default)]
+        #[serde(with = "id_encoding")]
+        recently_dead: Vec<BigInt>,
     },
     NotifyResponse,
     FoundSuccessorFinger {
         index: usize,
+        #[serde(with = "id_encoding")]
         finger_id: BigInt,
         successor: OtherNode,
     },
     AskFurtherFinger {
         index: usize,
+        #[serde(with = "id_encoding")]
         finger_id: BigInt,
         next_node: OtherNode,
     },
     GetSuccessorListResponse {
-        successor_list: Vec<OtherNode>
+        successor_list: Vec<OtherNode>,
+        // Passed on every stabilize cycle, so that reservations spread through the whole ring
+        #[serde(default)]
+        reservations: Vec<KeyRangeReservation>,
+    },
+    /// The nodes of the finger table, starting with the successor
+    FingerTableResponse {
+        fingers: Vec<OtherNode>
     },
     DHTStoredKey{
         key: String,
     },
+    /// The owner refused to store a value larger than its `max_value_size`
+    DHTRejectedKey {
+        key: String,
+        size: usize,
+        max_size: usize,
+    },
+    /// The owner holds more than its `storage_high_water_mark` and takes no new values for now
+    DHTOverloaded {
+        key: String,
+        stored_bytes: usize,
+        high_water_mark: usize,
+    },
     DHTFoundKey {
-        data: (BigInt, Option<DHTEntry>)
+        #[serde(with = "id_encoding")]
+        data: (BigInt, Option<DHTEntry>),
+        lookup: LookupContext,
+    },
+    /// One piece of a streamed value, chunks may arrive out of order
+    DHTFoundChunk {
+        chunk_index: usize,
+        total_chunks: usize,
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry),
+        lookup: LookupContext,
+    },
+    /// The lookup for `id` passed its deadline before an answer was found
+    LookupTimedOut {
+        #[serde(with = "id_encoding")]
+        id: BigInt,
+        lookup: LookupContext,
     },
     DHTDeletedKey {
         key_existed: bool
     },
+    DHTPopped {
+        data: Option<DHTEntry>
+    },
     DHTAskFurtherStore {
         next_node: OtherNode,
+        #[serde(with = "id_encoding")]
         data: (BigInt, DHTEntry),
+        #[serde(default)]
+        moved: bool,
     },
     DHTAskFurtherFind {
         next_node: OtherNode,
+        #[serde(with = "id_encoding")]
         key_id: BigInt,
+        lookup: LookupContext,
+    },
+    DHTAskFurtherFindStream {
+        next_node: OtherNode,
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        lookup: LookupContext,
     },
     DHTAskFurtherDelete {
         next_node: OtherNode,
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+    },
+    DHTAskFurtherGetAndDelete {
+        next_node: OtherNode,
+        #[serde(with = "id_encoding")]
         key_id: BigInt,
     },
+    DHTAskFurtherStoreChunk {
+        next_node: OtherNode,
+        chunk_index: usize,
+        total_chunks: usize,
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry),
+    },
+    /// Up to `dump_page_size` keys of a storage dump in key id order, `next_cursor` is the
+    /// key id to continue with on `node` if there are more
+    DumpedStoragePage {
+        node: OtherNode,
+        #[serde(with = "id_encoding")]
+        data: Vec<(BigInt, Option<DHTEntry>)>,
+        keys_only: bool,
+        #[serde(with = "id_encoding")]
+        next_cursor: Option<BigInt>,
+    },
+    DHTStoredIfAbsent {
+        stored: bool
+    },
+    DHTAskFurtherStoreIfAbsent {
+        next_node: OtherNode,
+        #[serde(with = "id_encoding")]
+        data: (BigInt, DHTEntry),
+    },
+    StabilizeResponse {
+        predecessor: Option<OtherNode>,
+        accepted: bool,
+        #[serde(default)]
+        #[serde(with = "id_encoding")]
+        recently_dead: Vec<BigInt>,
+    },
+    KeysOwned {
+        #[serde(with = "id_encoding")]
+        id: BigInt,
+        count: usize,
+    },
+    /// The predecessor of `node`, `depth` is the number of predecessors still wanted after it
+    PredecessorChainLink {
+        node: OtherNode,
+        predecessor: Option<OtherNode>,
+        depth: usize,
+    },
+    MaintenancePaused {
+        paused: bool
+    },
+    ReadOnlySet {
+        read_only: bool
+    },
+    /// The owner is read-only and does not change the key, see `ChordConfig::read_only`
+    DHTReadOnly {
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+    },
+    DHTCasResult {
+        swapped: bool,
+        current: Option<DHTEntry>,
+    },
+    DHTAskFurtherCompareAndSwap {
+        next_node: OtherNode,
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        expected: Option<DHTEntry>,
+        new: DHTEntry,
+    },
+    RingProbed {
+        successor: OtherNode,
+        lookup: LookupContext,
+    },
+    AskFurtherProbe {
+        next_node: OtherNode,
+        lookup: LookupContext,
+    },
+    DHTTransactionApplied {
+        ops: usize
+    },
+    /// None of the ops was applied because of the op with index `failed_op`
+    DHTTransactionRejected {
+        failed_op: usize,
+        reason: String,
+    },
+    DHTAskFurtherTransaction {
+        next_node: OtherNode,
+        ops: Vec<DHTOp>,
+    },
+    /// `length` is the length in bytes of the value after the append
+    DHTAppended {
+        key: String,
+        length: usize,
+    },
+    DHTAskFurtherAppend {
+        next_node: OtherNode,
+        #[serde(with = "id_encoding")]
+        key_id: BigInt,
+        key: String,
+        #[serde(with = "storage::value_encoding")]
+        data: Vec<u8>,
+    },
+    /// Sent instead of handling a lookup while `ChordConfig::max_inflight_lookups` lookups are
+    /// in flight at `node` already, the request can be sent to it again later
+    LookupBusy {
+        node: OtherNode,
+        request: Box<Request>,
+    },
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(id: u32) -> Message {
+        Message::Ping { sender: OtherNode::new(BigInt::from(id), "127.0.0.1:10000".parse().unwrap()) }
+    }
+
+    fn sender_id(msg: &str) -> BigInt {
+        match serde_json::from_str(msg).unwrap() {
+            Message::Ping { sender } => sender.get_id().clone(),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ids_are_sent_as_bytes_to_current_peers() {
+        let msg = id_encoding::with_peer_version(PROTOCOL_VERSION, || serde_json::to_string(&ping(12345)).unwrap());
+        assert!(msg.contains(r#""id":[0,48,57]"#), "{}", msg);
+        assert_eq!(sender_id(&msg), BigInt::from(12345));
+    }
+
+    #[test]
+    fn ids_are_sent_in_the_old_form_to_older_peers() {
+        let expected = serde_json::to_string(&BigInt::from(12345)).unwrap();
+        for version in 0..ID_BYTES_MIN_VERSION {
+            let msg = id_encoding::with_peer_version(version, || serde_json::to_string(&ping(12345)).unwrap());
+            assert!(msg.contains(&format!(r#""id":{}"#, expected)), "{}", msg);
+            assert_eq!(sender_id(&msg), BigInt::from(12345));
+        }
+    }
+
+    #[test]
+    fn messages_of_newer_versions_are_told_apart_from_broken_ones() {
+        let sender = serde_json::to_string(&OtherNode::new(BigInt::from(1), "127.0.0.1:10000".parse().unwrap())).unwrap();
+        let unknown_request = format!(r#"{{"RequestMessage":{{"sender":{},"request":{{"Teleport":{{}}}}}}}}"#, sender);
+        let unknown_response = format!(r#"{{"ResponseMessage":{{"sender":{},"response":"Teleported"}}}}"#, sender);
+        let broken_request = format!(r#"{{"RequestMessage":{{"sender":{},"request":{{"FindSuccessor":{{}}}}}}}}"#, sender);
+        assert!(serde_json::from_str::<Message>(&unknown_request).is_err());
+        assert!(Message::is_of_unknown_kind(unknown_request.as_bytes()));
+        assert!(Message::is_of_unknown_kind(unknown_response.as_bytes()));
+        assert!(Message::is_of_unknown_kind(br#"{"Gossip":{}}"#));
+        assert!(serde_json::from_str::<Message>(&broken_request).is_err());
+        assert!(!Message::is_of_unknown_kind(broken_request.as_bytes()));
+        assert!(!Message::is_of_unknown_kind(br#"{"Ping":{}}"#));
+        assert!(!Message::is_of_unknown_kind(b"not json"));
+    }
+
+    #[test]
+    fn ids_are_padded_to_the_circle() {
+        assert_eq!(id_encoding::id_to_bytes(&BigInt::from(0)), vec![0; id_encoding::ID_BYTES]);
+        let last = BigInt::from(chord::CHORD_RING_SIZE) - 1;
+        assert_eq!(id_encoding::id_from_bytes(&id_encoding::id_to_bytes(&last)), last);
+    }
+}
@@ -1,68 +1,531 @@
-use std::collections::hash_map::Iter;
 use std::collections::HashMap;
+use std::cmp;
+use std::io::{Read, Write};
+use std::str;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, Local};
 use colored::*;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
 use num::bigint::BigInt;
 
 use super::chord;
+use super::config::{ChordConfig, ValueCompression};
+use super::protocols::DHTOp;
+
+/// Keys written as `NAMESPACE:KEY` belong to the namespace before the first separator
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// Namespace of the keys derived from the values, see `content_key`
+pub const CONTENT_NAMESPACE: &str = "content";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DHTEntry {
     key: String,
-    value: String,
+    #[serde(with = "value_encoding")]
+    value: Vec<u8>,
+    /// Tag of the namespace the key belongs to, see `NAMESPACE_SEPARATOR`
+    #[serde(default)]
+    namespace: Option<String>,
+    /// Wall-clock time of the write in milliseconds since the unix epoch
+    #[serde(default)]
+    written_at: Option<u64>,
+    /// Lamport timestamp, assigned by the node that first stores the entry
+    #[serde(default)]
+    logical: Option<u64>,
+    /// Set while `Storage` holds the value compressed, entries it hands out never are
+    #[serde(skip)]
+    compressed: Option<Compressed>,
 }
 
 impl DHTEntry {
+    /// An entry with a string value
     pub fn new(key: String, value: String) -> DHTEntry {
-        DHTEntry { key, value }
+        DHTEntry::from_bytes(key, value.into_bytes())
+    }
+
+    pub fn from_bytes(key: String, value: Vec<u8>) -> DHTEntry {
+        let namespace = namespace_of(&key).map(|namespace| namespace.to_string());
+        DHTEntry { key, value, namespace, written_at: Some(now_millis()), logical: None, compressed: None }
+    }
+
+    /// Prefers the logical clocks and falls back to wall-clock time
+    /// if one of the entries has no logical timestamp
+    pub fn is_newer_than(&self, other: &DHTEntry) -> bool {
+        match (self.logical, other.logical) {
+            (Some(mine), Some(theirs)) => mine > theirs,
+            _ => match (self.written_at, other.written_at) {
+                (Some(mine), Some(theirs)) => mine > theirs,
+                _ => false,
+            }
+        }
+    }
+
+    /// How far the entry is dated into the future at `now`, if further than `chord::MAX_CLOCK_SKEW_MILLIS`
+    fn future_skew(&self, now: u64) -> Option<u64> {
+        match self.written_at {
+            Some(written_at) if written_at > now + chord::MAX_CLOCK_SKEW_MILLIS => Some(written_at - now),
+            _ => None,
+        }
     }
 
     pub fn get_key(&self) -> &String {
         &self.key
     }
 
-    pub fn get_value(&self) -> &String {
+    pub fn get_value(&self) -> &[u8] {
         &self.value
     }
+
+    /// The value as text, bytes which are not valid UTF-8 are replaced
+    pub fn value_string(&self) -> String {
+        String::from_utf8_lossy(&self.value).into_owned()
+    }
+
+    pub fn value_size(&self) -> usize {
+        self.value.len()
+    }
+}
+
+/// Writes values which are valid UTF-8 as JSON strings like before, so that peers and snapshots
+/// predating binary values read them unchanged, and other values as arrays of bytes. Reads both.
+pub mod value_encoding {
+    use std::fmt;
+    use std::str;
+
+    use serde::{Deserializer, Serializer};
+    use serde::de::{self, SeqAccess, Visitor};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match str::from_utf8(value) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => serializer.serialize_bytes(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+
+    struct ValueVisitor;
+
+    impl<'de> Visitor<'de> for ValueVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string or an array of bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn visit_string<E: de::Error>(self, value: String) -> Result<Vec<u8>, E> {
+            Ok(value.into_bytes())
+        }
+
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(value.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(value)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// The namespace of `key`, `None` if it has no non-empty prefix before a `NAMESPACE_SEPARATOR`
+pub fn namespace_of(key: &str) -> Option<&str> {
+    match key.find(NAMESPACE_SEPARATOR) {
+        Some(0) | None => None,
+        Some(end) => Some(&key[..end]),
+    }
+}
+
+/// Bytes an entry takes up in the storage, leaving out the fixed size parts
+fn entry_size(entry: &DHTEntry) -> usize {
+    entry.key.len() + entry.value.len()
+}
+
+/// Bytes an entry would take up in the storage if its value was not compressed
+fn uncompressed_entry_size(entry: &DHTEntry) -> usize {
+    match &entry.compressed {
+        Some(compressed) => entry.key.len() + compressed.original_size,
+        None => entry_size(entry),
+    }
+}
+
+/// How the value of a stored entry is compressed
+#[derive(Clone, Debug)]
+struct Compressed {
+    algorithm: ValueCompression,
+    original_size: usize,
+}
+
+/// The compressed `value`, `None` if compressing failed or did not make it smaller
+fn compress(algorithm: ValueCompression, value: &[u8]) -> Option<Vec<u8>> {
+    let compressed = match algorithm {
+        ValueCompression::None => return None,
+        ValueCompression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(value).and_then(|_| encoder.finish())
+        }
+        ValueCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(value).and_then(|_| encoder.finish())
+        }
+    };
+    match compressed {
+        Ok(compressed) if compressed.len() < value.len() => Some(compressed),
+        _ => None,
+    }
+}
+
+fn decompress(algorithm: ValueCompression, value: &[u8]) -> Vec<u8> {
+    let mut decompressed = Vec::new();
+    let result = match algorithm {
+        ValueCompression::None => {
+            decompressed.extend_from_slice(value);
+            Ok(value.len())
+        }
+        ValueCompression::Deflate => DeflateDecoder::new(value).read_to_end(&mut decompressed),
+        ValueCompression::Gzip => GzDecoder::new(value).read_to_end(&mut decompressed),
+    };
+    // Only values compressed by the storage itself are decompressed
+    result.expect("stored value could not be decompressed");
+    decompressed
+}
+
+/// Marks a deleted key, so that a copy of the entry moved here later does not bring it back
+#[derive(Clone)]
+struct Tombstone {
+    /// Lamport timestamp of the delete
+    logical: u64,
+    deleted: Instant,
+}
+
+/// Chunks of a value which has not been received completely yet
+#[derive(Clone)]
+struct ChunkedUpload {
+    key: String,
+    chunks: Vec<Option<Vec<u8>>>,
+    started: Instant,
 }
 
 #[derive(Clone)]
 pub struct Storage {
     data: HashMap<BigInt, DHTEntry>,
     logs: Vec<String>,
-
+    uploads: HashMap<BigInt, ChunkedUpload>,
+    /// Lamport clock of this node
+    clock: u64,
+    /// Approximate memory held by the stored keys and values, i.e. with compressed values.
+    /// Overload shedding goes by it, limits of single values go by their uncompressed size.
+    bytes_stored: usize,
+    /// What `bytes_stored` would be without compression
+    bytes_uncompressed: usize,
+    /// Keys deleted within the tombstone grace period
+    tombstones: HashMap<BigInt, Tombstone>,
+    compression: ValueCompression,
+    /// Values of at least this many bytes are compressed
+    compression_threshold: usize,
 }
 
 impl Storage {
-    pub fn new() -> Storage {
+    pub fn new(config: &ChordConfig) -> Storage {
         Storage {
             data: HashMap::new(),
             logs: Vec::new(),
+            uploads: HashMap::new(),
+            clock: 0,
+            bytes_stored: 0,
+            bytes_uncompressed: 0,
+            tombstones: HashMap::new(),
+            compression: config.value_compression,
+            compression_threshold: config.value_compression_threshold,
         }
     }
 
+    pub fn bytes_stored(&self) -> usize {
+        self.bytes_stored
+    }
+
+    pub fn bytes_uncompressed(&self) -> usize {
+        self.bytes_uncompressed
+    }
+
+    /// Inserts the entry and keeps `bytes_stored` up to date. A write revives a deleted key.
+    fn insert(&mut self, key_id: BigInt, entry: DHTEntry) {
+        let entry = self.compress_entry(entry);
+        self.tombstones.remove(&key_id);
+        self.bytes_stored += entry_size(&entry);
+        self.bytes_uncompressed += uncompressed_entry_size(&entry);
+        if let Some(replaced) = self.data.insert(key_id, entry) {
+            self.bytes_stored -= entry_size(&replaced);
+            self.bytes_uncompressed -= uncompressed_entry_size(&replaced);
+        }
+    }
+
+    fn compress_entry(&self, mut entry: DHTEntry) -> DHTEntry {
+        if entry.value.len() >= self.compression_threshold {
+            if let Some(compressed) = compress(self.compression, &entry.value) {
+                entry.compressed = Some(Compressed { algorithm: self.compression, original_size: entry.value.len() });
+                entry.value = compressed;
+            }
+        }
+        entry
+    }
+
+    /// The entry with its value decompressed, as it is handed out
+    fn expand_entry(entry: &DHTEntry) -> DHTEntry {
+        let mut expanded = entry.clone();
+        if let Some(compressed) = expanded.compressed.take() {
+            expanded.value = decompress(compressed.algorithm, &entry.value);
+        }
+        expanded
+    }
+
     pub fn get_data_as_vec(&self) -> Vec<(BigInt, DHTEntry)> {
-        self.data.iter().map(|(id, entry)| (id.clone(), entry.clone())).collect()
+        self.data.iter().map(|(id, entry)| (id.clone(), Storage::expand_entry(entry))).collect()
     }
-    pub fn get_data_as_iter(&self) -> Iter<BigInt, DHTEntry> {
-        self.data.iter()
+    /// The ids of the stored keys, without decompressing their values
+    pub fn get_key_ids(&self) -> impl Iterator<Item = &BigInt> {
+        self.data.keys()
+    }
+
+    pub fn get_data_as_iter(&self) -> impl Iterator<Item = (&BigInt, DHTEntry)> {
+        self.data.iter().map(|(id, entry)| (id, Storage::expand_entry(entry)))
     }
 
     pub fn is_data_empty(&self) -> bool {
         self.data.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Stores a new value from a client, or a timestamped entry moved from another node.
+    /// A moved entry does not overwrite a newer entry stored here, nor a later delete.
     pub fn store_key(&mut self, data: (BigInt, DHTEntry)) {
-        self.data.insert(data.0, data.1);
+        let (key_id, entry) = data;
+        if let Some(logical) = entry.logical {
+            if let Some(existing) = self.data.get(&key_id) {
+                if existing.is_newer_than(&entry) {
+                    debug!(target: chord::LOG_DHT, "Kept newer entry for key '{}' instead of the moved one", entry.key);
+                    return;
+                }
+            }
+            if let Some(tombstone) = self.tombstones.get(&key_id) {
+                if tombstone.logical >= logical {
+                    debug!(target: chord::LOG_DHT, "Dropped moved entry for key '{}', the key was deleted after it was written", entry.key);
+                    return;
+                }
+            }
+        }
+        let entry = self.stamp(entry);
+        self.insert(key_id, entry);
+    }
+
+    /// Stores the entry only if no entry exists for its key id yet
+    pub fn store_key_if_absent(&mut self, data: (BigInt, DHTEntry)) -> bool {
+        if self.data.contains_key(&data.0) {
+            return false;
+        }
+        let entry = self.stamp(data.1);
+        self.insert(data.0, entry);
+        true
+    }
+
+    /// Stores `new` only if the current value equals the one of `expected`, where `None` means
+    /// the key must be absent. Returns whether it was swapped and the entry stored afterwards.
+    pub fn compare_and_swap(&mut self, key_id: BigInt, expected: Option<DHTEntry>, new: DHTEntry) -> (bool, Option<DHTEntry>) {
+        let current = self.get_key(&key_id);
+        let matches = match (&expected, &current) {
+            (None, None) => true,
+            (Some(expected), Some(current)) => expected.value == current.value,
+            _ => false,
+        };
+        if matches {
+            let entry = self.stamp(new);
+            self.insert(key_id, entry.clone());
+            (true, Some(entry))
+        } else {
+            (false, current)
+        }
     }
 
-    pub fn get_key(&self, key_id: &BigInt) -> Option<&DHTEntry> {
-        self.data.get(key_id)
+    /// Applies all ops in order if every compare and swap matches the value left by the ops
+    /// before it, otherwise none of them and returns the index of the first one that did not match
+    pub fn apply_transaction(&mut self, ops: Vec<DHTOp>) -> Result<(), usize> {
+        let mut staged: HashMap<BigInt, Option<Vec<u8>>> = HashMap::new();
+        for (index, op) in ops.iter().enumerate() {
+            let key_id = op.get_key_id();
+            let current = match staged.get(key_id) {
+                Some(value) => value.clone(),
+                None => self.get_key(key_id).map(|entry| entry.value),
+            };
+            let value = match op {
+                DHTOp::Store { data } => Some(data.1.value.clone()),
+                DHTOp::Delete { .. } => None,
+                DHTOp::CompareAndSwap { expected, new, .. } => {
+                    if expected.as_ref().map(|expected| &expected.value) != current.as_ref() {
+                        return Err(index);
+                    }
+                    Some(new.value.clone())
+                }
+            };
+            staged.insert(key_id.clone(), value);
+        }
+        for op in ops {
+            match op {
+                DHTOp::Store { data: (key_id, entry) } | DHTOp::CompareAndSwap { key_id, new: entry, .. } => {
+                    let entry = self.stamp(entry);
+                    self.insert(key_id, entry);
+                }
+                DHTOp::Delete { key_id } => {
+                    self.delete_key(&key_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Advances the Lamport clock past the entry and assigns a logical timestamp to new entries
+    fn stamp(&mut self, mut entry: DHTEntry) -> DHTEntry {
+        if let Some(skew) = entry.future_skew(now_millis()) {
+            warn!(target: chord::LOG_DHT, "Entry for key '{}' is dated {} ms into the future, the clock of its writer is probably skewed",
+                  entry.key, skew);
+        }
+        self.clock = cmp::max(self.clock, entry.logical.unwrap_or(0)) + 1;
+        if entry.logical.is_none() {
+            entry.logical = Some(self.clock);
+        }
+        entry
     }
 
+    /// Appends the value of `entry` to the one stored for `key_id`, or stores `entry` if the
+    /// key is absent. Returns the length of the value afterwards.
+    pub fn append_to_key(&mut self, key_id: BigInt, mut entry: DHTEntry) -> usize {
+        if let Some(current) = self.get_key(&key_id) {
+            let mut value = current.value;
+            value.append(&mut entry.value);
+            entry.value = value;
+        }
+        let length = entry.value.len();
+        let entry = self.stamp(entry);
+        self.insert(key_id, entry);
+        length
+    }
+
+    pub fn get_key(&self, key_id: &BigInt) -> Option<DHTEntry> {
+        self.data.get(key_id).map(Storage::expand_entry)
+    }
+
+    /// Deletes the key and leaves a tombstone, see `store_key`
     pub fn delete_key(&mut self, key_id: &BigInt) -> Option<DHTEntry> {
-        self.data.remove(key_id)
+        let removed = self.remove_moved_key(key_id);
+        if removed.is_some() {
+            self.bury(key_id.clone());
+        }
+        removed
+    }
+
+    /// Removes a key handed to another node, which is no delete and leaves no tombstone
+    pub fn remove_moved_key(&mut self, key_id: &BigInt) -> Option<DHTEntry> {
+        let removed = self.data.remove(key_id);
+        if let Some(ref entry) = removed {
+            self.bytes_stored -= entry_size(entry);
+            self.bytes_uncompressed -= uncompressed_entry_size(entry);
+        }
+        removed.as_ref().map(Storage::expand_entry)
+    }
+
+    /// Deletes all entries tagged with `namespace` and returns how many there were
+    pub fn delete_namespace(&mut self, namespace: &str) -> usize {
+        let deleted: Vec<BigInt> = self.data.iter()
+            .filter(|(_, entry)| match &entry.namespace {
+                Some(tag) => tag == namespace,
+                None => false,
+            })
+            .map(|(key_id, _)| key_id.clone())
+            .collect();
+        for key_id in &deleted {
+            self.delete_key(key_id);
+        }
+        deleted.len()
+    }
+
+    /// Records the delete of `key_id` after every write this node has seen
+    fn bury(&mut self, key_id: BigInt) {
+        self.clock += 1;
+        self.tombstones.insert(key_id, Tombstone { logical: self.clock, deleted: Instant::now() });
+    }
+
+    /// Forgets tombstones older than `grace_period` and returns how many there were. An entry
+    /// moved here afterwards brings its key back, so the period should outlast any key move.
+    pub fn expire_tombstones(&mut self, grace_period: Duration) -> usize {
+        let before = self.tombstones.len();
+        self.tombstones.retain(|_, tombstone| tombstone.deleted.elapsed() <= grace_period);
+        before - self.tombstones.len()
+    }
+
+    /// Buffers a chunk of a value. Once all chunks have arrived the reassembled
+    /// value is stored and its key is returned.
+    pub fn store_chunk(&mut self, chunk_index: usize, total_chunks: usize, data: (BigInt, DHTEntry)) -> Option<String> {
+        if chunk_index >= total_chunks {
+            return None;
+        }
+        let (key_id, chunk) = data;
+        let upload = self.uploads.entry(key_id.clone()).or_insert_with(|| ChunkedUpload {
+            key: chunk.key.clone(),
+            chunks: vec![None; total_chunks],
+            started: Instant::now(),
+        });
+        // A new upload for the same key replaces an older, differently sized one
+        if upload.chunks.len() != total_chunks {
+            upload.chunks = vec![None; total_chunks];
+            upload.started = Instant::now();
+        }
+        upload.chunks[chunk_index] = Some(chunk.value);
+
+        if upload.chunks.iter().all(|c| c.is_some()) {
+            let upload = self.uploads.remove(&key_id).unwrap();
+            let value: Vec<u8> = upload.chunks.into_iter().flat_map(|c| c.unwrap()).collect();
+            self.store_key((key_id, DHTEntry::from_bytes(upload.key.clone(), value)));
+            Some(upload.key)
+        } else {
+            None
+        }
+    }
+
+    /// Number of bytes received so far for the chunked upload of `key_id`
+    pub fn chunked_upload_size(&self, key_id: &BigInt) -> usize {
+        self.uploads.get(key_id)
+            .map(|upload| upload.chunks.iter().flatten().map(|chunk| chunk.len()).sum())
+            .unwrap_or(0)
+    }
+
+    pub fn abort_chunked_upload(&mut self, key_id: &BigInt) {
+        self.uploads.remove(key_id);
+    }
+
+    /// Drops uploads older than `timeout` and returns their keys
+    pub fn expire_chunked_uploads(&mut self, timeout: Duration) -> Vec<String> {
+        let expired: Vec<BigInt> = self.uploads.iter()
+            .filter(|(_, upload)| upload.started.elapsed() > timeout)
+            .map(|(key_id, _)| key_id.clone())
+            .collect();
+        expired.iter().map(|key_id| self.uploads.remove(key_id).unwrap().key).collect()
     }
 
     pub fn write_log_entry(&mut self, str: String) {
@@ -88,7 +551,195 @@ impl Storage {
     }
 }
 
-pub fn make_hashed_key_value_pair(key: String, value: String) -> (BigInt, DHTEntry) {
-    let id = chord::create_id(&key);
-    (id, DHTEntry::new(key, value))
+fn now_millis() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+/// The key `value` is stored under by `Node::store_content`, e.g. `content:2aae6c35...`. Equal
+/// values get the same key and thus share one entry. The namespace lets them be deleted together.
+pub fn content_key(value: &[u8]) -> String {
+    format!("{}{}{}", CONTENT_NAMESPACE, NAMESPACE_SEPARATOR, chord::create_content_hash(value))
+}
+
+/// The newer of two copies of a key, `a` if neither is newer
+pub fn newer_entry(a: Option<DHTEntry>, b: Option<DHTEntry>) -> Option<DHTEntry> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.is_newer_than(&a) { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// `value` is a `String` or, for binary values, a `Vec<u8>`
+pub fn make_hashed_key_value_pair(key: String, value: impl Into<Vec<u8>>, key_space_bits: usize) -> (BigInt, DHTEntry) {
+    let id = chord::create_key_id(&key, key_space_bits);
+    (id, DHTEntry::from_bytes(key, value.into()))
+}
+
+/// Splits `value` into chunks of at most `chunk_size` bytes. Text is not broken up within
+/// characters, so that every chunk of a string value is a string as well.
+pub fn split_value_into_chunks(value: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let text = match str::from_utf8(value) {
+        Ok(text) => text,
+        Err(_) => return value.chunks(cmp::max(chunk_size, 1)).map(|chunk| chunk.to_vec()).collect(),
+    };
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if !current.is_empty() && current.len() + c.len_utf8() > chunk_size {
+            chunks.push(current.into_bytes());
+            current = String::new();
+        }
+        current.push(c);
+    }
+    chunks.push(current.into_bytes());
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn entry(value: &str) -> DHTEntry {
+        DHTEntry::new("key".to_string(), value.to_string())
+    }
+
+    // A copy of an entry as another node moves it here, with the Lamport timestamp of its write
+    fn moved_entry(value: &str, logical: u64) -> DHTEntry {
+        DHTEntry { logical: Some(logical), ..entry(value) }
+    }
+
+    fn value_of(storage: &Storage, key_id: &BigInt) -> Option<Vec<u8>> {
+        storage.get_key(key_id).map(|entry| entry.value)
+    }
+
+    #[test]
+    fn tombstone_drops_moved_entries_written_before_the_delete() {
+        let mut storage = Storage::new(&ChordConfig::default());
+        let key_id = BigInt::from(7);
+        storage.store_key((key_id.clone(), entry("value")));
+        let written = storage.get_key(&key_id).unwrap().logical.unwrap();
+        assert!(storage.delete_key(&key_id).is_some());
+
+        storage.store_key((key_id.clone(), moved_entry("value", written)));
+        assert_eq!(value_of(&storage, &key_id), None);
+        // A write after the delete is moved here as well
+        storage.store_key((key_id.clone(), moved_entry("newer", storage.clock + 1)));
+        assert_eq!(value_of(&storage, &key_id), Some(b"newer".to_vec()));
+    }
+
+    #[test]
+    fn write_from_a_client_revives_a_deleted_key() {
+        let mut storage = Storage::new(&ChordConfig::default());
+        let key_id = BigInt::from(7);
+        storage.store_key((key_id.clone(), entry("value")));
+        storage.delete_key(&key_id);
+        storage.store_key((key_id.clone(), entry("again")));
+        assert_eq!(value_of(&storage, &key_id), Some(b"again".to_vec()));
+        assert!(storage.tombstones.is_empty());
+    }
+
+    #[test]
+    fn moved_entry_brings_a_key_back_once_its_tombstone_expired() {
+        let mut storage = Storage::new(&ChordConfig::default());
+        let key_id = BigInt::from(7);
+        storage.store_key((key_id.clone(), entry("value")));
+        let written = storage.get_key(&key_id).unwrap().logical.unwrap();
+        storage.delete_key(&key_id);
+        // Deleting an absent key leaves no tombstone
+        storage.delete_key(&BigInt::from(8));
+        assert_eq!(storage.expire_tombstones(Duration::from_secs(60)), 0);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.expire_tombstones(Duration::from_millis(1)), 1);
+
+        storage.store_key((key_id.clone(), moved_entry("value", written)));
+        assert_eq!(value_of(&storage, &key_id), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn a_future_dated_entry_is_detected_as_skewed_and_loses_to_a_later_logical_write() {
+        let now = now_millis();
+        let hour = 60 * 60 * 1000;
+        let skewed = DHTEntry { written_at: Some(now + hour), ..moved_entry("skewed", 1) };
+        assert_eq!(skewed.future_skew(now), Some(hour));
+        assert_eq!(entry("value").future_skew(now), None);
+        assert_eq!(DHTEntry { written_at: Some(now + chord::MAX_CLOCK_SKEW_MILLIS), ..entry("value") }.future_skew(now), None);
+
+        // The logical clocks decide, not the skewed wall clock
+        let mut storage = Storage::new(&ChordConfig::default());
+        let key_id = BigInt::from(7);
+        storage.store_key((key_id.clone(), skewed.clone()));
+        storage.store_key((key_id.clone(), entry("later")));
+        assert_eq!(value_of(&storage, &key_id), Some(b"later".to_vec()));
+        storage.store_key((key_id.clone(), skewed));
+        assert_eq!(value_of(&storage, &key_id), Some(b"later".to_vec()));
+    }
+
+    #[test]
+    fn binary_values_round_trip_and_string_values_are_sent_as_before() {
+        let binary: Vec<u8> = (0..=255).rev().collect();
+        let sent = serde_json::to_string(&DHTEntry::from_bytes("key".to_string(), binary.clone())).unwrap();
+        let received: DHTEntry = serde_json::from_str(&sent).unwrap();
+        assert_eq!(received.get_value(), &binary[..]);
+        let mut storage = Storage::new(&ChordConfig::default());
+        storage.store_key((BigInt::from(7), received));
+        assert_eq!(value_of(&storage, &BigInt::from(7)), Some(binary));
+
+        // Peers predating binary values send and expect strings
+        let sent = serde_json::to_value(&entry("value")).unwrap();
+        assert_eq!(sent["value"], "value");
+        let legacy: DHTEntry = serde_json::from_str("{\"key\":\"key\",\"value\":\"value\"}").unwrap();
+        assert_eq!(legacy.get_value(), b"value");
+        assert_eq!(legacy.value_string(), "value");
+    }
+
+    fn compressing(value_compression: ValueCompression) -> Storage {
+        Storage::new(&ChordConfig { value_compression, value_compression_threshold: 1024, ..ChordConfig::default() })
+    }
+
+    #[test]
+    fn values_from_the_threshold_on_are_held_compressed_and_read_back_intact() {
+        for &algorithm in &[ValueCompression::Deflate, ValueCompression::Gzip] {
+            let mut storage = compressing(algorithm);
+            let large = "a compressible value ".repeat(1000);
+            storage.store_key((BigInt::from(7), entry(&large)));
+            assert_eq!(storage.bytes_uncompressed(), "key".len() + large.len());
+            assert!(storage.bytes_stored() * 10 < storage.bytes_uncompressed(), "{:?} did not save memory", algorithm);
+            assert_eq!(value_of(&storage, &BigInt::from(7)), Some(large.clone().into_bytes()));
+            assert_eq!(storage.get_data_as_vec()[0].1.value, large.into_bytes());
+
+            // Below the threshold values are held as they are
+            let (stored, uncompressed) = (storage.bytes_stored(), storage.bytes_uncompressed());
+            let small = "a".repeat(1023);
+            storage.store_key((BigInt::from(8), entry(&small)));
+            assert!(storage.data[&BigInt::from(8)].compressed.is_none());
+            assert_eq!(storage.bytes_stored() - stored, "key".len() + small.len());
+            assert_eq!(storage.bytes_uncompressed() - uncompressed, "key".len() + small.len());
+            assert_eq!(value_of(&storage, &BigInt::from(8)), Some(small.into_bytes()));
+        }
+    }
+
+    #[test]
+    fn legacy_and_incompressible_values_are_read_back_intact() {
+        let mut storage = compressing(ValueCompression::Deflate);
+        // Sent by a node predating compression, the value gets compressed here
+        let legacy: DHTEntry = serde_json::from_str(&format!("{{\"key\":\"key\",\"value\":\"{}\"}}", "x".repeat(4096))).unwrap();
+        storage.store_key((BigInt::from(7), legacy));
+        assert!(storage.data[&BigInt::from(7)].compressed.is_some());
+        assert_eq!(value_of(&storage, &BigInt::from(7)), Some(vec![b'x'; 4096]));
+
+        // A value compression does not make smaller is held as it is
+        let mut state = 0x2545_f491u32;
+        let incompressible: Vec<u8> = (0..4096).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+        storage.store_key((BigInt::from(8), DHTEntry::from_bytes("key".to_string(), incompressible.clone())));
+        assert!(storage.data[&BigInt::from(8)].compressed.is_none());
+        assert_eq!(value_of(&storage, &BigInt::from(8)), Some(incompressible));
+    }
 }
@@ -0,0 +1,375 @@
+//! Runs rings of real node processes on ephemeral localhost ports and talks to them through
+//! the `client` subcommand, like `ring_test.sh` does.
+
+extern crate serde_json;
+
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// How long a node may take to listen, and the ring to converge after a change
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const NUMBER_OF_KEYS: usize = 10;
+
+/// The node binary, built by cargo next to the directory of the test binary
+fn node_binary() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join(format!("hll-rust{}", env::consts::EXE_SUFFIX))
+}
+
+fn command() -> Command {
+    let mut command = Command::new(node_binary());
+    // The nodes read their log configuration relative to the crate
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+/// Forwards the lines of `output` to `lines`, and keeps reading once nobody listens anymore
+/// so that the node never blocks on a full pipe
+fn forward_lines<R: Read + Send + 'static>(output: R, lines: mpsc::Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => {
+                    let _ = lines.send(line);
+                }
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// A node process, killed when dropped
+struct TestNode {
+    child: Child,
+    addr: String,
+    /// What the node printed after it logged its port, line by line
+    output: mpsc::Receiver<String>,
+}
+
+impl TestNode {
+    /// Starts a node on an ephemeral port, joining the ring of `entry` if given, and waits
+    /// until it logged the port it listens on
+    fn start(entry: Option<&TestNode>) -> TestNode {
+        TestNode::start_with(entry, &[])
+    }
+
+    /// Like `start`, passing `options` on to the node
+    fn start_with(entry: Option<&TestNode>, options: &[&str]) -> TestNode {
+        let mut command = command();
+        command.arg("--headless");
+        TestNode::spawn(command, entry, options)
+    }
+
+    /// Starts `command` as a node, its stdin is a pipe nobody writes to unless taken
+    fn spawn(mut command: Command, entry: Option<&TestNode>, options: &[&str]) -> TestNode {
+        command.arg("127.0.0.1").arg("0");
+        if let Some(entry) = entry {
+            command.arg(&entry.addr);
+        }
+        let mut child = command.args(options)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("unable to start a node");
+        let (sender, lines) = mpsc::channel();
+        forward_lines(child.stdout.take().unwrap(), sender.clone());
+        forward_lines(child.stderr.take().unwrap(), sender);
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let now = Instant::now();
+            let remaining = if now < deadline { deadline - now } else { Duration::from_secs(0) };
+            match lines.recv_timeout(remaining) {
+                Ok(line) => {
+                    if let Some(port) = line.split("Listening on ephemeral port ").nth(1) {
+                        return TestNode { child, addr: format!("127.0.0.1:{}", port.trim()), output: lines };
+                    }
+                }
+                Err(_) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    panic!("node did not start listening within {:?}", TIMEOUT);
+                }
+            }
+        }
+    }
+
+    /// The lines the node prints within `duration`
+    fn output_within(&self, duration: Duration) -> Vec<String> {
+        let deadline = Instant::now() + duration;
+        let mut lines = Vec::new();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return lines;
+            }
+            if let Ok(line) = self.output.recv_timeout(deadline - now) {
+                lines.push(line);
+            }
+        }
+    }
+
+    /// Lets the node hand its keys off and leave the ring, as on SIGINT, and waits for it to exit
+    fn leave(self) -> ExitStatus {
+        self.request_leave();
+        self.wait_for_exit()
+    }
+
+    fn request_leave(&self) {
+        let mut stream = TcpStream::connect(&self.addr).expect("unable to connect to the leaving node");
+        stream.write_all(b"\"Kill\"\n").unwrap();
+    }
+
+    fn wait_for_exit(mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "the leaving node did not exit within {:?}", TIMEOUT);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Runs a client operation through `node` and returns what it printed, `None` if it failed
+fn client(node: &TestNode, args: &[&str]) -> Option<String> {
+    let output = command()
+        .arg("client")
+        .arg(&node.addr)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .expect("unable to run the client");
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Sends `request`, a request without fields, to `node` as a client and returns the response
+fn client_request(node: &TestNode, request: &str) -> Option<Value> {
+    let mut stream = TcpStream::connect(&node.addr).ok()?;
+    stream.set_read_timeout(Some(TIMEOUT)).ok()?;
+    let message = format!("{{\"ClientRequest\":{{\"request\":\"{}\"}}}}\n", request);
+    stream.write_all(message.as_bytes()).ok()?;
+    stream.shutdown(Shutdown::Write).ok()?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).ok()?;
+    let reply: Value = serde_json::from_str(&reply).ok()?;
+    Some(reply["ResponseMessage"]["response"].clone())
+}
+
+fn predecessor_addr(node: &TestNode) -> Option<String> {
+    let response = client_request(node, "GetPredecessor")?;
+    response["GetPredecessorResponse"]["predecessor"]["ip_addr"].as_str().map(String::from)
+}
+
+fn successor_addr(node: &TestNode) -> Option<String> {
+    let response = client_request(node, "GetSuccessorList")?;
+    response["GetSuccessorListResponse"]["successor_list"][0]["ip_addr"].as_str().map(String::from)
+}
+
+/// Whether the successors of the nodes form one ring of all of them, each node being the
+/// predecessor of its successor
+fn ring_is_consistent(nodes: &[TestNode]) -> bool {
+    let mut successors = Vec::new();
+    for node in nodes {
+        let successor = match successor_addr(node) {
+            Some(successor) => successor,
+            None => return false,
+        };
+        match nodes.iter().find(|other| other.addr == successor) {
+            Some(other) if predecessor_addr(other).as_ref() == Some(&node.addr) => successors.push(successor),
+            _ => return false,
+        }
+    }
+    successors.sort();
+    successors.dedup();
+    successors.len() == nodes.len()
+}
+
+fn keys() -> Vec<(String, String)> {
+    (0..NUMBER_OF_KEYS).map(|i| (format!("key{}", i), format!("value{}", i))).collect()
+}
+
+/// Checks `converged` until it holds, fails the test after `TIMEOUT`
+fn wait_until<F: FnMut() -> bool>(what: &str, mut converged: F) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !converged() {
+        assert!(Instant::now() < deadline, "{} within {:?}", what, TIMEOUT);
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Whether every node names the same owner for every key
+fn owners_agree(nodes: &[TestNode]) -> bool {
+    keys().iter().all(|(key, _)| {
+        let mut owners = nodes.iter().map(|node| client(node, &["owner", key]));
+        let first = owners.next().unwrap();
+        first.is_some() && owners.all(|owner| owner == first)
+    })
+}
+
+/// Whether every key is found with its value through every node
+fn keys_found(nodes: &[TestNode]) -> bool {
+    nodes.iter().all(|node| {
+        keys().iter().all(|(key, value)| client(node, &["get", key]).as_ref() == Some(value))
+    })
+}
+
+fn store_keys(nodes: &[TestNode]) {
+    for (i, (key, value)) in keys().iter().enumerate() {
+        let node = &nodes[i % nodes.len()];
+        assert_eq!(client(node, &["store", key, value]), Some(format!("Stored key '{}'", key)));
+    }
+}
+
+#[test]
+fn ring_joins_stores_finds_and_keeps_the_keys_of_a_leaving_node() {
+    let first = TestNode::start(None);
+    let mut nodes = vec![first];
+    for _ in 1..4 {
+        let node = TestNode::start(Some(&nodes[0]));
+        nodes.push(node);
+    }
+    wait_until("the ring did not converge", || ring_is_consistent(&nodes) && owners_agree(&nodes));
+
+    store_keys(&nodes);
+    assert!(keys_found(&nodes), "not every key was found through every node");
+
+    let leaving = nodes.pop().unwrap();
+    assert!(leaving.leave().success(), "the leaving node exited with an error");
+    wait_until("the ring did not converge after a node left", || ring_is_consistent(&nodes) && owners_agree(&nodes));
+    // Lookups may still be sent to the node that left until the fingers pointing to it are fixed
+    wait_until("the keys of the leaving node were not found after it left", || keys_found(&nodes));
+}
+
+#[test]
+fn ring_converges_with_nodes_joining_at_the_same_time() {
+    let first = TestNode::start(None);
+    let second = TestNode::start(Some(&first));
+    let mut nodes = vec![first, second];
+    wait_until("the ring of two nodes did not converge", || ring_is_consistent(&nodes));
+    store_keys(&nodes);
+
+    // All of them join after the same initial sleep, while the ring keeps stabilizing, so a
+    // joiner may find a successor which just took another joiner as predecessor
+    let joiners: Vec<TestNode> = (0..3).map(|_| TestNode::start_with(Some(&nodes[0]), &["--verify-join"])).collect();
+    nodes.extend(joiners);
+    wait_until("the ring did not converge after the joins", || ring_is_consistent(&nodes) && owners_agree(&nodes));
+    assert!(keys_found(&nodes), "not every key was found through every node after the joins");
+}
+
+#[test]
+fn ring_keeps_the_keys_of_two_adjacent_nodes_leaving_at_once() {
+    let first = TestNode::start(None);
+    let mut nodes = vec![first];
+    for _ in 1..4 {
+        let node = TestNode::start(Some(&nodes[0]));
+        nodes.push(node);
+    }
+    wait_until("the ring did not converge", || ring_is_consistent(&nodes) && owners_agree(&nodes));
+    store_keys(&nodes);
+    assert!(keys_found(&nodes), "not every key was found through every node");
+
+    // A node and its successor, both other than the first node
+    let leaving_addrs = nodes[1..].iter()
+        .filter_map(|node| successor_addr(node).map(|successor| vec![node.addr.clone(), successor]))
+        .find(|addrs| addrs[1] != nodes[0].addr)
+        .expect("no two adjacent nodes besides the first one");
+    let (leaving, nodes): (Vec<TestNode>, Vec<TestNode>) = nodes.into_iter().partition(|node| leaving_addrs.contains(&node.addr));
+    for node in &leaving {
+        node.request_leave();
+    }
+    for node in leaving {
+        assert!(node.wait_for_exit().success(), "a leaving node exited with an error");
+    }
+    wait_until("the ring did not converge after the nodes left", || ring_is_consistent(&nodes) && owners_agree(&nodes));
+    wait_until("the keys of the leaving nodes were not found after they left", || keys_found(&nodes));
+}
+
+/// The id of a node alone in its ring, its own successor
+fn own_successor_id(node: &TestNode) -> Value {
+    let response = client_request(node, "GetSuccessorList").expect("the node did not answer");
+    let successor = &response["GetSuccessorListResponse"]["successor_list"][0];
+    assert_eq!(successor["ip_addr"].as_str(), Some(node.addr.as_str()));
+    successor["id"].clone()
+}
+
+#[test]
+fn nodes_on_ephemeral_ports_are_reachable_and_take_their_ids_from_the_bound_ports() {
+    let first = TestNode::start(None);
+    let second = TestNode::start(None);
+    assert_ne!(first.addr, second.addr);
+    // Hashed from port 0 the ids would be the same
+    assert_ne!(own_successor_id(&first), own_successor_id(&second));
+}
+
+#[test]
+fn client_stores_gets_deletes_and_names_the_owner_without_joining_the_ring() {
+    let first = TestNode::start(None);
+    let second = TestNode::start(Some(&first));
+    let nodes = vec![first, second];
+    wait_until("the ring of two nodes did not converge", || ring_is_consistent(&nodes));
+
+    assert_eq!(client(&nodes[0], &["store", "key", "value"]), Some("Stored key 'key'".to_string()));
+    assert_eq!(client(&nodes[1], &["get", "key"]), Some("value".to_string()));
+    let owner = client(&nodes[1], &["owner", "key"]).expect("the owner of the key was not found");
+    assert!(nodes.iter().any(|node| owner.ends_with(&format!("({})", node.addr))), "{} is no node of the ring", owner);
+
+    assert_eq!(client(&nodes[1], &["delete", "key"]), Some("Deleted key 'key'".to_string()));
+    assert_eq!(client(&nodes[0], &["get", "key"]), Some("Key 'key' not found".to_string()));
+    assert_eq!(client(&nodes[0], &["delete", "key"]), Some("Key 'key' not found".to_string()));
+    // The clients did not become members
+    assert!(ring_is_consistent(&nodes));
+}
+
+/// Printed with the node state, see `print::format_current_node_state`
+const NODE_STATE_LINE: &str = "Outstanding requests";
+
+/// Printed with the menu once `m` is entered
+const MENU_LINE: &str = "press Enter!";
+
+/// Starts the node with `options`, enters `m` for the menu and returns what it printed meanwhile
+fn output_with_menu_entered(options: &[&str]) -> Vec<String> {
+    let mut node = TestNode::spawn(command(), None, options);
+    node.child.stdin.as_mut().unwrap().write_all(b"m\n").unwrap();
+    // Long enough for the node state to be printed twice
+    node.output_within(Duration::from_secs(5))
+}
+
+#[test]
+fn headless_node_neither_prints_its_state_nor_reads_stdin() {
+    let printed = output_with_menu_entered(&[]);
+    assert!(printed.iter().any(|line| line.contains(NODE_STATE_LINE)), "the node state was not printed");
+    assert!(printed.iter().any(|line| line.contains(MENU_LINE)), "the menu was not shown");
+
+    let printed = output_with_menu_entered(&["--headless"]);
+    assert!(!printed.iter().any(|line| line.contains(NODE_STATE_LINE)), "a headless node printed its state");
+    assert!(!printed.iter().any(|line| line.contains(MENU_LINE)), "a headless node read the menu from stdin");
+}